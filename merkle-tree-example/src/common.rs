@@ -7,6 +7,7 @@ use ark_crypto_primitives::crh::{
     pedersen,
 };
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_simple_payments::poseidon;
 
 pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -50,3 +51,11 @@ pub type TwoToOneHashParamsVar =
     <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
 
 pub type ConstraintF = ark_ed_on_bls12_381::Fq;
+
+// Poseidon-based alternatives to the hashes above, which cost far fewer constraints.
+// The leaf hash takes inputs of up to 72 bytes, like `LeafHash`.
+pub type PoseidonLeafHash = poseidon::CRH<ConstraintF, 72>;
+pub type PoseidonTwoToOneHash = poseidon::TwoToOneCRH<ConstraintF>;
+
+pub type PoseidonLeafHashGadget = poseidon::constraints::CRHGadget<ConstraintF, 72>;
+pub type PoseidonTwoToOneHashGadget = poseidon::constraints::TwoToOneCRHGadget<ConstraintF>;
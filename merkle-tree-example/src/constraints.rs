@@ -192,4 +192,64 @@ fn merkle_tree_constraints_soundness() {
     let is_satisfied = cs.is_satisfied().unwrap();
     // We expect this to fail!
     assert!(!is_satisfied);
-}
\ No newline at end of file
+}
+
+// Run this test via `cargo test --release poseidon_merkle_tree_is_cheaper`.
+// This compares the cost of checking a membership proof with Pedersen and with Poseidon.
+#[test]
+fn poseidon_merkle_tree_is_cheaper() {
+    use crate::PoseidonMerkleConfig;
+    use ark_crypto_primitives::crh::CRHGadget;
+    use ark_crypto_primitives::merkle_tree::{Config, MerkleTree};
+    use ark_relations::r1cs::ConstraintSystem;
+
+    /// Check a membership proof in a tree configured by `P`, and return the number
+    /// of constraints that this costs.
+    fn membership_constraints<P, LHG, THG>() -> usize
+    where
+        P: Config,
+        LHG: CRHGadget<P::LeafHash, ConstraintF>,
+        THG: TwoToOneCRHGadget<P::TwoToOneHash, ConstraintF>,
+    {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = <P::LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_crh_params = <P::TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let tree = MerkleTree::<P>::new(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &[1u8, 2u8, 3u8, 10u8, 9u8, 17u8, 70u8, 45u8],
+        )
+        .unwrap();
+        let proof = tree.generate_proof(4).unwrap();
+
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let root = THG::OutputVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+        let leaf = UInt8::new_input(cs.clone(), || Ok(9u8)).unwrap();
+        let leaf_crh_params =
+            LHG::ParametersVar::new_constant(cs.clone(), &leaf_crh_params).unwrap();
+        let two_to_one_crh_params =
+            THG::ParametersVar::new_constant(cs.clone(), &two_to_one_crh_params).unwrap();
+        let path =
+            PathVar::<P, LHG, THG, ConstraintF>::new_witness(cs.clone(), || Ok(&proof)).unwrap();
+        let is_member = path
+            .verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &vec![leaf].as_slice(),
+            )
+            .unwrap();
+        is_member.enforce_equal(&Boolean::TRUE).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        cs.num_constraints()
+    }
+
+    let pedersen =
+        membership_constraints::<crate::MerkleConfig, LeafHashGadget, TwoToOneHashGadget>();
+    let poseidon = membership_constraints::<
+        PoseidonMerkleConfig,
+        PoseidonLeafHashGadget,
+        PoseidonTwoToOneHashGadget,
+    >();
+    assert!(poseidon * 2 < pedersen);
+}
@@ -0,0 +1,239 @@
+use crate::common::*;
+use crate::{MerkleConfig, Root, SimplePath};
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::Error;
+use ark_ff::{PrimeField, ToBytes, Zero};
+use ark_simple_payments::sparse_merkle_tree::SparseMerkleTree;
+use ark_std::io::{Result as IoResult, Write};
+use std::collections::BTreeMap;
+
+/// The upper bound of the values of an `IndexedMerkleTree`, which is `(p - 1) / 2`
+/// for the modulus `p` of `ConstraintF`. Values must lie strictly between zero and
+/// this bound, so that circuits can compare them cheaply.
+pub fn max_value() -> ConstraintF {
+    ConstraintF::from_repr(ConstraintF::modulus_minus_one_div_two()).unwrap()
+}
+
+/// A leaf of an `IndexedMerkleTree`. The leaves form a linked list sorted by value:
+/// `next_value` is the smallest value of the tree that is larger than `value`, or
+/// `max_value()` if there is none, and `next_index` is the position of its leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexedLeaf {
+    pub value: ConstraintF,
+    pub next_index: u64,
+    pub next_value: ConstraintF,
+}
+
+impl IndexedLeaf {
+    /// Is `value` strictly between `self.value` and `self.next_value`? If this leaf
+    /// is in the tree, that proves that `value` is not.
+    pub fn is_low_leaf_of(&self, value: ConstraintF) -> bool {
+        self.value < value && value < self.next_value
+    }
+}
+
+impl ToBytes for IndexedLeaf {
+    /// Leaves are encoded as (Value || NextIndex || NextValue), which is 72 bytes.
+    #[inline]
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        self.value.write(&mut writer)?;
+        self.next_index.write(&mut writer)?;
+        self.next_value.write(&mut writer)
+    }
+}
+
+/// A Merkle tree of distinct values, which can prove that a value is *not* in it,
+/// in the style of the "low nullifier" trees of Aztec. Its leaves, see `IndexedLeaf`,
+/// link each value to the next larger one. Leaf 0 holds the value 0, which links
+/// to the smallest value, so the list starts there.
+///
+/// A value is not in the tree exactly when some leaf, its low leaf, holds a smaller
+/// value that links to a larger one. Empty leaves link to 0, so they are never
+/// low leaves.
+pub struct IndexedMerkleTree {
+    tree: SparseMerkleTree<MerkleConfig>,
+    leaves: Vec<IndexedLeaf>,
+    /// The position of the leaf of each value, keyed by value.
+    positions: BTreeMap<ConstraintF, usize>,
+}
+
+/// A proof that a value is not in an `IndexedMerkleTree`: its low leaf, and the
+/// authentication path of that leaf.
+#[derive(Clone)]
+pub struct NonMembershipProof {
+    pub low_leaf: IndexedLeaf,
+    pub path: SimplePath,
+}
+
+impl NonMembershipProof {
+    /// Check that `value` is not in the tree whose root is `root`.
+    pub fn verify(
+        &self,
+        leaf_crh_params: &<LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<TwoToOneHash as TwoToOneCRH>::Parameters,
+        root: &Root,
+        value: ConstraintF,
+    ) -> Result<bool, Error> {
+        Ok(self.low_leaf.is_low_leaf_of(value)
+            && self.path.verify(
+                leaf_crh_params,
+                two_to_one_crh_params,
+                root,
+                &ark_ff::to_bytes![self.low_leaf]?,
+            )?)
+    }
+}
+
+impl IndexedMerkleTree {
+    /// Create a tree of height `height` that holds no values.
+    pub fn new(
+        leaf_crh_params: &<LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<TwoToOneHash as TwoToOneCRH>::Parameters,
+        height: usize,
+    ) -> Result<Self, Error> {
+        let mut tree = SparseMerkleTree::blank(leaf_crh_params, two_to_one_crh_params, height)?;
+        let first_leaf = IndexedLeaf {
+            value: ConstraintF::zero(),
+            next_index: 0,
+            next_value: max_value(),
+        };
+        tree.update(0, &first_leaf)?;
+        let mut positions = BTreeMap::new();
+        positions.insert(first_leaf.value, 0);
+        Ok(Self {
+            tree,
+            leaves: vec![first_leaf],
+            positions,
+        })
+    }
+
+    /// Returns the root of the tree.
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Is `value` in the tree?
+    pub fn contains(&self, value: ConstraintF) -> bool {
+        !value.is_zero() && self.positions.contains_key(&value)
+    }
+
+    /// Returns the position of the low leaf of `value`, if `value` is not in the tree
+    /// and `0 < value < max_value()`.
+    fn low_leaf_index(&self, value: ConstraintF) -> Option<usize> {
+        let (_, &index) = self.positions.range(..value).next_back()?;
+        if self.leaves[index].is_low_leaf_of(value) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Insert `value` into the tree. Fails if `value` is already in the tree, if it
+    /// is not strictly between 0 and `max_value()`, or if the tree is full.
+    pub fn insert(&mut self, value: ConstraintF) -> Result<(), Error> {
+        let low_index = self
+            .low_leaf_index(value)
+            .ok_or("the value is already in the tree, or is out of range")?;
+        let new_index = self.leaves.len();
+        let low_leaf = self.leaves[low_index];
+        let new_leaf = IndexedLeaf {
+            value,
+            next_index: low_leaf.next_index,
+            next_value: low_leaf.next_value,
+        };
+        // Writing the new leaf first fails without changes if the tree is full.
+        self.tree.update(new_index, &new_leaf)?;
+        let low_leaf = IndexedLeaf {
+            next_index: new_index as u64,
+            next_value: value,
+            ..low_leaf
+        };
+        self.tree.update(low_index, &low_leaf)?;
+        self.leaves[low_index] = low_leaf;
+        self.leaves.push(new_leaf);
+        self.positions.insert(value, new_index);
+        Ok(())
+    }
+
+    /// Prove that `value` is not in the tree. Returns `None` if it is, or if it is
+    /// out of range.
+    pub fn prove_non_membership(&self, value: ConstraintF) -> Option<NonMembershipProof> {
+        let index = self.low_leaf_index(value)?;
+        Some(NonMembershipProof {
+            low_leaf: self.leaves[index],
+            path: self.tree.generate_proof(index).ok()?,
+        })
+    }
+}
+
+#[test]
+fn indexed_merkle_tree_non_membership() {
+    let mut rng = ark_std::test_rng();
+    let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
+    let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+    let mut tree = IndexedMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, 4).unwrap();
+    let value = |v: u64| ConstraintF::from(v);
+
+    // Values can be inserted in any order, but only once.
+    for v in [30, 10, 20, 5] {
+        tree.insert(value(v)).unwrap();
+    }
+    assert!(tree.insert(value(20)).is_err());
+    assert!(tree.contains(value(20)));
+    assert!(!tree.contains(value(15)));
+    // Values must lie strictly between 0 and `max_value()`.
+    assert!(tree.insert(value(0)).is_err());
+    assert!(tree.insert(max_value()).is_err());
+    // Leaf 0 and the four values fill 5 of the 8 leaves.
+    for v in [40, 50, 60] {
+        tree.insert(value(v)).unwrap();
+    }
+    assert!(tree.insert(value(70)).is_err());
+
+    let root = tree.root();
+    for v in [1, 7, 15, 25, 35, 1000] {
+        let proof = tree.prove_non_membership(value(v)).unwrap();
+        assert!(proof
+            .verify(&leaf_crh_params, &two_to_one_crh_params, &root, value(v))
+            .unwrap());
+        // The proof only covers the values between the low leaf and the next one.
+        assert!(!proof
+            .verify(&leaf_crh_params, &two_to_one_crh_params, &root, value(20))
+            .unwrap());
+    }
+    for v in [5, 10, 20, 30] {
+        assert!(tree.prove_non_membership(value(v)).is_none());
+    }
+
+    // Proofs are bound to the root they were made for.
+    let proof = tree.prove_non_membership(value(15)).unwrap();
+    let mut other_tree =
+        IndexedMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, 4).unwrap();
+    other_tree.insert(value(15)).unwrap();
+    assert!(!proof
+        .verify(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &other_tree.root(),
+            value(15)
+        )
+        .unwrap());
+
+    // Empty leaves link to 0, so they are not low leaves of anything.
+    let empty_leaf_proof = NonMembershipProof {
+        low_leaf: IndexedLeaf {
+            value: value(0),
+            next_index: 0,
+            next_value: value(0),
+        },
+        path: other_tree.tree.generate_proof(7).unwrap(),
+    };
+    assert!(!empty_leaf_proof
+        .verify(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &other_tree.root(),
+            value(15)
+        )
+        .unwrap());
+}
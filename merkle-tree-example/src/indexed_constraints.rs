@@ -0,0 +1,167 @@
+use crate::common::*;
+use crate::constraints::{RootVar, SimplePathVar};
+use crate::indexed::{IndexedLeaf, NonMembershipProof};
+use crate::Root;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::ns;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+/// The R1CS equivalent of an `IndexedLeaf`.
+pub struct IndexedLeafVar {
+    pub value: FpVar<ConstraintF>,
+    pub next_index: UInt64<ConstraintF>,
+    pub next_value: FpVar<ConstraintF>,
+}
+
+impl IndexedLeafVar {
+    /// Encode the leaf like `IndexedLeaf::write` does.
+    pub fn to_bytes(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let mut bytes = self.value.to_bytes()?;
+        bytes.extend(self.next_index.to_bytes()?);
+        bytes.extend(self.next_value.to_bytes()?);
+        Ok(bytes)
+    }
+
+    /// Enforce that `value` is strictly between `self.value` and `self.next_value`,
+    /// like `IndexedLeaf::is_low_leaf_of`. This also enforces that all three are at
+    /// most `max_value()`.
+    pub fn enforce_low_leaf_of(&self, value: &FpVar<ConstraintF>) -> Result<(), SynthesisError> {
+        self.value.enforce_cmp(value, Ordering::Less, false)?;
+        value.enforce_cmp(&self.next_value, Ordering::Less, false)
+    }
+}
+
+impl AllocVar<IndexedLeaf, ConstraintF> for IndexedLeafVar {
+    fn new_variable<T: Borrow<IndexedLeaf>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        // The assignment is missing during setup, which only needs the shape of the leaf.
+        let leaf = f().map(|leaf| *leaf.borrow()).ok();
+        let missing = SynthesisError::AssignmentMissing;
+        let value = FpVar::new_variable(cs.clone(), || leaf.map(|l| l.value).ok_or(missing), mode)?;
+        let next_index = UInt64::new_variable(
+            cs.clone(),
+            || leaf.map(|l| l.next_index).ok_or(missing),
+            mode,
+        )?;
+        let next_value =
+            FpVar::new_variable(cs, || leaf.map(|l| l.next_value).ok_or(missing), mode)?;
+        Ok(Self {
+            value,
+            next_index,
+            next_value,
+        })
+    }
+}
+
+/// Prove that `value` is not in the `IndexedMerkleTree` whose root is `root`, by
+/// showing that its low leaf is in the tree.
+///
+/// This relies on the leaves of the tree being sorted, which `IndexedMerkleTree`
+/// maintains. A tree built some other way must also be checked to be sorted.
+pub struct IndexedNonMembership {
+    // constants embedded in-circuit
+    pub leaf_crh_params: <LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
+
+    // public inputs
+    pub root: Root,
+    pub value: ConstraintF,
+
+    // witness
+    pub proof: Option<NonMembershipProof>,
+}
+
+impl ConstraintSynthesizer<ConstraintF> for IndexedNonMembership {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let root = RootVar::new_input(ns!(cs, "root"), || Ok(&self.root))?;
+        let value = FpVar::new_input(ns!(cs, "value"), || Ok(self.value))?;
+
+        let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &self.leaf_crh_params)?;
+        let node_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.two_to_one_crh_params)?;
+
+        let proof = self.proof.as_ref();
+        let low_leaf = IndexedLeafVar::new_witness(ns!(cs, "low_leaf"), || {
+            proof
+                .map(|proof| proof.low_leaf)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ns!(cs, "low_leaf_path"), || {
+            proof
+                .map(|proof| &proof.path)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // The low leaf is in the tree...
+        let low_leaf_bytes = low_leaf.to_bytes()?;
+        path.verify_membership(
+            &leaf_params,
+            &node_params,
+            &root,
+            &low_leaf_bytes.as_slice(),
+        )?
+        .enforce_equal(&Boolean::TRUE)?;
+        // ...and skips over `value`.
+        low_leaf.enforce_low_leaf_of(&value)
+    }
+}
+
+// Run this test via `cargo test --release indexed_non_membership`.
+#[test]
+fn indexed_non_membership_constraints() {
+    use crate::indexed::IndexedMerkleTree;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let mut rng = ark_std::test_rng();
+    let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
+    let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+    let mut tree = IndexedMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, 8).unwrap();
+    for value in [30u64, 10, 20] {
+        tree.insert(ConstraintF::from(value)).unwrap();
+    }
+
+    let is_satisfied = |value: u64, proof: Option<NonMembershipProof>| {
+        let circuit = IndexedNonMembership {
+            leaf_crh_params: leaf_crh_params.clone(),
+            two_to_one_crh_params: two_to_one_crh_params.clone(),
+            root: tree.root(),
+            value: ConstraintF::from(value),
+            proof,
+        };
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    };
+
+    // 15 is not in the tree: its low leaf is that of 10, which links to 20.
+    let proof = tree.prove_non_membership(ConstraintF::from(15u64));
+    assert_eq!(
+        proof.as_ref().unwrap().low_leaf.value,
+        ConstraintF::from(10u64)
+    );
+    assert!(is_satisfied(15, proof.clone()));
+    // Neither is 40, which is larger than all the values.
+    assert!(is_satisfied(
+        40,
+        tree.prove_non_membership(ConstraintF::from(40u64))
+    ));
+
+    // The low leaf of 15 proves nothing about 20, which is in the tree.
+    assert!(!is_satisfied(20, proof.clone()));
+    // Nor can a made-up leaf be used, as it is not in the tree.
+    let mut fake_proof = proof.unwrap();
+    fake_proof.low_leaf.next_value = ConstraintF::from(25u64);
+    assert!(!is_satisfied(20, Some(fake_proof)));
+}
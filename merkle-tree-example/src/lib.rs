@@ -5,6 +5,8 @@ pub mod common;
 use common::*;
 
 pub mod constraints;
+pub mod indexed;
+pub mod indexed_constraints;
 pub mod non_membership_constraints;
 // mod constraints_test;
 
@@ -17,6 +19,16 @@ impl Config for MerkleConfig {
     type TwoToOneHash = TwoToOneHash;
 }
 
+/// A Merkle tree configuration that hashes with Poseidon instead of Pedersen.
+/// Replace `MerkleConfig` with it to get a tree that is much cheaper to verify
+/// inside a circuit.
+#[derive(Clone)]
+pub struct PoseidonMerkleConfig;
+impl Config for PoseidonMerkleConfig {
+    type LeafHash = PoseidonLeafHash;
+    type TwoToOneHash = PoseidonTwoToOneHash;
+}
+
 /// A Merkle tree containing account information.
 pub type SimpleMerkleTree = MerkleTree<MerkleConfig>;
 /// The root of the account Merkle tree.
@@ -67,8 +79,8 @@ fn test_merkle_tree() {
 mod set_diff_tests {
     use super::*;
     use crate::non_membership_constraints::NonMembershipSetDiff;
-    use ark_crypto_primitives::crh::{CRH, TwoToOneCRH};
-    use ark_relations::r1cs::{ConstraintSystem, ConstraintSynthesizer};
+    use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
 
     #[test]
     fn non_membership_set_diff_satisfied() {
@@ -78,13 +90,15 @@ mod set_diff_tests {
         let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
 
         // Old tree: index 4 holds 9
-        let old_leaves = [1u8,2u8,3u8,10u8,9u8,17u8,70u8,45u8];
-        let old_tree = SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &old_leaves).unwrap();
+        let old_leaves = [1u8, 2u8, 3u8, 10u8, 9u8, 17u8, 70u8, 45u8];
+        let old_tree =
+            SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &old_leaves).unwrap();
 
         // New tree: index 4 is changed to 11
         let mut new_leaves = old_leaves;
         new_leaves[4] = 11u8;
-        let new_tree = SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &new_leaves).unwrap();
+        let new_tree =
+            SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &new_leaves).unwrap();
 
         let idx = 4;
         let path_old = old_tree.generate_proof(idx).unwrap();
@@ -114,11 +128,13 @@ mod set_diff_tests {
         let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
 
         // Old tree
-        let leaves = [1u8,2u8,3u8,10u8,9u8,17u8,70u8,45u8];
-        let old_tree = SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &leaves).unwrap();
+        let leaves = [1u8, 2u8, 3u8, 10u8, 9u8, 17u8, 70u8, 45u8];
+        let old_tree =
+            SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &leaves).unwrap();
 
         // New tree = old tree (no change)
-        let new_tree = SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &leaves).unwrap();
+        let new_tree =
+            SimpleMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, &leaves).unwrap();
 
         let idx = 4; // same value in both: 9
         let path_old = old_tree.generate_proof(idx).unwrap();
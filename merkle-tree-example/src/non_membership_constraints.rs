@@ -1,15 +1,14 @@
-use crate::{Root, SimplePath};
 use crate::common::*; // ConstraintF, LeafHashGadget, TwoToOneHashGadget, param vars, etc.
-use ark_crypto_primitives::crh::{CRH, TwoToOneCRH};
+use crate::{Root, SimplePath};
 use ark_crypto_primitives::crh::constraints::TwoToOneCRHGadget;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
 use ark_crypto_primitives::merkle_tree::constraints::PathVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
 use ark_relations::ns;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
 
 // R1CS equivalents
-pub type RootVar =
-    <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
+pub type RootVar = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
 pub type SimplePathVar =
     PathVar<crate::MerkleConfig, LeafHashGadget, TwoToOneHashGadget, ConstraintF>;
 
@@ -27,8 +26,8 @@ pub struct NonMembershipSetDiff {
     pub leaf_new: u8,
 
     // witnesses
-    pub path_new: Option<SimplePath>,            // path in new_root for index i
-    pub leaf_old_at_index: u8,                   // value at same index in old_root
+    pub path_new: Option<SimplePath>, // path in new_root for index i
+    pub leaf_old_at_index: u8,        // value at same index in old_root
     pub path_old_same_index: Option<SimplePath>, // path in old_root for index i
 }
 
@@ -47,8 +46,7 @@ impl ConstraintSynthesizer<ConstraintF> for NonMembershipSetDiff {
             UInt8::new_witness(ns!(cs, "leaf_old_at_index"), || Ok(&self.leaf_old_at_index))?;
 
         // Hash parameters as constants
-        let leaf_params =
-            LeafHashParamsVar::new_constant(cs.clone(), &self.leaf_crh_params)?;
+        let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &self.leaf_crh_params)?;
         let node_params =
             TwoToOneHashParamsVar::new_constant(cs.clone(), &self.two_to_one_crh_params)?;
 
@@ -1,25 +1,59 @@
 use crate::ledger::*;
 use crate::ConstraintF;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
-use ark_r1cs_std::bits::{uint8::UInt8, ToBytesGadget};
+#[cfg(not(feature = "account-id-u64"))]
+use ark_r1cs_std::bits::uint32::UInt32;
+use ark_r1cs_std::bits::{uint64::UInt64, uint8::UInt8, ToBytesGadget};
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_simple_payments::account::*;
-use ark_simple_payments::signature::schnorr::constraints::*;
+use ark_simple_payments::ledger::DefaultSignatureScheme;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use derivative::Derivative;
 use std::borrow::Borrow;
 
 /// Account public key used to verify transaction signatures.
-pub type AccountPublicKeyVar = PublicKeyVar<EdwardsProjective, EdwardsVar>;
+pub type AccountPublicKeyVar<S = DefaultSignatureScheme, SG = DefaultSigVerifyGadget> =
+    <SG as SigVerifyGadget<S, ConstraintF>>::PublicKeyVar;
 
-/// Account identifier. This prototype supports only 256 accounts at a time.
+/// The gadget for `AccountIdRepr`, whose width is set by the `account-id-u64` feature.
+#[cfg(not(feature = "account-id-u64"))]
+pub type AccountIdReprVar = UInt32<ConstraintF>;
+/// The gadget for `AccountIdRepr`, whose width is set by the `account-id-u64` feature.
+#[cfg(feature = "account-id-u64")]
+pub type AccountIdReprVar = UInt64<ConstraintF>;
+
+/// Account identifier.
 #[derive(Clone, Debug)]
-pub struct AccountIdVar(pub UInt8<ConstraintF>);
+pub struct AccountIdVar(pub AccountIdReprVar);
 
 impl AccountIdVar {
     /// Convert the account identifier to bytes.
     #[tracing::instrument(target = "r1cs", skip(self))]
     pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
-        vec![self.0.clone()]
+        self.0.to_bytes().unwrap()
+    }
+
+    /// Convert the account identifier to bits, which are also the bits of the
+    /// position of the account in the account tree.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bits_le(&self) -> Vec<Boolean<ConstraintF>> {
+        self.0.to_bits_le()
+    }
+
+    /// Return the identifier that follows `self`, enforcing that it does not overflow.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn checked_increment(&self) -> Result<Self, SynthesisError> {
+        self.0
+            .enforce_not_equal(&AccountIdReprVar::constant(AccountIdRepr::MAX))?;
+        let result = AccountIdReprVar::addmany(&[self.0.clone(), AccountIdReprVar::constant(1)])?;
+        Ok(AccountIdVar(result))
+    }
+}
+
+impl EqGadget<ConstraintF> for AccountIdVar {
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    fn is_eq(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.0.is_eq(&other.0)
     }
 }
 
@@ -30,20 +64,66 @@ impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
-        UInt8::new_variable(cs, || f().map(|u| u.borrow().0), mode).map(Self)
+        AccountIdReprVar::new_variable(cs, || f().map(|u| u.borrow().0), mode).map(Self)
+    }
+}
+
+/// Per-account transaction counter.
+#[derive(Clone, Debug)]
+pub struct NonceVar(pub UInt64<ConstraintF>);
+
+impl NonceVar {
+    /// Convert the nonce to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        self.0.to_bytes().unwrap()
+    }
+
+    /// Return the nonce that follows `self`, enforcing that it does not overflow.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn checked_increment(&self) -> Result<Self, SynthesisError> {
+        // The native ledger refuses to increment past `u64::MAX`, so we do the same
+        // instead of letting the `UInt64` addition wrap around to zero.
+        self.0.enforce_not_equal(&UInt64::constant(u64::MAX))?;
+        let result = UInt64::addmany(&[self.0.clone(), UInt64::constant(1)])?;
+        Ok(NonceVar(result))
+    }
+}
+
+impl EqGadget<ConstraintF> for NonceVar {
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    fn is_eq(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.0.is_eq(&other.0)
+    }
+}
+
+impl AllocVar<Nonce, ConstraintF> for NonceVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Nonce>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        UInt64::new_variable(cs, || f().map(|u| u.borrow().0), mode).map(Self)
     }
 }
 
 /// Information about the account, such as the balance and the associated public key.
-#[derive(Clone)]
-pub struct AccountInformationVar {
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct AccountInformationVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+> {
     /// The account public key.
-    pub public_key: AccountPublicKeyVar,
+    pub public_key: AccountPublicKeyVar<S, SG>,
     /// The balance associated with this this account.
     pub balance: AmountVar,
+    /// The nonce that the next transaction sent from this account must carry.
+    pub nonce: NonceVar,
 }
 
-impl AccountInformationVar {
+impl<S: SignatureScheme, SG: SigVerifyGadget<S, ConstraintF>> AccountInformationVar<S, SG> {
     /// Convert the account information to bytes.
     #[tracing::instrument(target = "r1cs", skip(self))]
     pub fn to_bytes_le(&self) -> Vec<UInt8<crate::ConstraintF>> {
@@ -52,13 +132,18 @@ impl AccountInformationVar {
             .unwrap()
             .into_iter()
             .chain(self.balance.to_bytes_le())
+            .chain(self.nonce.to_bytes_le())
             .collect()
     }
 }
 
-impl AllocVar<AccountInformation, ConstraintF> for AccountInformationVar {
+impl<S, SG> AllocVar<AccountInformation<S>, ConstraintF> for AccountInformationVar<S, SG>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+{
     #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
-    fn new_variable<T: Borrow<AccountInformation>>(
+    fn new_variable<T: Borrow<AccountInformation<S>>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
@@ -67,11 +152,13 @@ impl AllocVar<AccountInformation, ConstraintF> for AccountInformationVar {
             let info = info.borrow();
             let cs = cs.into();
             let public_key =
-                AccountPublicKeyVar::new_variable(cs.clone(), || Ok(&info.public_key), mode)?;
-            let balance = AmountVar::new_variable(cs, || Ok(&info.balance), mode)?;
+                SG::PublicKeyVar::new_variable(cs.clone(), || Ok(&info.public_key), mode)?;
+            let balance = AmountVar::new_variable(cs.clone(), || Ok(&info.balance), mode)?;
+            let nonce = NonceVar::new_variable(cs, || Ok(&info.nonce), mode)?;
             Ok(Self {
                 public_key,
                 balance,
+                nonce,
             })
         })
     }
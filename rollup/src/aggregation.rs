@@ -0,0 +1,623 @@
+use crate::backend::{Groth16Backend, SnarkBackend};
+use crate::ledger::{AccMerkleConfigGadget, AccRootVar, LeafHashParamsVar};
+use crate::operation::OperationsCommitmentVar;
+use crate::prover::{self, Proof};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::constraints::CRHGadget;
+use ark_crypto_primitives::crh::CRH;
+use ark_crypto_primitives::merkle_tree;
+use ark_crypto_primitives::snark::constraints::{BooleanInputVar, SNARKGadget};
+use ark_ec::{AffineCurve, PairingEngine};
+use ark_ff::{PrimeField, ToConstraintField, Zero};
+use ark_groth16::constraints::{Groth16VerifierGadget, ProofVar, VerifyingKeyVar};
+use ark_groth16::Groth16;
+use ark_mnt4_298::{constraints::PairingVar as MNT4PairingVar, MNT4_298};
+use ark_mnt6_298::{constraints::PairingVar as MNT6PairingVar, MNT6_298};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_r1cs_std::ToConstraintFieldGadget;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_simple_payments::{
+    ledger::{AccRoot, DefaultSignatureScheme, MerkleConfig, Parameters},
+    operation::{self, Operation, OperationsCommitment},
+    poseidon::{constraints::ParametersVar as PoseidonParametersVar, PoseidonParameters},
+    signature::{SigVerifyGadget, SignatureScheme},
+};
+use ark_std::rand::{CryptoRng, RngCore};
+use derivative::Derivative;
+
+/// The field of `StepCircuit`, which is the scalar field of MNT6-298 and the base
+/// field of MNT4-298, so that it can verify the proofs over MNT4-298 that
+/// `Groth16Backend` produces.
+pub type StepConstraintF = <MNT6_298 as PairingEngine>::Fr;
+
+/// The zkSNARK that proves `StepCircuit`.
+type StepSnark = Groth16<MNT6_298>;
+/// Verifies batch proofs and `WrapCircuit` proofs in `StepCircuit`.
+type MNT4VerifierGadget = Groth16VerifierGadget<MNT4_298, MNT4PairingVar>;
+/// Verifies `StepCircuit` proofs in `WrapCircuit`.
+type MNT6VerifierGadget = Groth16VerifierGadget<MNT6_298, MNT6PairingVar>;
+
+/// The number of public inputs of `StepCircuit`.
+const NUM_STEP_INPUTS: usize = 5;
+
+/// A proof that a chain of batches moves the ledger from `initial_root` to
+/// `final_root`, and that `commitment` is the commitment of `commit_to_chain`
+/// to the deposits and withdrawals of the batches. Its size, and the cost of
+/// verifying it, do not depend on the number of batches.
+#[derive(Clone)]
+pub struct ChainProof {
+    pub initial_root: AccRoot,
+    pub final_root: AccRoot,
+    pub commitment: OperationsCommitment,
+    pub proof: Proof<Groth16Backend>,
+}
+
+/// The key used to extend chains of batches that are proven with a given batch
+/// verifying key, for a ledger whose accounts use the signature scheme `S`, and
+/// whose account tree is configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct ProvingKey<
+    S: SignatureScheme = DefaultSignatureScheme,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The ledger parameters, which are needed to commit to the operations in a batch.
+    pub ledger_params: Parameters<S, M>,
+    batch_vk: ark_groth16::VerifyingKey<MNT4_298>,
+    batch_pvk: ark_groth16::PreparedVerifyingKey<MNT4_298>,
+    step_pk: ark_groth16::ProvingKey<MNT6_298>,
+    step_vk: ark_groth16::VerifyingKey<MNT6_298>,
+    step_vk_hash: ConstraintF,
+    wrap_pk: ark_groth16::ProvingKey<MNT4_298>,
+    wrap_vk: ark_groth16::VerifyingKey<MNT4_298>,
+}
+
+/// The key used to verify `ChainProof`s.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct VerifyingKey<
+    S: SignatureScheme = DefaultSignatureScheme,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The ledger parameters, which are needed to commit to the operations in a chain.
+    pub ledger_params: Parameters<S, M>,
+    wrap_pvk: ark_groth16::PreparedVerifyingKey<MNT4_298>,
+    step_vk_hash: ConstraintF,
+}
+
+/// Extends a chain of batches by one batch. Its public input is the initial
+/// root of the chain, the root after the batch, the commitment to the chain
+/// before the batch, the operations commitment of the batch, and the hash of
+/// the verifying key of `StepCircuit` itself, which `WrapCircuit` checks.
+/// The batch proof must show that the batch moves the ledger from some
+/// previous root to the final root. Unless the batch is the first of the chain,
+/// the `WrapCircuit` proof must show that the chain so far moves the ledger
+/// from the initial root to the previous root.
+struct StepCircuit {
+    batch_vk: ark_groth16::VerifyingKey<MNT4_298>,
+    wrap_vk: ark_groth16::VerifyingKey<MNT4_298>,
+    initial_root: AccRoot,
+    final_root: AccRoot,
+    previous_commitment: OperationsCommitment,
+    operations_commitment: OperationsCommitment,
+    step_vk_hash: ConstraintF,
+    previous_root: AccRoot,
+    batch_proof: Proof<Groth16Backend>,
+    is_first: bool,
+    previous_proof: Proof<Groth16Backend>,
+}
+
+impl ConstraintSynthesizer<StepConstraintF> for StepCircuit {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<StepConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Verifying keys constant
+        let batch_vk = VerifyingKeyVar::<MNT4_298, MNT4PairingVar>::new_constant(
+            ark_relations::ns!(cs, "Batch verifying key"),
+            &self.batch_vk,
+        )?;
+        let wrap_vk = VerifyingKeyVar::<MNT4_298, MNT4PairingVar>::new_constant(
+            ark_relations::ns!(cs, "Wrap verifying key"),
+            &self.wrap_vk,
+        )?;
+
+        // Public inputs, which are elements of `ConstraintF` embedded in the larger
+        // `StepConstraintF`
+        let initial_root = FpVar::new_input(ark_relations::ns!(cs, "Initial root"), || {
+            Ok(to_step_field(self.initial_root))
+        })?;
+        let final_root = FpVar::new_input(ark_relations::ns!(cs, "Final root"), || {
+            Ok(to_step_field(self.final_root))
+        })?;
+        let previous_commitment =
+            FpVar::new_input(ark_relations::ns!(cs, "Previous commitment"), || {
+                Ok(to_step_field(self.previous_commitment))
+            })?;
+        let operations_commitment =
+            FpVar::new_input(ark_relations::ns!(cs, "Operations commitment"), || {
+                Ok(to_step_field(self.operations_commitment))
+            })?;
+        let step_vk_hash =
+            FpVar::new_input(ark_relations::ns!(cs, "Step verifying key hash"), || {
+                Ok(to_step_field(self.step_vk_hash))
+            })?;
+
+        // Witnesses
+        let previous_root = FpVar::new_witness(ark_relations::ns!(cs, "Previous root"), || {
+            Ok(to_step_field(self.previous_root))
+        })?;
+        let is_first =
+            Boolean::new_witness(ark_relations::ns!(cs, "Is first"), || Ok(self.is_first))?;
+        let batch_proof = ProofVar::<MNT4_298, MNT4PairingVar>::new_witness(
+            ark_relations::ns!(cs, "Batch proof"),
+            || Ok(&self.batch_proof),
+        )?;
+        let previous_proof = ProofVar::<MNT4_298, MNT4PairingVar>::new_witness(
+            ark_relations::ns!(cs, "Previous proof"),
+            || Ok(&self.previous_proof),
+        )?;
+
+        let previous_root_bits = previous_root.to_bits_le()?;
+
+        // 1) The batch moves the ledger from the previous root to the final root.
+        let batch_input = BooleanInputVar::new(vec![
+            previous_root_bits.clone(),
+            final_root.to_bits_le()?,
+            operations_commitment.to_bits_le()?,
+        ]);
+        MNT4VerifierGadget::verify(&batch_vk, &batch_input, &batch_proof)?
+            .enforce_equal(&Boolean::TRUE)?;
+
+        // 2) The chain so far moves the ledger from the initial root to the previous
+        // root, with the same step verifying key. The first batch starts an empty
+        // chain instead, which has no proof.
+        let previous_input = BooleanInputVar::new(vec![
+            initial_root.to_bits_le()?,
+            previous_root_bits,
+            previous_commitment.to_bits_le()?,
+            step_vk_hash.to_bits_le()?,
+        ]);
+        MNT4VerifierGadget::verify(&wrap_vk, &previous_input, &previous_proof)?
+            .or(&is_first)?
+            .enforce_equal(&Boolean::TRUE)?;
+        previous_root.conditional_enforce_equal(&initial_root, &is_first)?;
+        previous_commitment.conditional_enforce_equal(&FpVar::zero(), &is_first)?;
+
+        Ok(())
+    }
+}
+
+/// Wraps a `StepCircuit` proof into a proof over MNT4-298, which `StepCircuit`
+/// can verify in turn. Its public input is the initial and final roots of the
+/// chain, the commitment to the chain, and the hash of the step verifying key.
+/// The `StepCircuit` proof must be valid under a verifying key with that hash,
+/// and the commitment to the chain must extend the commitment before the last
+/// batch with the operations commitment of the last batch.
+struct WrapCircuit<M: AccMerkleConfigGadget> {
+    leaf_crh_params: <<M as merkle_tree::Config>::LeafHash as CRH>::Parameters,
+    initial_root: AccRoot,
+    final_root: AccRoot,
+    commitment: OperationsCommitment,
+    step_vk_hash: ConstraintF,
+    step_vk: ark_groth16::VerifyingKey<MNT6_298>,
+    previous_commitment: OperationsCommitment,
+    operations_commitment: OperationsCommitment,
+    step_proof: ark_groth16::Proof<MNT6_298>,
+}
+
+impl<M: AccMerkleConfigGadget> ConstraintSynthesizer<ConstraintF> for WrapCircuit<M> {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Parameters constant
+        let leaf_crh_params = LeafHashParamsVar::<M>::new_constant(
+            ark_relations::ns!(cs, "Leaf hash parameters"),
+            &self.leaf_crh_params,
+        )?;
+
+        // Public inputs
+        let initial_root = AccRootVar::new_input(ark_relations::ns!(cs, "Initial root"), || {
+            Ok(self.initial_root)
+        })?;
+        let final_root =
+            AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || Ok(self.final_root))?;
+        let commitment =
+            OperationsCommitmentVar::new_input(ark_relations::ns!(cs, "Chain commitment"), || {
+                Ok(self.commitment)
+            })?;
+        let step_vk_hash =
+            FpVar::new_input(ark_relations::ns!(cs, "Step verifying key hash"), || {
+                Ok(self.step_vk_hash)
+            })?;
+
+        // Witnesses. The step verifying key cannot be a constant, as it embeds the
+        // verifying key of this circuit, so it is bound by its hash instead.
+        let step_vk = MNT6VerifierGadget::new_verification_key_unchecked(
+            ark_relations::ns!(cs, "Step verifying key"),
+            || Ok(&self.step_vk),
+            AllocationMode::Witness,
+        )?;
+        let previous_commitment = OperationsCommitmentVar::new_witness(
+            ark_relations::ns!(cs, "Previous commitment"),
+            || Ok(self.previous_commitment),
+        )?;
+        let operations_commitment = OperationsCommitmentVar::new_witness(
+            ark_relations::ns!(cs, "Operations commitment"),
+            || Ok(self.operations_commitment),
+        )?;
+        let step_proof = ProofVar::<MNT6_298, MNT6PairingVar>::new_witness(
+            ark_relations::ns!(cs, "Step proof"),
+            || Ok(&self.step_proof),
+        )?;
+
+        // 1) The step verifying key is the one that the public input commits to.
+        hash_step_vk_var(&step_vk)?.enforce_equal(&step_vk_hash)?;
+
+        // 2) The step proof extends the chain up to the final root.
+        let step_input = BooleanInputVar::new(vec![
+            initial_root.to_bits_le()?,
+            final_root.to_bits_le()?,
+            previous_commitment.to_bits_le()?,
+            operations_commitment.to_bits_le()?,
+            step_vk_hash.to_bits_le()?,
+        ]);
+        MNT6VerifierGadget::verify(&step_vk, &step_input, &step_proof)?
+            .enforce_equal(&Boolean::TRUE)?;
+
+        // 3) The chain commitment appends the operations commitment of the batch,
+        // like `extend_commitment`.
+        let mut input = previous_commitment.to_bytes()?;
+        input.extend(operations_commitment.to_bytes()?);
+        M::LeafHashGadget::evaluate(&leaf_crh_params, &input)?.enforce_equal(&commitment)?;
+
+        Ok(())
+    }
+}
+
+/// Embed an element of `ConstraintF` in the larger `StepConstraintF`.
+fn to_step_field(x: ConstraintF) -> StepConstraintF {
+    StepConstraintF::from_repr(x.into_repr()).expect("ConstraintF is smaller than StepConstraintF")
+}
+
+/// Hash the step verifying key into the public input of `WrapCircuit`.
+fn hash_step_vk(vk: &ark_groth16::VerifyingKey<MNT6_298>) -> ConstraintF {
+    let mut elements: Vec<ConstraintF> = vk.alpha_g1.to_field_elements().unwrap();
+    for g2 in &[vk.beta_g2, vk.gamma_g2, vk.delta_g2] {
+        elements.extend(g2.to_field_elements().unwrap());
+    }
+    for g1 in &vk.gamma_abc_g1 {
+        elements.extend(g1.to_field_elements().unwrap());
+    }
+    PoseidonParameters::cached().hash(elements.len() as u64, &elements)
+}
+
+/// Hash the step verifying key in-circuit, like `hash_step_vk`.
+fn hash_step_vk_var(
+    vk: &VerifyingKeyVar<MNT6_298, MNT6PairingVar>,
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let mut elements = vk.alpha_g1.to_constraint_field()?;
+    for g2 in &[&vk.beta_g2, &vk.gamma_g2, &vk.delta_g2] {
+        elements.extend(g2.to_constraint_field()?);
+    }
+    for g1 in &vk.gamma_abc_g1 {
+        elements.extend(g1.to_constraint_field()?);
+    }
+    let poseidon = PoseidonParametersVar::new_constant(
+        ConstraintSystemRef::None,
+        PoseidonParameters::cached(),
+    )?;
+    poseidon.hash(elements.len() as u64, &elements)
+}
+
+/// A placeholder proof, which fills the proof slots of the circuits during
+/// setup, and the previous proof slot of `StepCircuit` for the first batch.
+fn dummy_proof<E: PairingEngine>() -> ark_groth16::Proof<E> {
+    ark_groth16::Proof {
+        a: E::G1Affine::prime_subgroup_generator(),
+        b: E::G2Affine::prime_subgroup_generator(),
+        c: E::G1Affine::prime_subgroup_generator(),
+    }
+}
+
+/// Append the operations commitment of a batch to the commitment to a chain of
+/// batches. The commitment to the empty chain is zero.
+pub fn extend_commitment<S: SignatureScheme, M: AccMerkleConfigGadget>(
+    parameters: &Parameters<S, M>,
+    commitment: &OperationsCommitment,
+    operations_commitment: &OperationsCommitment,
+) -> OperationsCommitment {
+    let input = ark_ff::to_bytes![commitment, operations_commitment].unwrap();
+    M::LeafHash::evaluate(&parameters.leaf_crh_params, &input).unwrap()
+}
+
+/// Commit to the deposits and withdrawals of a chain of batches, given as the
+/// operations of each batch, in order.
+pub fn commit_to_chain<S: SignatureScheme, M: AccMerkleConfigGadget>(
+    parameters: &Parameters<S, M>,
+    batches: &[&[Operation<S>]],
+) -> OperationsCommitment {
+    batches
+        .iter()
+        .fold(OperationsCommitment::zero(), |commitment, operations| {
+            let operations_commitment = operation::commit_to_operations(parameters, operations);
+            extend_commitment(parameters, &commitment, &operations_commitment)
+        })
+}
+
+/// Sample proving and verifying keys for chains of batches that are proven with
+/// `batch_vk`.
+#[allow(clippy::type_complexity)]
+pub fn setup<
+    R: RngCore + CryptoRng,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+>(
+    batch_vk: &prover::VerifyingKey<Groth16Backend, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>,
+    rng: &mut R,
+) -> Result<(ProvingKey<S, M>, VerifyingKey<S, M>), SynthesisError> {
+    // `WrapCircuit` only depends on the number of inputs of the step verifying
+    // key, so it is set up first, with a placeholder key.
+    let g1 = <MNT6_298 as PairingEngine>::G1Affine::prime_subgroup_generator();
+    let g2 = <MNT6_298 as PairingEngine>::G2Affine::prime_subgroup_generator();
+    let placeholder_step_vk = ark_groth16::VerifyingKey {
+        alpha_g1: g1,
+        beta_g2: g2,
+        gamma_g2: g2,
+        delta_g2: g2,
+        gamma_abc_g1: vec![g1; NUM_STEP_INPUTS + 1],
+    };
+    let wrap_circuit = WrapCircuit::<M> {
+        leaf_crh_params: batch_vk.ledger_params.leaf_crh_params.clone(),
+        initial_root: AccRoot::zero(),
+        final_root: AccRoot::zero(),
+        commitment: OperationsCommitment::zero(),
+        step_vk_hash: ConstraintF::zero(),
+        step_vk: placeholder_step_vk,
+        previous_commitment: OperationsCommitment::zero(),
+        operations_commitment: OperationsCommitment::zero(),
+        step_proof: dummy_proof(),
+    };
+    let (wrap_pk, wrap_vk) = Groth16Backend::setup(wrap_circuit, rng)?;
+
+    let step_circuit = StepCircuit {
+        batch_vk: batch_vk.snark_vk.clone(),
+        wrap_vk: wrap_vk.clone(),
+        initial_root: AccRoot::zero(),
+        final_root: AccRoot::zero(),
+        previous_commitment: OperationsCommitment::zero(),
+        operations_commitment: OperationsCommitment::zero(),
+        step_vk_hash: ConstraintF::zero(),
+        previous_root: AccRoot::zero(),
+        batch_proof: dummy_proof(),
+        is_first: true,
+        previous_proof: dummy_proof(),
+    };
+    let (step_pk, step_vk) = <StepSnark as ark_snark::CircuitSpecificSetupSNARK<
+        StepConstraintF,
+    >>::setup(step_circuit, rng)?;
+    let step_vk_hash = hash_step_vk(&step_vk);
+
+    let wrap_pvk = Groth16Backend::process_vk(&wrap_vk)?;
+    let pk = ProvingKey {
+        ledger_params: batch_vk.ledger_params.clone(),
+        batch_vk: batch_vk.snark_vk.clone(),
+        batch_pvk: batch_vk.snark_pvk.clone(),
+        step_pk,
+        step_vk,
+        step_vk_hash,
+        wrap_pk,
+        wrap_vk,
+    };
+    let vk = VerifyingKey {
+        ledger_params: batch_vk.ledger_params.clone(),
+        wrap_pvk,
+        step_vk_hash,
+    };
+    Ok((pk, vk))
+}
+
+/// Extend `chain` with a batch that moves the ledger from `initial_root` to
+/// `final_root`, and contains exactly the deposits and withdrawals in
+/// `operations`. A `chain` of `None` starts a new chain with the batch.
+/// Returns `SynthesisError::Unsatisfiable` if `batch_proof` does not verify, or
+/// if the batch does not start at the final root of `chain`.
+pub fn extend_chain<R: RngCore + CryptoRng, S: SignatureScheme, M: AccMerkleConfigGadget>(
+    pk: &ProvingKey<S, M>,
+    chain: Option<&ChainProof>,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    operations: &[Operation<S>],
+    batch_proof: &Proof<Groth16Backend>,
+    rng: &mut R,
+) -> Result<ChainProof, SynthesisError> {
+    let operations_commitment = operation::commit_to_operations(&pk.ledger_params, operations);
+    let batch_inputs = prover::public_inputs(initial_root, final_root, &operations_commitment);
+    if !Groth16Backend::verify(&pk.batch_pvk, &batch_inputs, batch_proof)? {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let (chain_root, previous_commitment, previous_proof) = match chain {
+        Some(chain) if chain.final_root != *initial_root => {
+            return Err(SynthesisError::Unsatisfiable)
+        }
+        Some(chain) => (chain.initial_root, chain.commitment, chain.proof.clone()),
+        None => (*initial_root, OperationsCommitment::zero(), dummy_proof()),
+    };
+
+    let step_circuit = StepCircuit {
+        batch_vk: pk.batch_vk.clone(),
+        wrap_vk: pk.wrap_vk.clone(),
+        initial_root: chain_root,
+        final_root: *final_root,
+        previous_commitment,
+        operations_commitment,
+        step_vk_hash: pk.step_vk_hash,
+        previous_root: *initial_root,
+        batch_proof: batch_proof.clone(),
+        is_first: chain.is_none(),
+        previous_proof,
+    };
+    let step_proof =
+        <StepSnark as ark_snark::SNARK<StepConstraintF>>::prove(&pk.step_pk, step_circuit, rng)?;
+
+    let commitment = extend_commitment(
+        &pk.ledger_params,
+        &previous_commitment,
+        &operations_commitment,
+    );
+    let wrap_circuit = WrapCircuit::<M> {
+        leaf_crh_params: pk.ledger_params.leaf_crh_params.clone(),
+        initial_root: chain_root,
+        final_root: *final_root,
+        commitment,
+        step_vk_hash: pk.step_vk_hash,
+        step_vk: pk.step_vk.clone(),
+        previous_commitment,
+        operations_commitment,
+        step_proof,
+    };
+    let proof = Groth16Backend::prove(&pk.wrap_pk, wrap_circuit, rng)?;
+    Ok(ChainProof {
+        initial_root: chain_root,
+        final_root: *final_root,
+        commitment,
+        proof,
+    })
+}
+
+/// Check that `proof` shows that a chain of batches, whose deposits and
+/// withdrawals are exactly those in `batches`, moves the ledger from
+/// `initial_root` to `final_root`. This costs one Groth16 verification,
+/// however long the chain is, on top of committing to the operations.
+pub fn verify_chain<S: SignatureScheme, M: AccMerkleConfigGadget>(
+    vk: &VerifyingKey<S, M>,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    batches: &[&[Operation<S>]],
+    proof: &Proof<Groth16Backend>,
+) -> Result<bool, SynthesisError> {
+    let commitment = commit_to_chain(&vk.ledger_params, batches);
+    let inputs = [*initial_root, *final_root, commitment, vk.step_vk_hash];
+    Groth16Backend::verify(&vk.wrap_pvk, &inputs, proof)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::account::Nonce;
+    use ark_simple_payments::ledger::{Amount, State};
+    use ark_simple_payments::operation::Deposit;
+    use ark_simple_payments::transaction::Transaction;
+
+    #[test]
+    fn chains_of_batches_are_aggregated() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let (batch_pk, batch_vk) =
+            prover::setup::<Groth16Backend, _, 1, 0, 1>(&pp, 32, &mut rng).unwrap();
+        let (pk, vk) = setup(&batch_vk, &mut rng).unwrap();
+
+        // Alice funds her account and pays Bob in the first batch. Bob receives a
+        // deposit and another payment from Alice in the second.
+        let ops_1 = [Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(20),
+        })];
+        let txs_1 = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let root_0 = state.root();
+        let proof_1 =
+            prover::prove_batch(&batch_pk, &mut state, &[], &ops_1, &txs_1, &mut rng).unwrap();
+        let root_1 = state.root();
+
+        let ops_2 = [Operation::Deposit(Deposit {
+            account: bob_id,
+            amount: Amount(7),
+        })];
+        let txs_2 = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(3),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        )];
+        let proof_2 =
+            prover::prove_batch(&batch_pk, &mut state, &[], &ops_2, &txs_2, &mut rng).unwrap();
+        let root_2 = state.root();
+
+        let chain_1 =
+            extend_chain(&pk, None, &root_0, &root_1, &ops_1, &proof_1, &mut rng).unwrap();
+        assert!(verify_chain(&vk, &root_0, &root_1, &[&ops_1], &chain_1.proof).unwrap());
+
+        let chain_2 = extend_chain(
+            &pk,
+            Some(&chain_1),
+            &root_1,
+            &root_2,
+            &ops_2,
+            &proof_2,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(chain_2.initial_root, root_0);
+        assert_eq!(chain_2.final_root, root_2);
+        assert_eq!(chain_2.commitment, commit_to_chain(&pp, &[&ops_1, &ops_2]));
+        assert!(verify_chain(&vk, &root_0, &root_2, &[&ops_1, &ops_2], &chain_2.proof).unwrap());
+
+        // The proof does not verify against other roots.
+        assert!(!verify_chain(&vk, &root_1, &root_2, &[&ops_1, &ops_2], &chain_2.proof).unwrap());
+        assert!(!verify_chain(&vk, &root_0, &root_1, &[&ops_1, &ops_2], &chain_2.proof).unwrap());
+        // Nor against other deposits, or against the same deposits in another order.
+        assert!(!verify_chain(&vk, &root_0, &root_2, &[&ops_2], &chain_2.proof).unwrap());
+        assert!(!verify_chain(&vk, &root_0, &root_2, &[&ops_2, &ops_1], &chain_2.proof).unwrap());
+
+        // Batches that do not start where the chain ends cannot extend it.
+        assert!(matches!(
+            extend_chain(
+                &pk,
+                Some(&chain_2),
+                &root_1,
+                &root_2,
+                &ops_2,
+                &proof_2,
+                &mut rng
+            ),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+        // Nor can batches whose proof does not verify.
+        assert!(matches!(
+            extend_chain(
+                &pk,
+                Some(&chain_1),
+                &root_1,
+                &root_2,
+                &ops_1,
+                &proof_2,
+                &mut rng
+            ),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::ConstraintF;
+use ark_gm17::GM17;
+use ark_groth16::Groth16;
+use ark_relations::r1cs::{ConstraintSynthesizer, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+
+/// The pairing-friendly curve of the backends, whose scalar field is `ConstraintF`.
+/// It is BLS12-381 by default, and MNT4-298 if the `mnt4-298` feature is enabled.
+#[cfg(not(feature = "mnt4-298"))]
+pub type PairingCurve = ark_bls12_381::Bls12_381;
+/// The pairing-friendly curve of the backends, whose scalar field is `ConstraintF`.
+/// It is BLS12-381 by default, and MNT4-298 if the `mnt4-298` feature is enabled.
+#[cfg(feature = "mnt4-298")]
+pub type PairingCurve = ark_mnt4_298::MNT4_298;
+
+/// The Groth16 zkSNARK over `PairingCurve`.
+pub type Groth16Backend = Groth16<PairingCurve>;
+/// The Groth-Maller zkSNARK over `PairingCurve`.
+pub type GM17Backend = GM17<PairingCurve>;
+
+/// A zkSNARK that can be used to prove and verify `Rollup` batches, or any
+/// other circuit over `ConstraintF`.
+pub trait SnarkBackend {
+    /// The key used to prove batches.
+    type ProvingKey: Clone + CanonicalSerialize + CanonicalDeserialize;
+    /// The key used to verify batches.
+    type VerifyingKey: Clone + CanonicalSerialize + CanonicalDeserialize;
+    /// The verifying key, preprocessed to enable faster verification.
+    type ProcessedVerifyingKey: Clone;
+    /// A proof that a batch of transactions is valid.
+    type Proof: Clone + CanonicalSerialize + CanonicalDeserialize;
+
+    /// Sample proving and verifying keys for circuits with the same shape as `circuit`.
+    fn setup<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), SynthesisError>;
+
+    /// Prove that `circuit` is satisfied.
+    fn prove<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        pk: &Self::ProvingKey,
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<Self::Proof, SynthesisError>;
+
+    /// Preprocess `vk` to enable faster verification.
+    fn process_vk(vk: &Self::VerifyingKey) -> Result<Self::ProcessedVerifyingKey, SynthesisError>;
+
+    /// Check that `proof` is valid with respect to `public_inputs`.
+    fn verify(
+        pvk: &Self::ProcessedVerifyingKey,
+        public_inputs: &[ConstraintF],
+        proof: &Self::Proof,
+    ) -> Result<bool, SynthesisError>;
+}
+
+impl SnarkBackend for Groth16Backend {
+    type ProvingKey = ark_groth16::ProvingKey<PairingCurve>;
+    type VerifyingKey = ark_groth16::VerifyingKey<PairingCurve>;
+    type ProcessedVerifyingKey = ark_groth16::PreparedVerifyingKey<PairingCurve>;
+    type Proof = ark_groth16::Proof<PairingCurve>;
+
+    fn setup<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), SynthesisError> {
+        <Self as SNARK<ConstraintF>>::circuit_specific_setup(circuit, rng)
+    }
+
+    fn prove<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        pk: &Self::ProvingKey,
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<Self::Proof, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::prove(pk, circuit, rng)
+    }
+
+    fn process_vk(vk: &Self::VerifyingKey) -> Result<Self::ProcessedVerifyingKey, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::process_vk(vk)
+    }
+
+    fn verify(
+        pvk: &Self::ProcessedVerifyingKey,
+        public_inputs: &[ConstraintF],
+        proof: &Self::Proof,
+    ) -> Result<bool, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::verify_with_processed_vk(pvk, public_inputs, proof)
+    }
+}
+
+impl SnarkBackend for GM17Backend {
+    type ProvingKey = ark_gm17::ProvingKey<PairingCurve>;
+    type VerifyingKey = ark_gm17::VerifyingKey<PairingCurve>;
+    type ProcessedVerifyingKey = ark_gm17::PreparedVerifyingKey<PairingCurve>;
+    type Proof = ark_gm17::Proof<PairingCurve>;
+
+    fn setup<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), SynthesisError> {
+        <Self as SNARK<ConstraintF>>::circuit_specific_setup(circuit, rng)
+    }
+
+    fn prove<R: RngCore + CryptoRng, C: ConstraintSynthesizer<ConstraintF>>(
+        pk: &Self::ProvingKey,
+        circuit: C,
+        rng: &mut R,
+    ) -> Result<Self::Proof, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::prove(pk, circuit, rng)
+    }
+
+    fn process_vk(vk: &Self::VerifyingKey) -> Result<Self::ProcessedVerifyingKey, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::process_vk(vk)
+    }
+
+    fn verify(
+        pvk: &Self::ProcessedVerifyingKey,
+        public_inputs: &[ConstraintF],
+        proof: &Self::Proof,
+    ) -> Result<bool, SynthesisError> {
+        <Self as SNARK<ConstraintF>>::verify_with_processed_vk(pvk, public_inputs, proof)
+    }
+}
@@ -6,13 +6,20 @@ use ark_crypto_primitives::crh::{
     constraints::{CRHGadget, TwoToOneCRHGadget},
     injective_map::TECompressor,
 };
-use ark_crypto_primitives::merkle_tree::constraints::PathVar;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_crypto_primitives::merkle_tree::{self, constraints::PathVar};
 use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::curve::{constraints::EdwardsVar, EdwardsProjective, Fq};
 use ark_simple_payments::ledger::*;
-use ark_simple_payments::signature::schnorr::constraints::ParametersVar as SchnorrParamsVar;
+use ark_simple_payments::message::DomainTag;
+use ark_simple_payments::poseidon::constraints::{
+    CRHGadget as PoseidonCRHGadget, TwoToOneCRHGadget as PoseidonTwoToOneCRHGadget,
+};
+use ark_simple_payments::signature::schnorr::constraints::SchnorrSignatureVerifyGadget;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use derivative::Derivative;
 use std::borrow::Borrow;
 
 /// Represents transaction amounts and account balances.
@@ -95,36 +102,91 @@ pub type LeafHashGadget = PedersenCRHCompressorGadget<
     TECompressorGadget,
 >;
 
-pub type AccRootVar =
-    <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
-pub type AccPathVar = PathVar<MerkleConfig, LeafHashGadget, TwoToOneHashGadget, ConstraintF>;
-pub type LeafHashParamsVar = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::ParametersVar;
-pub type TwoToOneHashParamsVar =
-    <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
-
-/// The parameters that are used in transaction creation and validation.
-pub struct ParametersVar {
-    pub sig_params: SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
-    pub leaf_crh_params: LeafHashParamsVar,
-    pub two_to_one_crh_params: TwoToOneHashParamsVar,
+/// The gadgets that evaluate the hashes of an account tree configuration in-circuit.
+/// Like the native hashes, they output single field elements.
+pub trait AccMerkleConfigGadget: AccMerkleConfig {
+    type LeafHashGadget: CRHGadget<Self::LeafHash, ConstraintF, OutputVar = FpVar<ConstraintF>>;
+    type TwoToOneHashGadget: TwoToOneCRHGadget<
+        Self::TwoToOneHash,
+        ConstraintF,
+        OutputVar = FpVar<ConstraintF>,
+    >;
+}
+
+impl AccMerkleConfigGadget for MerkleConfig {
+    type LeafHashGadget = LeafHashGadget;
+    type TwoToOneHashGadget = TwoToOneHashGadget;
 }
 
-impl AllocVar<Parameters, ConstraintF> for ParametersVar {
+impl AccMerkleConfigGadget for PoseidonMerkleConfig {
+    type LeafHashGadget = PoseidonCRHGadget<Fq, LEAF_HASH_INPUT_BYTES>;
+    type TwoToOneHashGadget = PoseidonTwoToOneCRHGadget<Fq>;
+}
+
+pub type AccRootVar = FpVar<ConstraintF>;
+pub type AccPathVar<M = MerkleConfig> = PathVar<
+    M,
+    <M as AccMerkleConfigGadget>::LeafHashGadget,
+    <M as AccMerkleConfigGadget>::TwoToOneHashGadget,
+    ConstraintF,
+>;
+pub type LeafDigestVar = FpVar<ConstraintF>;
+pub type LeafHashParamsVar<M = MerkleConfig> =
+    <<M as AccMerkleConfigGadget>::LeafHashGadget as CRHGadget<
+        <M as merkle_tree::Config>::LeafHash,
+        ConstraintF,
+    >>::ParametersVar;
+pub type TwoToOneHashParamsVar<M = MerkleConfig> =
+    <<M as AccMerkleConfigGadget>::TwoToOneHashGadget as TwoToOneCRHGadget<
+        <M as merkle_tree::Config>::TwoToOneHash,
+        ConstraintF,
+    >>::ParametersVar;
+
+/// The gadget that verifies signatures of `DefaultSignatureScheme`.
+pub type DefaultSigVerifyGadget = SchnorrSignatureVerifyGadget<EdwardsProjective, EdwardsVar>;
+
+/// The parameters that are used in transaction creation and validation, where
+/// signatures of the scheme `S` are verified with the gadget `SG`, and the account
+/// tree hashes as configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct ParametersVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The domain tag of the ledger, which signed messages start with. It is a
+    /// constant of the circuit.
+    pub domain_tag: DomainTag,
+    pub sig_params: SG::ParametersVar,
+    pub leaf_crh_params: LeafHashParamsVar<M>,
+    pub two_to_one_crh_params: TwoToOneHashParamsVar<M>,
+}
+
+impl<S, SG, M> AllocVar<Parameters<S, M>, ConstraintF> for ParametersVar<S, SG, M>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+{
     #[tracing::instrument(target = "r1cs", skip(cs, f, _mode))]
-    fn new_variable<T: Borrow<Parameters>>(
+    fn new_variable<T: Borrow<Parameters<S, M>>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         _mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
         let cs = cs.into();
         f().and_then(|params| {
-            let params: &Parameters = params.borrow();
-            let sig_params = SchnorrParamsVar::new_constant(cs.clone(), &params.sig_params)?;
+            let params: &Parameters<S, M> = params.borrow();
+            let sig_params = SG::ParametersVar::new_constant(cs.clone(), &params.sig_params)?;
             let leaf_crh_params =
-                LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params)?;
-            let two_to_one_crh_params =
-                TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params)?;
+                LeafHashParamsVar::<M>::new_constant(cs.clone(), &params.leaf_crh_params)?;
+            let two_to_one_crh_params = TwoToOneHashParamsVar::<M>::new_constant(
+                cs.clone(),
+                &params.two_to_one_crh_params,
+            )?;
             Ok(Self {
+                domain_tag: params.domain_tag,
                 sig_params,
                 leaf_crh_params,
                 two_to_one_crh_params,
@@ -132,3 +194,188 @@ impl AllocVar<Parameters, ConstraintF> for ParametersVar {
         })
     }
 }
+
+/// A membership proof for a leaf of the account tree whose position is given
+/// in-circuit, rather than being chosen by the prover as in `AccPathVar`.
+/// This ties the leaf to a particular account, or to the account counter.
+pub struct AccIndexedPathVar {
+    /// The hash of the sibling of the leaf.
+    leaf_sibling: LeafDigestVar,
+    /// The siblings of the non-leaf nodes on the path, from top to bottom.
+    auth_path: Vec<AccRootVar>,
+}
+
+impl AccIndexedPathVar {
+    /// Compute the root of the account tree in which the leaf at position `index`
+    /// is `leaf`. `index` is given in little-endian bits, and missing high bits are
+    /// zero. Enforces that `index` fits in the tree.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, index, leaf))]
+    pub fn calculate_root<S, SG, M>(
+        &self,
+        parameters: &ParametersVar<S, SG, M>,
+        index: &[Boolean<ConstraintF>],
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<AccRootVar, SynthesisError>
+    where
+        S: SignatureScheme,
+        SG: SigVerifyGadget<S, ConstraintF>,
+        M: AccMerkleConfigGadget,
+    {
+        self.enforce_index_fits(index)?;
+        self.root_from_leaf(parameters, index, leaf)
+    }
+
+    /// Enforce that the high bits of `index`, which the tree has no levels for, are zero.
+    fn enforce_index_fits(&self, index: &[Boolean<ConstraintF>]) -> Result<(), SynthesisError> {
+        let height = self.auth_path.len() + 2;
+        for bit in index.iter().skip(height - 1) {
+            bit.enforce_equal(&Boolean::FALSE)?;
+        }
+        Ok(())
+    }
+
+    /// Like `calculate_root`, but without checking that `index` fits in the tree.
+    fn root_from_leaf<S, SG, M>(
+        &self,
+        parameters: &ParametersVar<S, SG, M>,
+        index: &[Boolean<ConstraintF>],
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<AccRootVar, SynthesisError>
+    where
+        S: SignatureScheme,
+        SG: SigVerifyGadget<S, ConstraintF>,
+        M: AccMerkleConfigGadget,
+    {
+        let index_bit = |level: usize| index.get(level).cloned().unwrap_or(Boolean::FALSE);
+
+        let leaf_hash = M::LeafHashGadget::evaluate(&parameters.leaf_crh_params, leaf)?;
+        let is_right_child = index_bit(0);
+        let left = is_right_child.select(&self.leaf_sibling, &leaf_hash)?;
+        let right = is_right_child.select(&leaf_hash, &self.leaf_sibling)?;
+        let mut current = M::TwoToOneHashGadget::evaluate(
+            &parameters.two_to_one_crh_params,
+            &left.to_bytes()?,
+            &right.to_bytes()?,
+        )?;
+        // Walk up the tree, so that the siblings are visited from bottom to top.
+        for (level, sibling) in (1..).zip(self.auth_path.iter().rev()) {
+            let is_right_child = index_bit(level);
+            let left = is_right_child.select(sibling, &current)?;
+            let right = is_right_child.select(&current, sibling)?;
+            current = M::TwoToOneHashGadget::evaluate(
+                &parameters.two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        Ok(current)
+    }
+}
+
+/// Updates a leaf of the account tree along a single authentication path: the old
+/// leaf is checked against the old root, and the new root is computed from the new
+/// leaf and the same siblings. Sharing the path means that no other leaf can
+/// change, unlike with separate pre-update and post-update paths, and that the
+/// index is only checked once.
+pub struct MerkleUpdateGadget;
+
+impl MerkleUpdateGadget {
+    /// Check whether the leaf at position `index` is `old_leaf` in the tree whose
+    /// root is `old_root`, and compute the root of the tree in which it is
+    /// `new_leaf` instead. `index` is given as for `AccIndexedPathVar::calculate_root`,
+    /// and must fit in the tree. Returns whether the old leaf is in the tree, and the
+    /// new root, which is only meaningful if it is.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(parameters, path, index, old_leaf, new_leaf, old_root)
+    )]
+    pub fn update<S, SG, M>(
+        parameters: &ParametersVar<S, SG, M>,
+        path: &AccIndexedPathVar,
+        index: &[Boolean<ConstraintF>],
+        old_leaf: &[UInt8<ConstraintF>],
+        new_leaf: &[UInt8<ConstraintF>],
+        old_root: &AccRootVar,
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError>
+    where
+        S: SignatureScheme,
+        SG: SigVerifyGadget<S, ConstraintF>,
+        M: AccMerkleConfigGadget,
+    {
+        path.enforce_index_fits(index)?;
+        let old_leaf_exists = path
+            .root_from_leaf(parameters, index, old_leaf)?
+            .is_eq(old_root)?;
+        let new_root = path.root_from_leaf(parameters, index, new_leaf)?;
+        Ok((old_leaf_exists, new_root))
+    }
+}
+
+/// An update of the leaf at position `index` of the account tree, from `old_leaf`
+/// to `new_leaf`. Both leaves share the siblings of `path`, so no other leaf
+/// changes.
+pub struct AccUpdateVar<'a> {
+    /// The position of the leaf, in little-endian bits.
+    pub index: Vec<Boolean<ConstraintF>>,
+    /// The authentication path of the leaf, in the tree before the update.
+    pub path: &'a AccIndexedPathVar,
+    pub old_leaf: Vec<UInt8<ConstraintF>>,
+    pub new_leaf: Vec<UInt8<ConstraintF>>,
+}
+
+impl AccUpdateVar<'_> {
+    /// Apply `updates` in turn to the account tree whose root is `root`. Each update
+    /// starts from the root left by the previous one: for a transaction, the sender's
+    /// leaf is updated in `root_0`, which gives `root_1`, and the recipient's leaf is
+    /// then updated in `root_1`, which gives `root_2`. The intermediate roots are
+    /// computed in-circuit. Returns whether each old leaf was in the tree that it was
+    /// updated in, and the root after the last update.
+    #[tracing::instrument(target = "r1cs", skip(parameters, root, updates))]
+    pub fn apply_in_turn<S, SG, M>(
+        parameters: &ParametersVar<S, SG, M>,
+        root: &AccRootVar,
+        updates: &[Self],
+    ) -> Result<(Boolean<ConstraintF>, AccRootVar), SynthesisError>
+    where
+        S: SignatureScheme,
+        SG: SigVerifyGadget<S, ConstraintF>,
+        M: AccMerkleConfigGadget,
+    {
+        let mut all_exist = Boolean::TRUE;
+        let mut root = root.clone();
+        for update in updates {
+            let (exists, new_root) = MerkleUpdateGadget::update(
+                parameters,
+                update.path,
+                &update.index,
+                &update.old_leaf,
+                &update.new_leaf,
+                &root,
+            )?;
+            all_exist = all_exist.and(&exists)?;
+            root = new_root;
+        }
+        Ok((all_exist, root))
+    }
+}
+
+impl<M: AccMerkleConfig> AllocVar<AccPath<M>, ConstraintF> for AccIndexedPathVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AccPath<M>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|path| {
+            let path: &AccPath<M> = path.borrow();
+            let leaf_sibling =
+                LeafDigestVar::new_variable(cs.clone(), || Ok(path.leaf_sibling_hash), mode)?;
+            let auth_path = Vec::<AccRootVar>::new_variable(cs, || Ok(&path.auth_path[..]), mode)?;
+            Ok(Self {
+                leaf_sibling,
+                auth_path,
+            })
+        })
+    }
+}
@@ -1,17 +1,44 @@
-pub type ConstraintF = ark_bls12_381::Fr;
+/// The field of the rollup circuits, which is the base field of the ledger's curve.
+pub type ConstraintF = ark_simple_payments::curve::Fq;
 
 pub mod account;
+#[cfg(feature = "mnt4-298")]
+pub mod aggregation;
+pub mod backend;
 pub mod ledger;
-pub mod transaction;
+pub mod operation;
+pub mod prover;
+pub mod registration;
 pub mod rollup;
+pub mod rollup_fee;
+pub mod transaction;
+pub mod transaction_fee;
 
 #[cfg(test)]
 mod tests {
     use crate::rollup::Rollup;
-    use ark_relations::r1cs::{ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints};
-    use tracing_subscriber::layer::SubscriberExt;
-    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use crate::rollup_fee::RollupWithFee;
+    use ark_relations::r1cs::{
+        ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
+    };
+    use ark_simple_payments::account::{AccountId, AccountInformation, Nonce};
+    use ark_simple_payments::curve::{constraints::EdwardsVar, EdwardsProjective, Fq};
+    use ark_simple_payments::ledger::{
+        next_account_leaf, Amount, ChainId, Parameters, State, NEXT_ACCOUNT_LEAF_INDEX,
+    };
+    use ark_simple_payments::message;
+    use ark_simple_payments::operation::{commit_to_operations, Deposit, Operation, Withdrawal};
+    use ark_simple_payments::random_oracle::poseidon;
+    use ark_simple_payments::signature::schnorr::{
+        constraints::SchnorrSignatureVerifyGadget, Schnorr,
+    };
+    use ark_simple_payments::signature::threshold::{
+        self, constraints::ThresholdSignatureVerifyGadget, Threshold,
+    };
+    use ark_simple_payments::signature::SignatureScheme;
     use ark_simple_payments::transaction::Transaction;
+    use ark_simple_payments::Error;
+    use tracing_subscriber::layer::SubscriberExt;
 
     #[test]
     fn fee_as_second_tx_validity_test() {
@@ -26,23 +53,40 @@ mod tests {
         let mut state = State::new(32, &pp);
 
         // Accounts
-        let (alice_id, _alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
         state.update_balance(alice_id, Amount(20)).unwrap();
 
         let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
         let (collector_id, _c_pk, _c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // tx + fee-as-tx
-        let tx      = Transaction::create(&pp, alice_id, bob_id,       Amount(5), &alice_sk, &mut rng);
-        let fee_tx  = Transaction::create(&pp, alice_id, collector_id, Amount(2), &alice_sk, &mut rng);
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let fee_tx = Transaction::create(
+            &pp,
+            alice_id,
+            collector_id,
+            Amount(2),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
 
-        let mut temp_state = state.clone();
-        let rollup = Rollup::<2>::with_state_and_transactions(
+        let (rollup, diff) = Rollup::<2>::with_state_and_transactions(
             pp.clone(),
             &[tx.clone(), fee_tx.clone()],
-            &mut temp_state,
+            &state,
             true,
-        ).unwrap();
+        )
+        .unwrap();
 
         // Prove constraints
         let cs = ConstraintSystem::new_ref();
@@ -50,8 +94,1061 @@ mod tests {
         assert!(cs.is_satisfied().unwrap());
 
         // Native balances reflect (5 to Bob) and (2 to collector)
-        assert_eq!(temp_state.id_to_account_info.get(&alice_id).unwrap().balance, Amount(20 - 5 - 2));
-        assert_eq!(temp_state.id_to_account_info.get(&bob_id).unwrap().balance,   Amount(5));
-        assert_eq!(temp_state.id_to_account_info.get(&collector_id).unwrap().balance, Amount(2));
+        assert_eq!(
+            diff.account_info(&alice_id).unwrap().balance,
+            Amount(20 - 5 - 2)
+        );
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(5));
+        assert_eq!(diff.account_info(&collector_id).unwrap().balance, Amount(2));
+    }
+
+    #[test]
+    fn replayed_tx_in_batch_is_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+
+        // Natively, the second copy of `tx` carries a stale nonce.
+        assert!(matches!(
+            Rollup::<2>::with_state_and_transactions(
+                pp.clone(),
+                &[tx.clone(), tx.clone()],
+                &state,
+                true,
+            ),
+            Err(Error::BadNonce)
+        ));
+
+        // An operator that skips native validation cannot prove the replay either.
+        let (rollup, _) = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &state,
+            false,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn tx_signed_for_another_chain_is_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // The same keys sign for another chain, whose parameters are otherwise equal.
+        let mut other_pp = pp.clone();
+        other_pp.chain_id = ChainId(1);
+        other_pp.domain_tag = message::domain_tag(&other_pp);
+        let tx = Transaction::create(
+            &other_pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(matches!(
+            Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &state,
+                true,
+            ),
+            Err(Error::BadSignature)
+        ));
+
+        // The circuit checks the signature over this ledger's domain tag.
+        let (rollup, _) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &state, false).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn fee_tx_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (collector_id, _c_pk, _c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(collector_id).unwrap();
+
+        let tx1 = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            Amount(2),
+            &alice_sk,
+            &mut rng,
+        );
+        let tx2 = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(3),
+            Nonce(1),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+
+        let (rollup, diff) = RollupWithFee::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx2.clone()],
+            &state,
+            true,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(diff.account_info(&alice_id).unwrap().balance, Amount(9));
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(8));
+        assert_eq!(diff.account_info(&collector_id).unwrap().balance, Amount(3));
+
+        // Claiming that the fees went to some other key fails.
+        let (mut rollup, _) = RollupWithFee::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx2.clone()],
+            &state,
+            true,
+        )
+        .unwrap();
+        rollup.fee_collector_pk = Some(bob_pk);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // The fee is bound by the signature, so the operator cannot change it.
+        let mut bad_tx = tx1.clone();
+        bad_tx.fee = Amount(4);
+        let (rollup, _) = RollupWithFee::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[bad_tx, tx2.clone()],
+            &state,
+            false,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // The fee-less rollup does not accept transactions that pay a fee.
+        let (rollup, _) =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &[tx1, tx2], &state, true)
+                .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn self_transfers_and_repeated_accounts_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays herself, then pays Bob, who pays her back, all in one batch.
+        let txs = [
+            Transaction::create(
+                &pp,
+                alice_id,
+                alice_id,
+                Amount(5),
+                Nonce(0),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(4),
+                Nonce(1),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create(
+                &pp,
+                bob_id,
+                alice_id,
+                Amount(1),
+                Nonce(0),
+                &bob_sk,
+                &mut rng,
+            ),
+        ];
+        let (rollup, diff) =
+            Rollup::<3>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        // The self-transfer only increments Alice's nonce.
+        let alice_info = diff.account_info(&alice_id).unwrap();
+        assert_eq!(alice_info.balance, Amount(17));
+        assert_eq!(alice_info.nonce, Nonce(2));
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(3));
+
+        // Alice cannot pay herself more than she has, even though her balance would
+        // not change.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            alice_id,
+            Amount(25),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(matches!(
+            Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &state,
+                true
+            ),
+            Err(Error::InsufficientBalance)
+        ));
+        let (rollup, _) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &state, false).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn untouched_accounts_cannot_change_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let (mut rollup, diff) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], &state, true).unwrap();
+
+        // The operator also credits Carol, and claims the resulting root. The new root
+        // is computed in-circuit from the paths of Alice and Bob, so it cannot include
+        // changes to other accounts.
+        let mut forged_state = state.clone();
+        forged_state.commit(diff).unwrap();
+        forged_state.update_balance(carol_id, Amount(100)).unwrap();
+        let forged_root = forged_state.root();
+        rollup.post_tx_roots = Some(vec![forged_root]);
+        rollup.final_root = Some(forged_root);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn fee_tx_with_repeated_accounts_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (collector_id, _c_pk, c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(collector_id).unwrap();
+
+        let txs = [
+            // Alice pays herself, and the fee.
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                alice_id,
+                Amount(5),
+                Nonce(0),
+                Amount(2),
+                &alice_sk,
+                &mut rng,
+            ),
+            // Alice pays the fee collector, who also receives the fee.
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                collector_id,
+                Amount(3),
+                Nonce(1),
+                Amount(1),
+                &alice_sk,
+                &mut rng,
+            ),
+            // The fee collector pays Alice, and the fee to itself.
+            Transaction::create_with_fee(
+                &pp,
+                collector_id,
+                alice_id,
+                Amount(2),
+                Nonce(0),
+                Amount(1),
+                &c_sk,
+                &mut rng,
+            ),
+            // The fee collector pays itself, and the fee to itself.
+            Transaction::create_with_fee(
+                &pp,
+                collector_id,
+                collector_id,
+                Amount(1),
+                Nonce(1),
+                Amount(1),
+                &c_sk,
+                &mut rng,
+            ),
+        ];
+        let (rollup, diff) =
+            RollupWithFee::<4>::with_state_and_transactions(pp.clone(), &txs, &state, true)
+                .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        let alice_info = diff.account_info(&alice_id).unwrap();
+        assert_eq!(alice_info.balance, Amount(16));
+        assert_eq!(alice_info.nonce, Nonce(2));
+        let collector_info = diff.account_info(&collector_id).unwrap();
+        assert_eq!(collector_info.balance, Amount(4));
+        assert_eq!(collector_info.nonce, Nonce(2));
+
+        // The circuit must know which account is the fee collector.
+        let (mut rollup, _) =
+            RollupWithFee::<4>::with_state_and_transactions(pp.clone(), &txs, &state, true)
+                .unwrap();
+        rollup.fee_collector_id = Some(bob_id);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn wide_account_ids_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(1024, &pp);
+        let mut accounts = Vec::new();
+        for _ in 0..300 {
+            accounts.push(state.sample_keys_and_register(&pp, &mut rng).unwrap());
+        }
+        let (alice_id, _alice_pk, alice_sk) = accounts.pop().unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let bob_id = accounts[256].0;
+        assert!(bob_id > AccountId(255));
+
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, _) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // The whole identifier is covered by the signature in-circuit.
+        let (mut rollup, _) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        rollup.transactions.as_mut().unwrap()[0].recipient = AccountId(bob_id.0 - 256);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn tall_sparse_tree_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        // A tree with 2^32 leaves, of which only two are occupied.
+        let mut state = State::new(1 << 33, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, _) =
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn merkle_update_constraints_test() {
+        use crate::account::{AccountIdVar, AccountInformationVar};
+        use crate::ledger::{
+            AccIndexedPathVar, AccPathVar, AccRootVar, DefaultSigVerifyGadget, MerkleUpdateGadget,
+            ParametersVar,
+        };
+        use ark_r1cs_std::prelude::*;
+        use ark_simple_payments::ledger::DefaultSignatureScheme;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<DefaultSignatureScheme>::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+
+        let old_root = state.root();
+        let old_info = state.id_to_account_info[&alice_id].clone();
+        let mut new_info = old_info.clone();
+        new_info.balance = Amount(13);
+        let pre_path = state
+            .account_merkle_tree
+            .generate_proof(alice_id.leaf_index().unwrap())
+            .unwrap();
+        let new_root = state
+            .updated_root(alice_id, &pre_path, &old_info, &new_info)
+            .unwrap();
+        state.update_balance(alice_id, Amount(13)).unwrap();
+        assert_eq!(state.root(), new_root);
+        let post_path = state
+            .account_merkle_tree
+            .generate_proof(alice_id.leaf_index().unwrap())
+            .unwrap();
+
+        // Count the constraints of an account update, including the allocation of the
+        // paths it needs, and check that it agrees with `State::updated_root`.
+        let update_constraints = |strategy: &str| {
+            let cs = ConstraintSystem::new_ref();
+            let params =
+                ParametersVar::<DefaultSignatureScheme, DefaultSigVerifyGadget>::new_constant(
+                    cs.clone(),
+                    &pp,
+                )
+                .unwrap();
+            let old_root_var = AccRootVar::new_input(cs.clone(), || Ok(old_root)).unwrap();
+            let new_root_var = AccRootVar::new_input(cs.clone(), || Ok(new_root)).unwrap();
+            let index = AccountIdVar::new_witness(cs.clone(), || Ok(alice_id))
+                .unwrap()
+                .to_bits_le();
+            let old_leaf =
+                AccountInformationVar::<_, DefaultSigVerifyGadget>::new_witness(cs.clone(), || {
+                    Ok(old_info.clone())
+                })
+                .unwrap()
+                .to_bytes_le();
+            let new_leaf =
+                AccountInformationVar::<_, DefaultSigVerifyGadget>::new_witness(cs.clone(), || {
+                    Ok(new_info.clone())
+                })
+                .unwrap()
+                .to_bytes_le();
+
+            let before = cs.num_constraints();
+            match strategy {
+                "update" => {
+                    let path =
+                        AccIndexedPathVar::new_witness(cs.clone(), || Ok(&pre_path)).unwrap();
+                    let (exists, root) = MerkleUpdateGadget::update(
+                        &params,
+                        &path,
+                        &index,
+                        &old_leaf,
+                        &new_leaf,
+                        &old_root_var,
+                    )
+                    .unwrap();
+                    exists.enforce_equal(&Boolean::TRUE).unwrap();
+                    root.enforce_equal(&new_root_var).unwrap();
+                }
+                "indexed paths" => {
+                    let path =
+                        AccIndexedPathVar::new_witness(cs.clone(), || Ok(&pre_path)).unwrap();
+                    path.calculate_root(&params, &index, &old_leaf)
+                        .unwrap()
+                        .enforce_equal(&old_root_var)
+                        .unwrap();
+                    path.calculate_root(&params, &index, &new_leaf)
+                        .unwrap()
+                        .enforce_equal(&new_root_var)
+                        .unwrap();
+                }
+                "pre and post paths" => {
+                    let pre = AccPathVar::new_witness(cs.clone(), || Ok(&pre_path)).unwrap();
+                    let post = AccPathVar::new_witness(cs.clone(), || Ok(&post_path)).unwrap();
+                    pre.verify_membership(
+                        &params.leaf_crh_params,
+                        &params.two_to_one_crh_params,
+                        &old_root_var,
+                        &old_leaf.as_slice(),
+                    )
+                    .unwrap()
+                    .enforce_equal(&Boolean::TRUE)
+                    .unwrap();
+                    post.verify_membership(
+                        &params.leaf_crh_params,
+                        &params.two_to_one_crh_params,
+                        &new_root_var,
+                        &new_leaf.as_slice(),
+                    )
+                    .unwrap()
+                    .enforce_equal(&Boolean::TRUE)
+                    .unwrap();
+                }
+                _ => unreachable!(),
+            }
+            assert!(cs.is_satisfied().unwrap());
+            cs.num_constraints() - before
+        };
+
+        let update = update_constraints("update");
+        let indexed_paths = update_constraints("indexed paths");
+        let pre_and_post_paths = update_constraints("pre and post paths");
+        // Sharing the path saves the second index check. Unconstrained pre- and
+        // post-paths cost about as much, but let the prover change any other leaf.
+        assert!(update < indexed_paths);
+        assert!(pre_and_post_paths * 100 > update * 99);
+    }
+
+    #[test]
+    fn registration_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+
+        // Register Bob and Carol, and pay Carol in the same batch.
+        let (bob_pk, _bob_sk) =
+            Schnorr::<EdwardsProjective>::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (carol_pk, _carol_sk) =
+            Schnorr::<EdwardsProjective>::keygen(&pp.sig_params, &mut rng).unwrap();
+        let carol_id = AccountId(alice_id.0 + 2);
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            carol_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, diff) = Rollup::<1, 2>::with_state_registrations_and_transactions(
+            pp.clone(),
+            &[bob_pk, carol_pk],
+            &txs,
+            &state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(diff.account_info(&carol_id).unwrap().public_key, carol_pk);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // The new account must be given the identifier stored in the counter.
+        let (mut rollup, _) = Rollup::<1, 2>::with_state_registrations_and_transactions(
+            pp.clone(),
+            &[bob_pk, carol_pk],
+            &txs,
+            &state,
+            true,
+        )
+        .unwrap();
+        rollup.registration_witnesses.as_mut().unwrap()[0].0 = carol_id;
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // The circuit registers exactly `NUM_REG` accounts.
+        assert!(matches!(
+            Rollup::<1, 2>::with_state_registrations_and_transactions(
+                pp.clone(),
+                &[bob_pk],
+                &txs,
+                &state,
+                true,
+            ),
+            Err(Error::WrongRegistrationCount {
+                expected: 2,
+                got: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn registration_at_wrong_index_is_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (bob_pk, _bob_sk) =
+            Schnorr::<EdwardsProjective>::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        let (rollup, _) = Rollup::<0, 1>::with_state_registrations_and_transactions(
+            pp.clone(),
+            &[bob_pk],
+            &[],
+            &state,
+            true,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        let (mut rollup, _) = Rollup::<0, 1>::with_state_registrations_and_transactions(
+            pp.clone(),
+            &[bob_pk],
+            &[],
+            &state,
+            true,
+        )
+        .unwrap();
+        // Place the account at leaf 5 instead of leaf 1, while incrementing the counter.
+        let new_account: AccountInformation = AccountInformation {
+            public_key: bob_pk,
+            balance: Amount(0),
+            nonce: Nonce(0),
+        };
+        let tree = &mut state.account_merkle_tree;
+        tree.update(
+            NEXT_ACCOUNT_LEAF_INDEX,
+            &next_account_leaf(Some(AccountId(2))),
+        )
+        .unwrap();
+        tree.update(5, &new_account.to_bytes_le()).unwrap();
+        rollup.registration_witnesses.as_mut().unwrap()[0].2 = tree.generate_proof(5).unwrap();
+        rollup.post_registration_roots = Some(vec![tree.root()]);
+        rollup.final_root = Some(tree.root());
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn deposit_and_withdrawal_validity_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, Amount(10)).unwrap();
+
+        // Alice deposits funds and pays Bob, who withdraws some of his funds.
+        let ops = [
+            Operation::Deposit(Deposit {
+                account: alice_id,
+                amount: Amount(20),
+            }),
+            Operation::Withdrawal(Withdrawal::create(
+                &pp,
+                bob_id,
+                Amount(3),
+                Nonce(0),
+                &bob_sk,
+                &mut rng,
+            )),
+        ];
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, diff) =
+            Rollup::<1, 0, 2>::with_state_and_batch(pp.clone(), &[], &ops, &txs, &state, true)
+                .unwrap();
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(12));
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // The public commitment must match the operations.
+        let (mut rollup, _) =
+            Rollup::<1, 0, 2>::with_state_and_batch(pp.clone(), &[], &ops, &txs, &state, true)
+                .unwrap();
+        rollup.operations_commitment = Some(commit_to_operations(&pp, &ops[..1]));
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // The circuit applies exactly `NUM_OPS` deposits and withdrawals.
+        assert!(matches!(
+            Rollup::<1, 0, 2>::with_state_and_batch(pp.clone(), &[], &ops[..1], &txs, &state, true),
+            Err(Error::WrongOperationCount {
+                expected: 2,
+                got: 1
+            })
+        ));
+
+        // Only Bob can withdraw from his account.
+        let forged_ops = [
+            ops[0].clone(),
+            Operation::Withdrawal(Withdrawal::create(
+                &pp,
+                bob_id,
+                Amount(3),
+                Nonce(0),
+                &alice_sk,
+                &mut rng,
+            )),
+        ];
+        let (rollup, _) = Rollup::<1, 0, 2>::with_state_and_batch(
+            pp.clone(),
+            &[],
+            &forged_ops,
+            &txs,
+            &state,
+            false,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn short_batches_are_padded_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (collector_id, _c_pk, _c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(collector_id).unwrap();
+
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, diff) =
+            Rollup::<3>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        assert_eq!(rollup.transactions.as_ref().unwrap().len(), 3);
+        assert_eq!(rollup.final_root, Some(diff.root()));
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // An empty batch leaves the state unchanged.
+        let (rollup, _) =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &[], &state, true).unwrap();
+        assert_eq!(rollup.initial_root, rollup.final_root);
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // No-op transactions cannot change the state, for example to undo the real one.
+        let (mut rollup, _) =
+            Rollup::<3>::with_state_and_transactions(pp.clone(), &txs, &state, true).unwrap();
+        rollup.post_tx_roots.as_mut().unwrap()[1] = state.root();
+        rollup.pre_tx_roots.as_mut().unwrap()[2] = state.root();
+        rollup.post_tx_roots.as_mut().unwrap()[2] = state.root();
+        rollup.final_root = Some(state.root());
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        // Batches that pay fees are padded too.
+        let (rollup, _) =
+            RollupWithFee::<2>::with_state_and_transactions(pp.clone(), &txs, &state, true)
+                .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // Batches cannot be longer than `NUM_TX`, with or without fees.
+        let too_many = [txs[0].clone(), txs[0].clone()];
+        assert!(matches!(
+            Rollup::<1>::with_state_and_transactions(pp.clone(), &too_many, &state, true),
+            Err(Error::BatchTooLarge { max: 1, got: 2 })
+        ));
+        assert!(matches!(
+            RollupWithFee::<1>::with_state_and_transactions(pp.clone(), &too_many, &state, true),
+            Err(Error::BatchTooLarge { max: 1, got: 2 })
+        ));
+    }
+
+    #[test]
+    fn rollup_with_another_signature_scheme() {
+        type PoseidonSchnorr = Schnorr<EdwardsProjective, poseidon::RO<Fq>>;
+        type PoseidonSchnorrGadget = SchnorrSignatureVerifyGadget<
+            EdwardsProjective,
+            EdwardsVar,
+            poseidon::RO<Fq>,
+            poseidon::constraints::ROGadget,
+        >;
+        type PoseidonRollup = Rollup<1, 1, 1, PoseidonSchnorr, PoseidonSchnorrGadget>;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<PoseidonSchnorr>::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_pk, bob_sk) = PoseidonSchnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        let bob_id = AccountId(2);
+
+        // Bob registers, Alice withdraws some of her balance, and pays Bob.
+        let ops = [Operation::Withdrawal(Withdrawal::create(
+            &pp,
+            alice_id,
+            Amount(3),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        ))];
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        )];
+        let (rollup, diff) =
+            PoseidonRollup::with_state_and_batch(pp.clone(), &[bob_pk], &ops, &txs, &state, true)
+                .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(5));
+
+        // Signatures are checked with the Poseidon challenge in the circuit too.
+        let forged_txs = [Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(1),
+            &bob_sk,
+            &mut rng,
+        )];
+        let (rollup, _) = PoseidonRollup::with_state_and_batch(
+            pp.clone(),
+            &[bob_pk],
+            &ops,
+            &forged_txs,
+            &state,
+            false,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn rollup_with_a_poseidon_account_tree() {
+        use crate::ledger::{AccMerkleConfigGadget, DefaultSigVerifyGadget};
+        use ark_simple_payments::ledger::{
+            DefaultSignatureScheme, MerkleConfig, PoseidonMerkleConfig,
+        };
+
+        // Prove the same batch over an account tree configured by `M`, and return
+        // the number of constraints.
+        fn batch_constraints<M: AccMerkleConfigGadget>() -> usize {
+            let mut rng = ark_std::test_rng();
+            let pp = Parameters::<DefaultSignatureScheme, M>::sample(&mut rng);
+            let mut state = State::new(32, &pp);
+            let (alice_id, _alice_pk, alice_sk) =
+                state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, Amount(20)).unwrap();
+            let (bob_pk, _bob_sk) =
+                DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+            let bob_id = AccountId(2);
+
+            // Bob registers, Alice withdraws some of her balance, and pays Bob.
+            let ops = [Operation::Withdrawal(Withdrawal::create(
+                &pp,
+                alice_id,
+                Amount(3),
+                Nonce(0),
+                &alice_sk,
+                &mut rng,
+            ))];
+            let txs = [Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                Amount(5),
+                Nonce(1),
+                &alice_sk,
+                &mut rng,
+            )];
+            let batch = || {
+                Rollup::<1, 1, 1, DefaultSignatureScheme, DefaultSigVerifyGadget, M>::with_state_and_batch(
+                    pp.clone(),
+                    &[bob_pk],
+                    &ops,
+                    &txs,
+                    &state,
+                    true,
+                )
+                .unwrap()
+            };
+            let (rollup, diff) = batch();
+            assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(5));
+            let cs = ConstraintSystem::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            let num_constraints = cs.num_constraints();
+
+            // The circuit rejects a batch that claims to leave the ledger unchanged.
+            let (mut rollup, _) = batch();
+            rollup.final_root = rollup.initial_root;
+            let cs = ConstraintSystem::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            assert!(!cs.is_satisfied().unwrap());
+            num_constraints
+        }
+
+        let pedersen = batch_constraints::<MerkleConfig>();
+        let poseidon = batch_constraints::<PoseidonMerkleConfig>();
+        assert!(poseidon < pedersen);
+    }
+
+    #[test]
+    fn rollup_with_threshold_accounts() {
+        type PoseidonSchnorr = Schnorr<EdwardsProjective, poseidon::RO<Fq>>;
+        type PoseidonSchnorrGadget = SchnorrSignatureVerifyGadget<
+            EdwardsProjective,
+            EdwardsVar,
+            poseidon::RO<Fq>,
+            poseidon::constraints::ROGadget,
+        >;
+        type TwoOfThree = Threshold<PoseidonSchnorr, Fq, 3>;
+        type TwoOfThreeGadget =
+            ThresholdSignatureVerifyGadget<PoseidonSchnorr, PoseidonSchnorrGadget, Fq, 3>;
+        type TwoOfThreeRollup = Rollup<1, 1, 0, TwoOfThree, TwoOfThreeGadget>;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<TwoOfThree>::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (treasury_pk, treasury_sk) = TwoOfThree::keygen(&pp.sig_params, &mut rng).unwrap();
+        let treasury_pk = threshold::PublicKey::new(2, treasury_pk.keys).unwrap();
+        let treasury_id = state.register(treasury_pk).unwrap();
+        state.update_balance(treasury_id, Amount(20)).unwrap();
+        let (bob_pk, _bob_sk) = TwoOfThree::keygen(&pp.sig_params, &mut rng).unwrap();
+        let bob_id = AccountId(2);
+
+        // Each officer of the treasury signs with their own key.
+        let partial_txs = (0..3)
+            .map(|i| {
+                let mut officer_sk = threshold::SecretKey::default();
+                officer_sk.keys[i] = treasury_sk.keys[i].clone();
+                Transaction::create(
+                    &pp,
+                    treasury_id,
+                    bob_id,
+                    Amount(5),
+                    Nonce(0),
+                    &officer_sk,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Bob registers, and two officers of the treasury pay him.
+        let mut tx = partial_txs[1].clone();
+        tx.signature = threshold::Signature::combine(&[
+            partial_txs[1].signature.clone(),
+            partial_txs[2].signature.clone(),
+        ]);
+        let (rollup, diff) = TwoOfThreeRollup::with_state_and_batch(
+            pp.clone(),
+            std::slice::from_ref(&bob_pk),
+            &[],
+            &[tx],
+            &state,
+            true,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(diff.account_info(&bob_id).unwrap().balance, Amount(5));
+
+        // A single officer cannot pay him.
+        let (rollup, _) = TwoOfThreeRollup::with_state_and_batch(
+            pp.clone(),
+            &[bob_pk],
+            &[],
+            &partial_txs[..1],
+            &state,
+            false,
+        )
+        .unwrap();
+        let cs = ConstraintSystem::new_ref();
+        rollup.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
     }
 }
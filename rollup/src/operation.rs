@@ -0,0 +1,173 @@
+use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar, NonceVar};
+use crate::ledger::DefaultSigVerifyGadget;
+use crate::ledger::{
+    self, AccIndexedPathVar, AccMerkleConfigGadget, AccRootVar, AmountVar, MerkleUpdateGadget,
+};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::constraints::CRHGadget;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::account::Nonce;
+use ark_simple_payments::ledger::DefaultSignatureScheme;
+use ark_simple_payments::message::MessageKind;
+use ark_simple_payments::operation::Operation;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use std::borrow::Borrow;
+
+/// A commitment to a list of operations.
+pub type OperationsCommitmentVar = FpVar<ConstraintF>;
+
+/// A deposit or a withdrawal. Both kinds are represented by the same variables,
+/// so that a circuit can handle either kind in each of its operation slots.
+pub struct OperationVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+> {
+    /// Is this a withdrawal, rather than a deposit?
+    pub is_withdrawal: Boolean<ConstraintF>,
+    /// The account that the operation credits or debits.
+    pub account: AccountIdVar,
+    /// The amount that the operation moves.
+    pub amount: AmountVar,
+    /// For withdrawals, the account's nonce at the time the withdrawal was created.
+    pub nonce: NonceVar,
+    /// For withdrawals, a signature over the account, the amount and the nonce.
+    pub signature: SG::SignatureVar,
+}
+
+impl<S: SignatureScheme, SG: SigVerifyGadget<S, ConstraintF>> OperationVar<S, SG> {
+    /// Convert the operation to the bytes that are committed to.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        // (Kind || AccId || Amount), where the kind is 0 for deposits and 1 for withdrawals.
+        let mut kind_bits = vec![self.is_withdrawal.clone()];
+        kind_bits.resize(8, Boolean::FALSE);
+        let mut bytes = vec![UInt8::from_bits_le(&kind_bits)];
+        bytes.extend(self.account.to_bytes_le());
+        bytes.extend(self.amount.to_bytes_le());
+        bytes
+    }
+
+    /// Append the operation to the hash chain ending in `commitment`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, commitment))]
+    pub fn commit<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        commitment: &OperationsCommitmentVar,
+    ) -> Result<OperationsCommitmentVar, SynthesisError> {
+        let mut input = commitment.to_bytes()?;
+        input.extend(self.to_bytes_le());
+        M::LeafHashGadget::evaluate(&parameters.leaf_crh_params, &input)
+    }
+
+    /// Verify just the signature of a withdrawal.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pub_key))]
+    pub fn verify_signature<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        pub_key: &AccountPublicKeyVar<S, SG>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // The authorized message consists of
+        // (Version || Kind || DomainTag || AccId || Amount || Nonce).
+        let mut message =
+            UInt8::constant_vec(&MessageKind::Withdrawal.prefix(&parameters.domain_tag));
+        message.extend(self.account.to_bytes_le());
+        message.extend(self.amount.to_bytes_le());
+        message.extend(self.nonce.to_bytes_le());
+        SG::verify(&parameters.sig_params, pub_key, &message, &self.signature)
+    }
+
+    /// Check that the operation is valid for the given ledger state, and that
+    /// applying it moves the ledger from `pre_root` to `post_root`. This checks
+    /// that `pre_acc_info` is stored at the leaf of `self.account`, and that
+    /// 1. deposits credit the account, without overflowing;
+    /// 2. withdrawals are signed by the account's public key, carry the account's
+    ///    nonce, which is incremented, and debit the account, without underflowing.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, pre_acc_info, path, pre_root, post_root)
+    )]
+    pub fn validate<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        pre_acc_info: &AccountInformationVar<S, SG>,
+        path: &AccIndexedPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // 1) Withdrawals must be authorized by the account owner, and carry its nonce.
+        let sig_ok = self.verify_signature(parameters, &pre_acc_info.public_key)?;
+        let nonce_ok = self.nonce.is_eq(&pre_acc_info.nonce)?;
+        let authorized = self.is_withdrawal.not().or(&sig_ok.and(&nonce_ok)?)?;
+
+        // 2) Credit deposits, and debit withdrawals. Only one of the two is non-zero,
+        // so that the checked arithmetic does not constrain the other kind.
+        let zero = AmountVar(UInt64::constant(0));
+        let credit = AmountVar(self.is_withdrawal.select(&zero.0, &self.amount.0)?);
+        let debit = AmountVar(self.is_withdrawal.select(&self.amount.0, &zero.0)?);
+        let mut post_acc_info = pre_acc_info.clone();
+        post_acc_info.balance = pre_acc_info
+            .balance
+            .checked_add(&credit)?
+            .checked_sub(&debit)?;
+        let incremented_nonce = pre_acc_info.nonce.checked_increment()?;
+        post_acc_info.nonce = NonceVar(
+            self.is_withdrawal
+                .select(&incremented_nonce.0, &pre_acc_info.nonce.0)?,
+        );
+
+        // 3) The account is updated at its own leaf.
+        let index = self.account.to_bits_le();
+        let (account_exists, new_root) = MerkleUpdateGadget::update(
+            parameters,
+            path,
+            &index,
+            &pre_acc_info.to_bytes_le(),
+            &post_acc_info.to_bytes_le(),
+            pre_root,
+        )?;
+        let account_updated = new_root.is_eq(post_root)?;
+
+        account_exists.and(&account_updated)?.and(&authorized)
+    }
+}
+
+impl<S, SG> AllocVar<Operation<S>, ConstraintF> for OperationVar<S, SG>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+{
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Operation<S>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|operation| {
+            let operation: &Operation<S> = operation.borrow();
+            // Deposits have no nonce or signature, so these are filled with dummy values.
+            let (nonce, signature) = match operation {
+                Operation::Deposit(_) => (Nonce::default(), S::Signature::default()),
+                Operation::Withdrawal(withdrawal) => {
+                    (withdrawal.nonce, withdrawal.signature.clone())
+                }
+            };
+            let is_withdrawal =
+                Boolean::new_variable(cs.clone(), || Ok(operation.is_withdrawal()), mode)?;
+            let account = AccountIdVar::new_variable(cs.clone(), || Ok(operation.account()), mode)?;
+            let amount = AmountVar::new_variable(cs.clone(), || Ok(operation.amount()), mode)?;
+            let nonce = NonceVar::new_variable(cs.clone(), || Ok(nonce), mode)?;
+            let signature = SG::SignatureVar::new_variable(cs, || Ok(signature), mode)?;
+            Ok(Self {
+                is_withdrawal,
+                account,
+                amount,
+                nonce,
+                signature,
+            })
+        })
+    }
+}
@@ -0,0 +1,409 @@
+use crate::backend::SnarkBackend;
+use crate::ledger::{AccMerkleConfigGadget, DefaultSigVerifyGadget};
+use crate::rollup::Rollup;
+use crate::ConstraintF;
+use ark_ff::ToConstraintField;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments::{
+    account::{AccountPublicKey, Nonce},
+    ledger::{AccRoot, Amount, DefaultSignatureScheme, MerkleConfig, Parameters, State},
+    operation::{self, Deposit, Operation, OperationsCommitment},
+    signature::{SigVerifyGadget, SignatureScheme},
+    transaction::Transaction,
+    Error,
+};
+use ark_std::rand::{CryptoRng, RngCore};
+use derivative::Derivative;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A proof, produced by the zkSNARK `B`, that a batch of registrations, deposits,
+/// withdrawals and transactions moves the ledger from an initial root to a final root.
+pub type Proof<B> = <B as SnarkBackend>::Proof;
+
+/// The key used to prove batches of `NUM_REG` registrations, `NUM_OPS` deposits
+/// and withdrawals, and `NUM_TX` transactions with the zkSNARK `B`, for a ledger
+/// whose accounts use the signature scheme `S`, and whose account tree is
+/// configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct ProvingKey<
+    B: SnarkBackend,
+    const NUM_TX: usize,
+    const NUM_REG: usize = 0,
+    const NUM_OPS: usize = 0,
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The ledger parameters that are hard-coded in the circuit.
+    pub ledger_params: Parameters<S, M>,
+    /// The height of the account Merkle tree that the circuit was set up for.
+    pub tree_height: usize,
+    /// The underlying zkSNARK proving key.
+    pub snark_pk: B::ProvingKey,
+    #[doc(hidden)]
+    _signature_gadget: PhantomData<SG>,
+}
+
+/// The key used to verify proofs for batches of `NUM_REG` registrations, `NUM_OPS`
+/// deposits and withdrawals, and `NUM_TX` transactions with the zkSNARK `B`, for a
+/// ledger whose accounts use the signature scheme `S`, and whose account tree is
+/// configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct VerifyingKey<
+    B: SnarkBackend,
+    const NUM_TX: usize,
+    const NUM_REG: usize = 0,
+    const NUM_OPS: usize = 0,
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The ledger parameters, which are needed to commit to the operations in a batch.
+    pub ledger_params: Parameters<S, M>,
+    /// The underlying zkSNARK verifying key.
+    pub snark_vk: B::VerifyingKey,
+    /// The underlying zkSNARK verifying key, prepared for faster verification.
+    pub snark_pvk: B::ProcessedVerifyingKey,
+    #[doc(hidden)]
+    _signature_gadget: PhantomData<SG>,
+}
+
+/// Errors that can occur when proving a batch.
+#[derive(Debug)]
+pub enum ProveError {
+    /// The ledger rejected the batch, for the given reason.
+    Rejected(Error),
+    /// The zkSNARK failed to prove the batch.
+    Synthesis(SynthesisError),
+    /// The proving key was set up for an account tree of height `expected`, but the
+    /// ledger's tree has height `actual`.
+    TreeHeightMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for ProveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProveError::Rejected(e) => write!(f, "batch rejected: {}", e),
+            ProveError::Synthesis(e) => write!(f, "proving failed: {}", e),
+            ProveError::TreeHeightMismatch { expected, actual } => write!(
+                f,
+                "the proving key was set up for a tree of height {}, not {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProveError {}
+
+impl From<Error> for ProveError {
+    fn from(e: Error) -> Self {
+        ProveError::Rejected(e)
+    }
+}
+
+impl From<SynthesisError> for ProveError {
+    fn from(e: SynthesisError) -> Self {
+        ProveError::Synthesis(e)
+    }
+}
+
+/// Convert a ledger root into the field elements that represent it as a public input.
+pub fn root_to_field_elements(root: &AccRoot) -> Vec<ConstraintF> {
+    root.to_field_elements().unwrap()
+}
+
+/// Compute the public input of `Rollup`, in the order in which
+/// `Rollup::generate_constraints` allocates it.
+pub fn public_inputs(
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    operations_commitment: &OperationsCommitment,
+) -> Vec<ConstraintF> {
+    let mut inputs = root_to_field_elements(initial_root);
+    inputs.extend(root_to_field_elements(final_root));
+    inputs.extend(operations_commitment.to_field_elements().unwrap());
+    inputs
+}
+
+/// Sample proving and verifying keys for batches of `NUM_REG` registrations,
+/// `NUM_OPS` deposits and withdrawals, and `NUM_TX` transactions over a ledger
+/// created via `State::new(num_accounts, ledger_params)`, using the zkSNARK `B`.
+#[allow(clippy::type_complexity)]
+pub fn setup<
+    B: SnarkBackend,
+    R: RngCore + CryptoRng,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+>(
+    ledger_params: &Parameters,
+    num_accounts: usize,
+    rng: &mut R,
+) -> Result<
+    (
+        ProvingKey<B, NUM_TX, NUM_REG, NUM_OPS>,
+        VerifyingKey<B, NUM_TX, NUM_REG, NUM_OPS>,
+    ),
+    SynthesisError,
+> {
+    setup_with_signature_scheme(ledger_params, num_accounts, rng)
+}
+
+/// Like `setup`, but for a ledger whose accounts use the signature scheme `S`,
+/// which the circuit verifies with `SG`, and whose account tree is configured by `M`.
+#[allow(clippy::type_complexity)]
+pub fn setup_with_signature_scheme<
+    B: SnarkBackend,
+    R: RngCore + CryptoRng,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+>(
+    ledger_params: &Parameters<S, M>,
+    num_accounts: usize,
+    rng: &mut R,
+) -> Result<
+    (
+        ProvingKey<B, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>,
+        VerifyingKey<B, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>,
+    ),
+    SynthesisError,
+> {
+    let mut state = State::new(num_accounts, ledger_params);
+    let tree_height = state.account_merkle_tree.height();
+    let circuit =
+        sample_circuit::<_, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>(ledger_params, &mut state, rng);
+    let (pk, vk) = B::setup(circuit, rng)?;
+    let pvk = B::process_vk(&vk)?;
+    Ok((
+        ProvingKey {
+            ledger_params: ledger_params.clone(),
+            tree_height,
+            snark_pk: pk,
+            _signature_gadget: PhantomData,
+        },
+        VerifyingKey {
+            ledger_params: ledger_params.clone(),
+            snark_vk: vk,
+            snark_pvk: pvk,
+            _signature_gadget: PhantomData,
+        },
+    ))
+}
+
+/// Construct a circuit with the same shape as any batch of `NUM_REG` registrations,
+/// `NUM_OPS` deposits and withdrawals, and `NUM_TX` transactions over `state`. The
+/// batch registers accounts for random keys, and makes zero-value deposits into,
+/// and zero-value transfers from, a freshly registered account.
+fn sample_circuit<
+    R: RngCore + CryptoRng,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+>(
+    ledger_params: &Parameters<S, M>,
+    state: &mut State<S, M>,
+    rng: &mut R,
+) -> Rollup<NUM_TX, NUM_REG, NUM_OPS, S, SG, M> {
+    let (id, _pk, sk) = state
+        .sample_keys_and_register(ledger_params, rng)
+        .expect("ledger should have space for an account");
+    let transactions = (0..NUM_TX as u64)
+        .map(|i| Transaction::create(ledger_params, id, id, Amount(0), Nonce(i), &sk, rng))
+        .collect::<Vec<_>>();
+    let registrations = (0..NUM_REG)
+        .map(|_| S::keygen(&ledger_params.sig_params, rng).unwrap().0)
+        .collect::<Vec<_>>();
+    // Deposits and withdrawals have the same shape in-circuit.
+    let operations = (0..NUM_OPS)
+        .map(|_| {
+            Operation::Deposit(Deposit {
+                account: id,
+                amount: Amount(0),
+            })
+        })
+        .collect::<Vec<_>>();
+    Rollup::with_state_and_batch(
+        ledger_params.clone(),
+        &registrations,
+        &operations,
+        &transactions,
+        state,
+        false,
+    )
+    .expect("ledger should have space for the registrations")
+    .0
+}
+
+/// Register an account for each of `registrations` in `state`, then apply
+/// `operations` and `transactions`, and prove that doing so moves the ledger
+/// from its previous root to its new root. `state` is only updated once the
+/// proof is produced.
+/// Returns `ProveError::Rejected`, with the reason, if a registration fails or
+/// if any of the operations or transactions is invalid, and
+/// `ProveError::TreeHeightMismatch` if `pk` was set up for another tree height.
+pub fn prove_batch<
+    B: SnarkBackend,
+    R: RngCore + CryptoRng,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+>(
+    pk: &ProvingKey<B, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>,
+    state: &mut State<S, M>,
+    registrations: &[AccountPublicKey<S>],
+    operations: &[Operation<S>],
+    transactions: &[Transaction<S>],
+    rng: &mut R,
+) -> Result<Proof<B>, ProveError> {
+    let tree_height = state.account_merkle_tree.height();
+    if tree_height != pk.tree_height {
+        return Err(ProveError::TreeHeightMismatch {
+            expected: pk.tree_height,
+            actual: tree_height,
+        });
+    }
+    let (circuit, diff) = Rollup::<NUM_TX, NUM_REG, NUM_OPS, S, SG, M>::with_state_and_batch(
+        pk.ledger_params.clone(),
+        registrations,
+        operations,
+        transactions,
+        state,
+        true,
+    )?;
+    let proof = B::prove(&pk.snark_pk, circuit, rng)?;
+    state.commit(diff)?;
+    Ok(proof)
+}
+
+/// Check that `proof` shows that a batch of `NUM_REG` registrations, `NUM_TX`
+/// transactions, and exactly the deposits and withdrawals in `operations`, moves
+/// the ledger from `initial_root` to `final_root`.
+pub fn verify_batch<
+    B: SnarkBackend,
+    const NUM_TX: usize,
+    const NUM_REG: usize,
+    const NUM_OPS: usize,
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+>(
+    vk: &VerifyingKey<B, NUM_TX, NUM_REG, NUM_OPS, S, SG, M>,
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    operations: &[Operation<S>],
+    proof: &Proof<B>,
+) -> Result<bool, SynthesisError> {
+    let commitment = operation::commit_to_operations(&vk.ledger_params, operations);
+    let inputs = public_inputs(initial_root, final_root, &commitment);
+    B::verify(&vk.snark_pvk, &inputs, proof)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::{GM17Backend, Groth16Backend};
+    use ark_simple_payments::curve::EdwardsProjective;
+    use ark_simple_payments::signature::schnorr;
+
+    fn prove_and_verify_batch<B: SnarkBackend>() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let (pk, vk) = setup::<B, _, 1, 1, 1>(&pp, 32, &mut rng).unwrap();
+
+        // Alice funds her account with a deposit. Carol is registered in the batch,
+        // and is paid by Alice in the same batch.
+        let ops = [Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(20),
+        })];
+        let (carol_pk, _carol_sk) =
+            schnorr::Schnorr::<EdwardsProjective>::keygen(&pp.sig_params, &mut rng).unwrap();
+        let carol_id = state.next_available_account.unwrap();
+        let txs = [Transaction::create(
+            &pp,
+            alice_id,
+            carol_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        )];
+        let initial_root = state.root();
+        let proof = prove_batch(&pk, &mut state, &[carol_pk], &ops, &txs, &mut rng).unwrap();
+        let final_root = state.root();
+        assert_eq!(state.pub_key_to_id[&carol_pk], carol_id);
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(15));
+        assert_eq!(state.id_to_account_info[&carol_id].balance, Amount(5));
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(0));
+
+        assert!(verify_batch(&vk, &initial_root, &final_root, &ops, &proof).unwrap());
+        // The proof does not verify against other roots.
+        assert!(!verify_batch(&vk, &initial_root, &initial_root, &ops, &proof).unwrap());
+        assert!(!verify_batch(&vk, &final_root, &initial_root, &ops, &proof).unwrap());
+        // Nor against other deposits.
+        let other_ops = [Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(21),
+        })];
+        assert!(!verify_batch(&vk, &initial_root, &final_root, &other_ops, &proof).unwrap());
+
+        // Invalid batches cannot be proven.
+        let (dave_pk, _dave_sk) =
+            schnorr::Schnorr::<EdwardsProjective>::keygen(&pp.sig_params, &mut rng).unwrap();
+        assert!(matches!(
+            prove_batch(&pk, &mut state, &[dave_pk], &ops, &txs, &mut rng),
+            Err(ProveError::Rejected(Error::BadNonce))
+        ));
+        assert!(matches!(
+            prove_batch(&pk, &mut state, &[carol_pk], &ops, &[], &mut rng),
+            Err(ProveError::Rejected(Error::DuplicatePublicKey))
+        ));
+        let too_many = [txs[0].clone(), txs[0].clone()];
+        assert!(matches!(
+            prove_batch(&pk, &mut state, &[dave_pk], &ops, &too_many, &mut rng),
+            Err(ProveError::Rejected(Error::BatchTooLarge {
+                max: 1,
+                got: 2
+            }))
+        ));
+        // Dave's registration was not kept.
+        assert_eq!(state.root(), final_root);
+
+        // The proving key only fits ledgers with the tree height it was set up for.
+        let mut taller_state = State::new(64, &pp);
+        assert!(matches!(
+            prove_batch(&pk, &mut taller_state, &[], &[], &[], &mut rng),
+            Err(ProveError::TreeHeightMismatch { .. })
+        ));
+        assert_eq!(taller_state.root(), State::new(64, &pp).root());
+    }
+
+    #[test]
+    fn groth16_prove_and_verify_batch() {
+        prove_and_verify_batch::<Groth16Backend>();
+    }
+
+    #[test]
+    fn gm17_prove_and_verify_batch() {
+        prove_and_verify_batch::<GM17Backend>();
+    }
+}
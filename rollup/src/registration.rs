@@ -0,0 +1,101 @@
+use crate::account::{
+    AccountIdReprVar, AccountIdVar, AccountInformationVar, AccountPublicKeyVar, NonceVar,
+};
+use crate::ledger::{
+    AccIndexedPathVar, AccMerkleConfigGadget, AccRootVar, AmountVar, DefaultSigVerifyGadget,
+    MerkleUpdateGadget, ParametersVar,
+};
+use crate::ConstraintF;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::account::AccountPublicKey;
+use ark_simple_payments::ledger::DefaultSignatureScheme;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use std::borrow::Borrow;
+
+/// The registration of a new account with public key `public_key`.
+pub struct RegistrationVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+> {
+    /// The public key of the new account.
+    pub public_key: AccountPublicKeyVar<S, SG>,
+}
+
+impl<S: SignatureScheme, SG: SigVerifyGadget<S, ConstraintF>> RegistrationVar<S, SG> {
+    /// Check that registering the account as `id` moves the ledger from `pre_root`
+    /// to `post_root`, like `State::register` does natively.
+    /// Checks:
+    ///   - the account counter in `pre_root` is `id`, which is not 0,
+    ///   - the counter is incremented, which requires `id` to not be the largest identifier,
+    ///   - the leaf of `id` is empty once the counter is incremented,
+    ///   - the leaf of `id` holds `(public_key, 0, 0)` in `post_root`.
+    ///
+    /// `counter_path` is the path to the counter leaf *before* the registration, and
+    /// `account_path` is the path to the leaf of `id` *after* the registration.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, parameters, id, counter_path, account_path, pre_root, post_root)
+    )]
+    pub fn validate<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ParametersVar<S, SG, M>,
+        id: &AccountIdVar,
+        counter_path: &AccIndexedPathVar,
+        account_path: &AccIndexedPathVar,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // 1) The counter holds `id`, and there are identifiers left.
+        let id_ok = id.is_neq(&AccountIdVar(AccountIdReprVar::constant(0)))?;
+
+        // 2) Increment the counter.
+        let next_id = id.checked_increment()?;
+        let (counter_ok, mid_root) = MerkleUpdateGadget::update(
+            parameters,
+            counter_path,
+            &[],
+            &id.to_bytes_le(),
+            &next_id.to_bytes_le(),
+            pre_root,
+        )?;
+
+        // 3) The leaf of `id` moves from empty to the new account.
+        let new_account = AccountInformationVar::<S, SG> {
+            public_key: self.public_key.clone(),
+            balance: AmountVar(UInt64::constant(0)),
+            nonce: NonceVar(UInt64::constant(0)),
+        };
+        let new_leaf = new_account.to_bytes_le();
+        // Empty leaves are all-zero, and the leaf hash pads its input with zeros.
+        let empty_leaf = vec![UInt8::constant(0); new_leaf.len()];
+        let index = id.to_bits_le();
+        let (was_empty, new_root) = MerkleUpdateGadget::update(
+            parameters,
+            account_path,
+            &index,
+            &empty_leaf,
+            &new_leaf,
+            &mid_root,
+        )?;
+        let registered = new_root.is_eq(post_root)?;
+
+        counter_ok.and(&id_ok)?.and(&was_empty)?.and(&registered)
+    }
+}
+
+impl<S, SG> AllocVar<AccountPublicKey<S>, ConstraintF> for RegistrationVar<S, SG>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+{
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AccountPublicKey<S>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        SG::PublicKeyVar::new_variable(cs, f, mode).map(|public_key| Self { public_key })
+    }
+}
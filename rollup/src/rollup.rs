@@ -1,62 +1,114 @@
-use crate::account::AccountInformationVar;
+use crate::account::{AccountIdVar, AccountInformationVar};
 use crate::ledger::*;
+use crate::operation::{OperationVar, OperationsCommitmentVar};
+use crate::registration::RegistrationVar;
 use crate::transaction::TransactionVar;
 use crate::ConstraintF;
+use ark_ff::Zero;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
 use ark_simple_payments::{
-    account::AccountInformation,
-    ledger::{AccPath, AccRoot, Parameters, State},
+    account::{AccountId, AccountInformation, AccountPublicKey, Nonce},
+    ledger::{
+        AccMerkleConfig, AccPath, AccRoot, Amount, DefaultSignatureScheme, MerkleConfig,
+        Parameters, State, NEXT_ACCOUNT_LEAF_INDEX,
+    },
+    operation::{self, Operation, OperationsCommitment},
+    overlay::{StateDiff, StateOverlay},
+    signature::{SigVerifyGadget, SignatureScheme},
     transaction::Transaction,
+    Error,
 };
-
-pub struct Rollup<const NUM_TX: usize> {
+use std::marker::PhantomData;
+
+/// A batch that registers `NUM_REG` new accounts, then applies `NUM_OPS` deposits
+/// and withdrawals, and then applies `NUM_TX` transactions. Transactions and
+/// withdrawals are signed with the scheme `S`, and the circuit verifies their
+/// signatures with the gadget `SG`. The account tree hashes as configured by `M`.
+pub struct Rollup<
+    const NUM_TX: usize,
+    const NUM_REG: usize = 0,
+    const NUM_OPS: usize = 0,
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
     /// The ledger parameters.
-    pub ledger_params: Parameters,
+    pub ledger_params: Parameters<S, M>,
     /// The Merkle tree root before applying this batch of transactions.
     pub initial_root: Option<AccRoot>,
     /// The Merkle tree root after applying this batch of transactions.
     pub final_root: Option<AccRoot>,
+    /// The commitment to the deposits and withdrawals in this batch, as computed by
+    /// `operation::commit_to_operations`.
+    pub operations_commitment: Option<OperationsCommitment>,
+    /// The public keys of the accounts that are registered in this batch.
+    pub registrations: Option<Vec<AccountPublicKey<S>>>,
+    /// For each registration, the identifier of the new account, the authentication
+    /// path of the account counter *before* the registration, and the authentication
+    /// path of the new account *after* the registration.
+    #[allow(clippy::type_complexity)]
+    pub registration_witnesses: Option<Vec<(AccountId, AccPath<M>, AccPath<M>)>>,
+    /// List of state roots, so that the i-th root is the state root after the
+    /// i-th registration.
+    pub post_registration_roots: Option<Vec<AccRoot>>,
+    /// The deposits and withdrawals in this batch.
+    pub operations: Option<Vec<Operation<S>>>,
+    /// The account information and authentication path of the account that each
+    /// operation credits or debits, *before* applying the operation.
+    pub operation_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
+    /// List of state roots, so that the i-th root is the state root after the
+    /// i-th operation.
+    pub post_operation_roots: Option<Vec<AccRoot>>,
     /// The current batch of transactions.
-    pub transactions: Option<Vec<Transaction>>,
+    pub transactions: Option<Vec<Transaction<S>>>,
     /// The sender's account information and corresponding authentication path,
-    /// *before* applying the transactions.
-    pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
-    /// The authentication path corresponding to the sender's account information
-    /// *after* applying the transactions.
-    pub sender_post_paths: Option<Vec<AccPath>>,
+    /// *before* applying each transaction.
+    pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
     /// The recipient's account information and corresponding authentication path,
-    /// *before* applying the transactions.
-    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
-    /// The authentication path corresponding to the recipient's account information
-    /// *after* applying the transactions.
-    pub recv_post_paths: Option<Vec<AccPath>>,
+    /// once the sender of each transaction is debited, but *before* the recipient
+    /// is credited.
+    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
     /// List of state roots, so that the i-th root is the state roots before applying
-    /// the i-th transaction. This means that `pre_tx_roots[0] == initial_root`.
+    /// the i-th transaction. Without registrations and operations, this means that
+    /// `pre_tx_roots[0] == initial_root`.
     pub pre_tx_roots: Option<Vec<AccRoot>>,
     /// List of state roots, so that the i-th root is the state root after applying
     /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`.
     pub post_tx_roots: Option<Vec<AccRoot>>,
+    _signature_gadget: PhantomData<SG>,
 }
 
-impl<const NUM_TX: usize> Rollup<NUM_TX> {
-    pub fn new_empty(ledger_params: Parameters) -> Self {
+impl<const NUM_TX: usize, const NUM_REG: usize, const NUM_OPS: usize, S, SG, M>
+    Rollup<NUM_TX, NUM_REG, NUM_OPS, S, SG, M>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+{
+    pub fn new_empty(ledger_params: Parameters<S, M>) -> Self {
         Self {
             ledger_params,
             initial_root: None,
             final_root: None,
+            operations_commitment: None,
+            registrations: None,
+            registration_witnesses: None,
+            post_registration_roots: None,
+            operations: None,
+            operation_info_and_paths: None,
+            post_operation_roots: None,
             transactions: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
             recv_pre_tx_info_and_paths: None,
-            recv_post_paths: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            _signature_gadget: PhantomData,
         }
     }
 
     pub fn only_initial_and_final_roots(
-        ledger_params: Parameters,
+        ledger_params: Parameters<S, M>,
         initial_root: AccRoot,
         final_root: AccRoot,
     ) -> Self {
@@ -64,121 +116,375 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
             ledger_params,
             initial_root: Some(initial_root),
             final_root: Some(final_root),
+            operations_commitment: None,
+            registrations: None,
+            registration_witnesses: None,
+            post_registration_roots: None,
+            operations: None,
+            operation_info_and_paths: None,
+            post_operation_roots: None,
             transactions: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
             recv_pre_tx_info_and_paths: None,
-            recv_post_paths: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            _signature_gadget: PhantomData,
         }
     }
 
     pub fn with_state_and_transactions(
-        ledger_params: Parameters,
-        transactions: &[Transaction],
-        state: &mut State,
+        ledger_params: Parameters<S, M>,
+        transactions: &[Transaction<S>],
+        state: &State<S, M>,
+        validate_transactions: bool,
+    ) -> Result<(Self, StateDiff<S, M>), Error> {
+        Self::with_state_registrations_and_transactions(
+            ledger_params,
+            &[],
+            transactions,
+            state,
+            validate_transactions,
+        )
+    }
+
+    pub fn with_state_registrations_and_transactions(
+        ledger_params: Parameters<S, M>,
+        registrations: &[AccountPublicKey<S>],
+        transactions: &[Transaction<S>],
+        state: &State<S, M>,
         validate_transactions: bool,
-    ) -> Option<Self> {
-        assert_eq!(transactions.len(), NUM_TX);
+    ) -> Result<(Self, StateDiff<S, M>), Error> {
+        Self::with_state_and_batch(
+            ledger_params,
+            registrations,
+            &[],
+            transactions,
+            state,
+            validate_transactions,
+        )
+    }
+
+    /// Register an account for each of `registrations` in `state`, then apply
+    /// `operations` and `transactions`, and record the witnesses that show that
+    /// this is what happened. If there are fewer than `NUM_TX` transactions, the
+    /// batch is padded with no-op transactions.
+    /// The batch is applied to an overlay of `state`, which is left untouched. The
+    /// changes are returned alongside the witnesses, and are applied by passing them
+    /// to `State::commit`, for example once the batch is proven.
+    /// Returns `Error::WrongRegistrationCount` if there are not exactly `NUM_REG`
+    /// registrations, `Error::WrongOperationCount` if there are not exactly `NUM_OPS`
+    /// deposits and withdrawals, `Error::BatchTooLarge` if there are more than
+    /// `NUM_TX` transactions, `Error::LedgerFull` if a registration fails,
+    /// `Error::UnknownAccount` if an operation refers to an account that does not
+    /// exist, and the reason for which an operation or a transaction is invalid if
+    /// `validate_transactions` is set.
+    pub fn with_state_and_batch(
+        ledger_params: Parameters<S, M>,
+        registrations: &[AccountPublicKey<S>],
+        operations: &[Operation<S>],
+        transactions: &[Transaction<S>],
+        state: &State<S, M>,
+        validate_transactions: bool,
+    ) -> Result<(Self, StateDiff<S, M>), Error> {
+        if registrations.len() != NUM_REG {
+            return Err(Error::WrongRegistrationCount {
+                expected: NUM_REG,
+                got: registrations.len(),
+            });
+        }
+        if operations.len() != NUM_OPS {
+            return Err(Error::WrongOperationCount {
+                expected: NUM_OPS,
+                got: operations.len(),
+            });
+        }
+        if transactions.len() > NUM_TX {
+            return Err(Error::BatchTooLarge {
+                max: NUM_TX,
+                got: transactions.len(),
+            });
+        }
+        let mut transactions = transactions.to_vec();
+        transactions.resize(NUM_TX, Transaction::noop());
         let initial_root = Some(state.root());
+        let mut state = state.overlay();
+
+        let mut registration_witnesses = Vec::with_capacity(NUM_REG);
+        let mut post_registration_roots = Vec::with_capacity(NUM_REG);
+        for public_key in registrations {
+            let counter_path = state
+                .account_merkle_tree()
+                .generate_proof(NEXT_ACCOUNT_LEAF_INDEX)
+                .unwrap();
+            let id = state.register(public_key.clone())?;
+            // The path of the new account does not depend on its own leaf, so it
+            // is also valid for the tree in which only the counter is updated.
+            let index = id
+                .leaf_index()
+                .expect("registered accounts fit in the tree");
+            let account_path = state.account_merkle_tree().generate_proof(index).unwrap();
+            registration_witnesses.push((id, counter_path, account_path));
+            post_registration_roots.push(state.root());
+        }
+
+        let mut operation_info_and_paths = Vec::with_capacity(NUM_OPS);
+        let mut post_operation_roots = Vec::with_capacity(NUM_OPS);
+        for op in operations {
+            let id = op.account();
+            let pre_acc_info = state
+                .account_info(&id)
+                .ok_or(Error::UnknownAccount(id))?
+                .clone();
+            let path = state
+                .account_merkle_tree()
+                .generate_proof(id.leaf_index().ok_or(Error::UnknownAccount(id))?)
+                .unwrap();
+            if validate_transactions {
+                state.apply_operation(&ledger_params, op)?;
+            } else {
+                let _ = state.apply_operation(&ledger_params, op);
+            }
+            operation_info_and_paths.push((pre_acc_info, path));
+            post_operation_roots.push(state.root());
+        }
+
         let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
         let mut recipient_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
-        let mut sender_post_paths = Vec::with_capacity(NUM_TX);
-        let mut recipient_post_paths = Vec::with_capacity(NUM_TX);
         let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
         let mut post_tx_roots = Vec::with_capacity(NUM_TX);
 
-        for tx in transactions {
-            if !tx.validate(&ledger_params, &*state) && validate_transactions {
-                return None;
-            }
-        }
-
-        for tx in transactions {
-            let sender_id = tx.sender;
-            let recipient_id = tx.recipient;
+        for tx in &transactions {
             let pre_tx_root = state.root();
+            if tx.is_noop() {
+                let (noop_acc_info, noop_path) = noop_witness(&state);
+                sender_pre_tx_info_and_paths.push((noop_acc_info.clone(), noop_path.clone()));
+                recipient_pre_tx_info_and_paths.push((noop_acc_info, noop_path));
+                pre_tx_roots.push(pre_tx_root);
+                post_tx_roots.push(pre_tx_root);
+                continue;
+            }
 
-            let sender_pre_acc_info = *state.id_to_account_info.get(&sender_id)?;
-            let sender_pre_path = state.account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-
-            let recipient_pre_acc_info = *state.id_to_account_info.get(&recipient_id)?;
-            let recipient_pre_path = state.account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
-
+            // The accounts are recorded just before they are updated, so that the
+            // recipient is seen once the sender is debited. Transactions are validated
+            // against the state they are applied to, so that several transactions from
+            // the same sender see the sender's updated nonce.
+            let mut witnesses = Vec::with_capacity(2);
+            let result = state.apply_transaction_with(&ledger_params, tx, |state, id| {
+                witnesses.push(account_witness(state, id).expect("updated accounts exist"));
+            });
             if validate_transactions {
-                state.apply_transaction(&ledger_params, tx)?;
-            } else {
-                let _ = state.apply_transaction(&ledger_params, tx);
+                result?;
+            }
+            // An invalid transaction updates no account, so its accounts are recorded
+            // as they are.
+            for &(id, unknown) in [
+                (tx.sender, Error::UnknownSender(tx.sender)),
+                (tx.recipient, Error::UnknownRecipient(tx.recipient)),
+            ]
+            .iter()
+            .skip(witnesses.len())
+            {
+                witnesses.push(account_witness(&state, id).ok_or(unknown)?);
             }
 
-            let post_tx_root = state.root();
-            let sender_post_path = state.account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-            let recipient_post_path = state.account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
-
-            sender_pre_tx_info_and_paths.push((sender_pre_acc_info, sender_pre_path));
-            recipient_pre_tx_info_and_paths.push((recipient_pre_acc_info, recipient_pre_path));
-            sender_post_paths.push(sender_post_path);
-            recipient_post_paths.push(recipient_post_path);
+            let mut witnesses = witnesses.into_iter();
+            sender_pre_tx_info_and_paths.push(witnesses.next().unwrap());
+            recipient_pre_tx_info_and_paths.push(witnesses.next().unwrap());
             pre_tx_roots.push(pre_tx_root);
-            post_tx_roots.push(post_tx_root);
+            post_tx_roots.push(state.root());
         }
 
-        Some(Self {
+        let rollup = Self {
+            operations_commitment: Some(operation::commit_to_operations(
+                &ledger_params,
+                operations,
+            )),
             ledger_params,
             initial_root,
             final_root: Some(state.root()),
-            transactions: Some(transactions.to_vec()),
+            registrations: Some(registrations.to_vec()),
+            registration_witnesses: Some(registration_witnesses),
+            post_registration_roots: Some(post_registration_roots),
+            operations: Some(operations.to_vec()),
+            operation_info_and_paths: Some(operation_info_and_paths),
+            post_operation_roots: Some(post_operation_roots),
+            transactions: Some(transactions),
             sender_pre_tx_info_and_paths: Some(sender_pre_tx_info_and_paths),
             recv_pre_tx_info_and_paths: Some(recipient_pre_tx_info_and_paths),
-            sender_post_paths: Some(sender_post_paths),
-            recv_post_paths: Some(recipient_post_paths),
             pre_tx_roots: Some(pre_tx_roots),
             post_tx_roots: Some(post_tx_roots),
-        })
+            _signature_gadget: PhantomData,
+        };
+        Ok((rollup, state.into_diff()))
     }
 }
 
-impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX> {
+/// The information of the account `id` and its authentication path in `state`, if
+/// the account exists.
+pub(crate) fn account_witness<S: SignatureScheme, M: AccMerkleConfig>(
+    state: &StateOverlay<S, M>,
+    id: AccountId,
+) -> Option<(AccountInformation<S>, AccPath<M>)> {
+    let account_info = state.account_info(&id)?.clone();
+    let path = state
+        .account_merkle_tree()
+        .generate_proof(id.leaf_index()?)
+        .unwrap();
+    Some((account_info, path))
+}
+
+/// Witnesses for the accounts of a no-op transaction. The circuit ignores them,
+/// apart from the balance and nonce arithmetic, which they satisfy by being zero.
+pub(crate) fn noop_witness<S: SignatureScheme, M: AccMerkleConfig>(
+    state: &StateOverlay<S, M>,
+) -> (AccountInformation<S>, AccPath<M>) {
+    let account_info = AccountInformation {
+        public_key: S::PublicKey::default(),
+        balance: Amount(0),
+        nonce: Nonce::default(),
+    };
+    let path = state
+        .account_merkle_tree()
+        .generate_proof(NEXT_ACCOUNT_LEAF_INDEX)
+        .unwrap();
+    (account_info, path)
+}
+
+impl<const NUM_TX: usize, const NUM_REG: usize, const NUM_OPS: usize, S, SG, M>
+    ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX, NUM_REG, NUM_OPS, S, SG, M>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+{
     #[tracing::instrument(target = "r1cs", skip(self, cs))]
     fn generate_constraints(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
     ) -> Result<(), SynthesisError> {
         // Parameters constant
-        let ledger_params = ParametersVar::new_constant(
+        let ledger_params = ParametersVar::<S, SG, M>::new_constant(
             ark_relations::ns!(cs, "Ledger parameters"),
             &self.ledger_params,
         )?;
 
-        // Public inputs: initial and final root
+        // Public inputs: initial and final root, and the operations commitment
         let initial_root = AccRootVar::new_input(ark_relations::ns!(cs, "Initial root"), || {
             self.initial_root.ok_or(SynthesisError::AssignmentMissing)
         })?;
         let final_root = AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || {
             self.final_root.ok_or(SynthesisError::AssignmentMissing)
         })?;
+        let operations_commitment = OperationsCommitmentVar::new_input(
+            ark_relations::ns!(cs, "Operations commitment"),
+            || {
+                self.operations_commitment
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
 
         let mut prev_root = initial_root;
 
+        // Registrations come before the transactions, so that new accounts can
+        // receive payments in the same batch.
+        for i in 0..NUM_REG {
+            let public_key = self.registrations.as_ref().map(|r| &r[i]);
+            let witness = self.registration_witnesses.as_ref().map(|w| &w[i]);
+            let post_root = self.post_registration_roots.as_ref().map(|r| r[i]);
+
+            // Witnesses:
+            let registration =
+                RegistrationVar::new_witness(ark_relations::ns!(cs, "Registration"), || {
+                    public_key.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let id = AccountIdVar::new_witness(ark_relations::ns!(cs, "New Account Id"), || {
+                witness
+                    .map(|w| w.0)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
+            let counter_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Counter Path"), || {
+                    witness
+                        .map(|w| &w.1)
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let account_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "New Account Path"), || {
+                    witness
+                        .map(|w| &w.2)
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let post_root =
+                AccRootVar::new_witness(ark_relations::ns!(cs, "Post-registration Root"), || {
+                    post_root.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            // Validate registration
+            registration
+                .validate(
+                    &ledger_params,
+                    &id,
+                    &counter_path,
+                    &account_path,
+                    &prev_root,
+                    &post_root,
+                )?
+                .enforce_equal(&Boolean::TRUE)?;
+
+            // advance
+            prev_root = post_root;
+        }
+
+        // Deposits and withdrawals come next, so that new accounts can be funded, and
+        // each of them is appended to the commitment.
+        let mut commitment = OperationsCommitmentVar::constant(OperationsCommitment::zero());
+        for i in 0..NUM_OPS {
+            let op = self.operations.as_ref().map(|o| &o[i]);
+            let acc_info = self.operation_info_and_paths.as_ref().map(|t| &t[i].0);
+            let path = self.operation_info_and_paths.as_ref().map(|t| &t[i].1);
+            let post_root = self.post_operation_roots.as_ref().map(|r| r[i]);
+
+            // Witnesses:
+            let op = OperationVar::new_witness(ark_relations::ns!(cs, "Operation"), || {
+                op.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
+            let acc_info = AccountInformationVar::new_witness(
+                ark_relations::ns!(cs, "Operation Account Info"),
+                || acc_info.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+
+            let path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Operation Path"), || {
+                    path.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let post_root =
+                AccRootVar::new_witness(ark_relations::ns!(cs, "Post-operation Root"), || {
+                    post_root.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            // Validate operation
+            op.validate(&ledger_params, &acc_info, &path, &prev_root, &post_root)?
+                .enforce_equal(&Boolean::TRUE)?;
+            commitment = op.commit(&ledger_params, &commitment)?;
+
+            // advance
+            prev_root = post_root;
+        }
+        commitment.enforce_equal(&operations_commitment)?;
+
         for i in 0..NUM_TX {
             let tx = self.transactions.as_ref().and_then(|t| t.get(i));
-            let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
-            let sender_pre_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
-
-            let recipient_acc_info = self.recv_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
-            let recipient_pre_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
+            let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].0);
+            let sender_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
 
-            let sender_post_path = self.sender_post_paths.as_ref().map(|t| &t[i]);
-            let recipient_post_path = self.recv_post_paths.as_ref().map(|t| &t[i]);
+            let recipient_acc_info = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].0);
+            let recipient_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
 
             let pre_tx_root = self.pre_tx_roots.as_ref().map(|t| t[i]);
             let post_tx_root = self.post_tx_roots.as_ref().map(|t| t[i]);
@@ -193,14 +499,9 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                 || sender_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
 
-            let sender_pre_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Pre-Path"), || {
-                    sender_pre_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
-
-            let sender_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Post-Path"), || {
-                    sender_post_path.ok_or(SynthesisError::AssignmentMissing)
+            let sender_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Sender Path"), || {
+                    sender_path.ok_or(SynthesisError::AssignmentMissing)
                 })?;
 
             let recipient_acc_info = AccountInformationVar::new_witness(
@@ -208,14 +509,9 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                 || recipient_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
 
-            let recipient_pre_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Pre-Path"), || {
-                    recipient_pre_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
-
-            let recipient_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
-                    recipient_post_path.ok_or(SynthesisError::AssignmentMissing)
+            let recipient_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Recipient Path"), || {
+                    recipient_path.ok_or(SynthesisError::AssignmentMissing)
                 })?;
 
             let pre_tx_root =
@@ -235,11 +531,9 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
             tx.validate(
                 &ledger_params,
                 &sender_acc_info,
-                &sender_pre_path,
-                &sender_post_path,
+                &sender_path,
                 &recipient_acc_info,
-                &recipient_pre_path,
-                &recipient_post_path,
+                &recipient_path,
                 &pre_tx_root,
                 &post_tx_root,
             )?
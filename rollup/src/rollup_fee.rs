@@ -0,0 +1,332 @@
+use crate::account::{AccountIdVar, AccountInformationVar};
+use crate::ledger::*;
+use crate::rollup::{account_witness, noop_witness};
+use crate::transaction_fee::TransactionWithFeeVar;
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_simple_payments::{
+    account::{AccountId, AccountInformation, AccountPublicKey},
+    ledger::{AccPath, AccRoot, DefaultSignatureScheme, MerkleConfig, Parameters, State},
+    overlay::StateDiff,
+    signature::{SigVerifyGadget, SignatureScheme},
+    transaction::Transaction,
+    Error,
+};
+use std::marker::PhantomData;
+
+/// A rollup whose transactions pay a fee to the ledger's fee collector.
+/// In addition to the initial and final roots, the public key of the fee
+/// collector is a public input, so that fees can only be paid to the account
+/// controlled by that key. As in `Rollup`, transactions are signed with the
+/// scheme `S`, whose signatures the circuit verifies with the gadget `SG`, and
+/// the account tree hashes as configured by `M`.
+pub struct RollupWithFee<
+    const NUM_TX: usize,
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+    M: AccMerkleConfigGadget = MerkleConfig,
+> {
+    /// The ledger parameters.
+    pub ledger_params: Parameters<S, M>,
+    /// The Merkle tree root before applying this batch of transactions.
+    pub initial_root: Option<AccRoot>,
+    /// The Merkle tree root after applying this batch of transactions.
+    pub final_root: Option<AccRoot>,
+    /// The public key of the account that receives the fees.
+    pub fee_collector_pk: Option<AccountPublicKey<S>>,
+    /// The identifier of the fee collector's account, which gives the position of
+    /// its leaf.
+    pub fee_collector_id: Option<AccountId>,
+    /// The current batch of transactions.
+    pub transactions: Option<Vec<Transaction<S>>>,
+    /// The sender's account information and corresponding authentication path,
+    /// *before* applying each transaction.
+    pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
+    /// The recipient's account information and corresponding authentication path,
+    /// once the sender of each transaction is debited, but *before* the recipient
+    /// is credited.
+    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
+    /// The fee collector's account information and corresponding authentication path,
+    /// once the recipient of each transaction is credited, but *before* the fee is
+    /// paid.
+    pub fee_pre_tx_info_and_paths: Option<Vec<(AccountInformation<S>, AccPath<M>)>>,
+    /// List of state roots, so that the i-th root is the state roots before applying
+    /// the i-th transaction. This means that `pre_tx_roots[0] == initial_root`.
+    pub pre_tx_roots: Option<Vec<AccRoot>>,
+    /// List of state roots, so that the i-th root is the state root after applying
+    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`.
+    pub post_tx_roots: Option<Vec<AccRoot>>,
+    _signature_gadget: PhantomData<SG>,
+}
+
+impl<const NUM_TX: usize, S, SG, M> RollupWithFee<NUM_TX, S, SG, M>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+{
+    pub fn new_empty(ledger_params: Parameters<S, M>) -> Self {
+        Self {
+            ledger_params,
+            initial_root: None,
+            final_root: None,
+            fee_collector_pk: None,
+            fee_collector_id: None,
+            transactions: None,
+            sender_pre_tx_info_and_paths: None,
+            recv_pre_tx_info_and_paths: None,
+            fee_pre_tx_info_and_paths: None,
+            pre_tx_roots: None,
+            post_tx_roots: None,
+            _signature_gadget: PhantomData,
+        }
+    }
+
+    pub fn only_public_inputs(
+        ledger_params: Parameters<S, M>,
+        initial_root: AccRoot,
+        final_root: AccRoot,
+        fee_collector_pk: AccountPublicKey<S>,
+    ) -> Self {
+        Self {
+            initial_root: Some(initial_root),
+            final_root: Some(final_root),
+            fee_collector_pk: Some(fee_collector_pk),
+            ..Self::new_empty(ledger_params)
+        }
+    }
+
+    /// Apply `transactions` to `state`, recording the witnesses needed to prove
+    /// that the fees were paid to `state.fee_collector`. If there are fewer than
+    /// `NUM_TX` transactions, the batch is padded with no-op transactions.
+    /// As in `Rollup::with_state_and_batch`, `state` is left untouched, and the
+    /// changes are returned alongside the witnesses.
+    /// Returns `Error::BatchTooLarge` if there are more than `NUM_TX` transactions,
+    /// `Error::NoFeeCollector` if the state has no fee collector, and the reason for
+    /// which a transaction is invalid if `validate_transactions` is set.
+    pub fn with_state_and_transactions(
+        ledger_params: Parameters<S, M>,
+        transactions: &[Transaction<S>],
+        state: &State<S, M>,
+        validate_transactions: bool,
+    ) -> Result<(Self, StateDiff<S, M>), Error> {
+        if transactions.len() > NUM_TX {
+            return Err(Error::BatchTooLarge {
+                max: NUM_TX,
+                got: transactions.len(),
+            });
+        }
+        let mut transactions = transactions.to_vec();
+        transactions.resize(NUM_TX, Transaction::noop());
+        let mut state = state.overlay();
+        let fee_collector_id = state.fee_collector().ok_or(Error::NoFeeCollector)?;
+        let fee_collector_pk = Some(
+            state
+                .account_info(&fee_collector_id)
+                .ok_or(Error::UnknownAccount(fee_collector_id))?
+                .public_key
+                .clone(),
+        );
+        let initial_root = Some(state.root());
+        let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
+        let mut recipient_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
+        let mut fee_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
+        let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
+        let mut post_tx_roots = Vec::with_capacity(NUM_TX);
+
+        for tx in &transactions {
+            let pre_tx_root = state.root();
+            if tx.is_noop() {
+                let (noop_acc_info, noop_path) = noop_witness(&state);
+                // The circuit checks the fee collector's public key even for no-ops.
+                let fee_witness = account_witness(&state, fee_collector_id)
+                    .ok_or(Error::UnknownAccount(fee_collector_id))?;
+                sender_pre_tx_info_and_paths.push((noop_acc_info.clone(), noop_path.clone()));
+                recipient_pre_tx_info_and_paths.push((noop_acc_info, noop_path));
+                fee_pre_tx_info_and_paths.push(fee_witness);
+                pre_tx_roots.push(pre_tx_root);
+                post_tx_roots.push(pre_tx_root);
+                continue;
+            }
+            // As in `Rollup::with_state_and_batch`, the accounts are recorded just
+            // before they are updated.
+            let mut witnesses = Vec::with_capacity(3);
+            let result = state.apply_transaction_with(&ledger_params, tx, |state, id| {
+                witnesses.push(account_witness(state, id).expect("updated accounts exist"));
+            });
+            if validate_transactions {
+                result?;
+            }
+            // Accounts that are not updated, such as the fee collector of a
+            // transaction without a fee, or all accounts of an invalid transaction,
+            // are recorded as they are.
+            for &(id, unknown) in [
+                (tx.sender, Error::UnknownSender(tx.sender)),
+                (tx.recipient, Error::UnknownRecipient(tx.recipient)),
+                (fee_collector_id, Error::UnknownAccount(fee_collector_id)),
+            ]
+            .iter()
+            .skip(witnesses.len())
+            {
+                witnesses.push(account_witness(&state, id).ok_or(unknown)?);
+            }
+
+            let mut witnesses = witnesses.into_iter();
+            sender_pre_tx_info_and_paths.push(witnesses.next().unwrap());
+            recipient_pre_tx_info_and_paths.push(witnesses.next().unwrap());
+            fee_pre_tx_info_and_paths.push(witnesses.next().unwrap());
+            pre_tx_roots.push(pre_tx_root);
+            post_tx_roots.push(state.root());
+        }
+
+        let rollup = Self {
+            ledger_params,
+            initial_root,
+            final_root: Some(state.root()),
+            fee_collector_pk,
+            fee_collector_id: Some(fee_collector_id),
+            transactions: Some(transactions),
+            sender_pre_tx_info_and_paths: Some(sender_pre_tx_info_and_paths),
+            recv_pre_tx_info_and_paths: Some(recipient_pre_tx_info_and_paths),
+            fee_pre_tx_info_and_paths: Some(fee_pre_tx_info_and_paths),
+            pre_tx_roots: Some(pre_tx_roots),
+            post_tx_roots: Some(post_tx_roots),
+            _signature_gadget: PhantomData,
+        };
+        Ok((rollup, state.into_diff()))
+    }
+}
+
+impl<const NUM_TX: usize, S, SG, M> ConstraintSynthesizer<ConstraintF>
+    for RollupWithFee<NUM_TX, S, SG, M>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+    M: AccMerkleConfigGadget,
+{
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Parameters constant
+        let ledger_params = ParametersVar::<S, SG, M>::new_constant(
+            ark_relations::ns!(cs, "Ledger parameters"),
+            &self.ledger_params,
+        )?;
+
+        // Public inputs: initial and final root, and the fee collector's public key
+        let initial_root = AccRootVar::new_input(ark_relations::ns!(cs, "Initial root"), || {
+            self.initial_root.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let final_root = AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || {
+            self.final_root.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let fee_collector_pk = SG::PublicKeyVar::new_input(
+            ark_relations::ns!(cs, "Fee collector public key"),
+            || {
+                self.fee_collector_pk
+                    .as_ref()
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
+
+        let fee_collector_id =
+            AccountIdVar::new_witness(ark_relations::ns!(cs, "Fee collector Id"), || {
+                self.fee_collector_id
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
+        let mut prev_root = initial_root;
+
+        for i in 0..NUM_TX {
+            let tx = self.transactions.as_ref().and_then(|t| t.get(i));
+            let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].0);
+            let sender_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
+
+            let recipient_acc_info = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].0);
+            let recipient_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
+
+            let fee_acc_info = self.fee_pre_tx_info_and_paths.as_ref().map(|t| &t[i].0);
+            let fee_path = self.fee_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
+
+            let pre_tx_root = self.pre_tx_roots.as_ref().map(|t| t[i]);
+            let post_tx_root = self.post_tx_roots.as_ref().map(|t| t[i]);
+
+            // Witnesses:
+            let tx =
+                TransactionWithFeeVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
+                    tx.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let sender_acc_info = AccountInformationVar::new_witness(
+                ark_relations::ns!(cs, "Sender Account Info"),
+                || sender_acc_info.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+
+            let sender_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Sender Path"), || {
+                    sender_path.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let recipient_acc_info = AccountInformationVar::new_witness(
+                ark_relations::ns!(cs, "Recipient Account Info"),
+                || recipient_acc_info.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+
+            let recipient_path =
+                AccIndexedPathVar::new_witness(ark_relations::ns!(cs, "Recipient Path"), || {
+                    recipient_path.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let fee_acc_info = AccountInformationVar::<S, SG>::new_witness(
+                ark_relations::ns!(cs, "Fee Collector Account Info"),
+                || fee_acc_info.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+
+            let fee_path = AccIndexedPathVar::new_witness(
+                ark_relations::ns!(cs, "Fee Collector Path"),
+                || fee_path.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+
+            let pre_tx_root =
+                AccRootVar::new_witness(ark_relations::ns!(cs, "Pre-tx Root"), || {
+                    pre_tx_root.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            let post_tx_root =
+                AccRootVar::new_witness(ark_relations::ns!(cs, "Post-tx Root"), || {
+                    post_tx_root.ok_or(SynthesisError::AssignmentMissing)
+                })?;
+
+            // Chain roots
+            prev_root.enforce_equal(&pre_tx_root)?;
+
+            // The fee must go to the account controlled by the fee collector's key.
+            fee_acc_info.public_key.enforce_equal(&fee_collector_pk)?;
+
+            // Validate tx
+            tx.validate_with_fee(
+                &ledger_params,
+                &fee_collector_id,
+                &sender_acc_info,
+                &sender_path,
+                &recipient_acc_info,
+                &recipient_path,
+                &fee_acc_info,
+                &fee_path,
+                &pre_tx_root,
+                &post_tx_root,
+            )?
+            .enforce_equal(&Boolean::TRUE)?;
+
+            // advance
+            prev_root = post_tx_root;
+        }
+
+        // Final root must match
+        prev_root.enforce_equal(&final_root)?;
+        Ok(())
+    }
+}
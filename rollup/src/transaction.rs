@@ -1,152 +1,186 @@
-use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar};
-use crate::ledger::{self, AccPathVar, AccRootVar, AmountVar};
+use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar, NonceVar};
+use crate::ledger::{
+    self, AccIndexedPathVar, AccMerkleConfigGadget, AccRootVar, AccUpdateVar, AmountVar,
+    DefaultSigVerifyGadget,
+};
 use crate::ConstraintF;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{Namespace, SynthesisError};
-use ark_simple_payments::signature::schnorr::constraints::{
-    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget, SignatureVar,
-};
-use ark_simple_payments::signature::SigVerifyGadget;
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
+use ark_simple_payments::ledger::{Amount, DefaultSignatureScheme};
+use ark_simple_payments::message::MessageKind;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
 use ark_simple_payments::transaction::Transaction;
 use std::borrow::Borrow;
 
 /// Transaction transferring some amount from one account to another.
-pub struct TransactionVar {
+pub struct TransactionVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+> {
     /// The account information of the sender.
     pub sender: AccountIdVar,
     /// The account information of the recipient.
     pub recipient: AccountIdVar,
     /// The amount being transferred from the sender to the receiver.
     pub amount: AmountVar,
-    /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: SignatureVar<EdwardsProjective, EdwardsVar>,
+    /// The sender's nonce at the time the transaction was created.
+    pub nonce: NonceVar,
+    /// The spend authorization is a signature over the ledger's domain tag, the
+    /// sender, the recipient, the amount, the nonce, and the fee.
+    pub signature: SG::SignatureVar,
+    /// Is this a real transaction, rather than a no-op that pads a batch?
+    pub is_real: Boolean<ConstraintF>,
 }
 
-impl TransactionVar {
-    /// Verify just the signature in the transaction.
-    #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key))]
-    pub fn verify_signature(
+impl<S: SignatureScheme, SG: SigVerifyGadget<S, ConstraintF>> TransactionVar<S, SG> {
+    /// Construct the message that the sender signs, given the fee that the
+    /// transaction pays. It is encoded like `simple_payments` encodes it.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, fee))]
+    pub fn signed_message<M: AccMerkleConfigGadget>(
         &self,
-        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
-        pub_key: &AccountPublicKeyVar,
-    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        fee: &AmountVar,
+    ) -> Vec<UInt8<ConstraintF>> {
         // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
-        let mut message = self.sender.to_bytes_le();
+        // (Version || Kind || DomainTag || SenderAccId || RecipientAccId || Amount || Nonce || Fee)
+        let mut message =
+            UInt8::constant_vec(&MessageKind::Transaction.prefix(&parameters.domain_tag));
+        message.extend(self.sender.to_bytes_le());
         message.extend(self.recipient.to_bytes_le());
         message.extend(self.amount.to_bytes_le());
-        SchnorrSignatureVerifyGadget::verify(&pp, &pub_key, &message, &self.signature)
+        message.extend(self.nonce.to_bytes_le());
+        message.extend(fee.to_bytes_le());
+        message
+    }
+
+    /// Verify just the signature in the transaction. Transactions validated by
+    /// this gadget do not pay a fee, so the signature must be over a zero fee.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pub_key))]
+    pub fn verify_signature<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        pub_key: &AccountPublicKeyVar<S, SG>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let zero_fee = AmountVar::new_constant(ConstraintSystemRef::None, Amount(0))?;
+        let message = self.signed_message(parameters, &zero_fee);
+        SG::verify(&parameters.sig_params, pub_key, &message, &self.signature)
     }
 
     /// Check that the transaction is valid for the given ledger state. This checks
     /// the following conditions:
     /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
+    ///    corresponding to `self.sender`.
     /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
+    ///    the transaction.
     /// 3. Verify that the recipient's account exists.
+    /// 4. Verify that the transaction nonce matches the sender's current nonce,
+    ///    and that the sender's nonce is incremented.
+    ///
+    /// The accounts are updated in turn, at the leaves given by their identifiers:
+    /// the sender is debited in `pre_root`, which gives an intermediate root, and the
+    /// recipient is then credited in that root, which must give `post_root`. So
+    /// `sender_path` is the sender's path in `pre_root`, while
+    /// `pre_recipient_acc_info` and `recipient_path` describe the recipient once the
+    /// sender is debited. As in the native ledger, the recipient may be the sender,
+    /// in which case the transaction only increments the nonce.
+    ///
+    /// If `self.is_real` is false, the transaction is a no-op, and this only checks
+    /// that the state is unchanged.
     #[tracing::instrument(
         target = "r1cs",
         skip(
             self,
             parameters,
             pre_sender_acc_info,
-            pre_sender_path,
-            post_sender_path,
+            sender_path,
             pre_recipient_acc_info,
-            pre_recipient_path,
-            post_recipient_path,
+            recipient_path,
             pre_root,
             post_root
         )
     )]
-    pub fn validate(
+    #[allow(clippy::too_many_arguments)]
+    pub fn validate<M: AccMerkleConfigGadget>(
         &self,
-        parameters: &ledger::ParametersVar,
-        pre_sender_acc_info: &AccountInformationVar,
-        pre_sender_path: &AccPathVar,
-        post_sender_path: &AccPathVar,
-        pre_recipient_acc_info: &AccountInformationVar,
-        pre_recipient_path: &AccPathVar,
-        post_recipient_path: &AccPathVar,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        pre_sender_acc_info: &AccountInformationVar<S, SG>,
+        sender_path: &AccIndexedPathVar,
+        pre_recipient_acc_info: &AccountInformationVar<S, SG>,
+        recipient_path: &AccIndexedPathVar,
         pre_root: &AccRootVar,
         post_root: &AccRootVar,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
         // Verify the signature against the sender pubkey.
-        let sig_verifies =
-            self.verify_signature(&parameters.sig_params, &pre_sender_acc_info.public_key)?; 
+        let sig_verifies = self.verify_signature(parameters, &pre_sender_acc_info.public_key)?;
+
+        // Check that the transaction carries the sender's current nonce.
+        let nonce_matches = self.nonce.is_eq(&pre_sender_acc_info.nonce)?;
 
-        // Compute the new sender balance.
+        // Compute the new sender balance and nonce.
         let mut post_sender_acc_info = pre_sender_acc_info.clone();
-        post_sender_acc_info.balance = post_sender_acc_info.balance.checked_sub(&self.amount)?; 
+        post_sender_acc_info.balance = post_sender_acc_info.balance.checked_sub(&self.amount)?;
+        post_sender_acc_info.nonce = post_sender_acc_info.nonce.checked_increment()?;
         // Compute the new receiver balance.
         let mut post_recipient_acc_info = pre_recipient_acc_info.clone();
-        post_recipient_acc_info.balance = post_recipient_acc_info.balance.checked_add(&self.amount)?; 
-
-        // Check that the pre-tx sender account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx sender account
-        // information is correct with respect to `post_tx_root`.
-        let sender_exists = pre_sender_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &pre_root,
-            &pre_sender_acc_info.to_bytes_le().as_slice(),
-        )?; 
+        post_recipient_acc_info.balance =
+            post_recipient_acc_info.balance.checked_add(&self.amount)?;
 
-        let sender_updated_correctly = post_sender_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &post_root,
-            &post_sender_acc_info.to_bytes_le().as_slice(),
-        )?; 
+        // Check that both accounts are in the tree that they are updated in, and
+        // that updating the sender and then the recipient leads to `post_root`.
+        let updates = [
+            AccUpdateVar {
+                index: self.sender.to_bits_le(),
+                path: sender_path,
+                old_leaf: pre_sender_acc_info.to_bytes_le(),
+                new_leaf: post_sender_acc_info.to_bytes_le(),
+            },
+            AccUpdateVar {
+                index: self.recipient.to_bits_le(),
+                path: recipient_path,
+                old_leaf: pre_recipient_acc_info.to_bytes_le(),
+                new_leaf: post_recipient_acc_info.to_bytes_le(),
+            },
+        ];
+        let (accounts_exist, root) = AccUpdateVar::apply_in_turn(parameters, pre_root, &updates)?;
+        let updated_correctly = root.is_eq(post_root)?;
 
-        // Check that the pre-tx recipient account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx recipient account
-        // information is correct with respect to `post_tx_root`.
-        let recipient_exists = pre_recipient_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &pre_root,
-            &pre_recipient_acc_info.to_bytes_le().as_slice(),
-        )?; 
-
-        let recipient_updated_correctly = post_recipient_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &post_root,
-            &post_recipient_acc_info.to_bytes_le().as_slice(),
-        )?; 
-
-        sender_exists
-            .and(&sender_updated_correctly)?
-            .and(&recipient_exists)?
-            .and(&recipient_updated_correctly)?
-            .and(&sig_verifies)
+        let real_tx_ok = accounts_exist
+            .and(&updated_correctly)?
+            .and(&nonce_matches)?
+            .and(&sig_verifies)?;
+        let noop_ok = pre_root.is_eq(post_root)?;
+        self.is_real.select(&real_tx_ok, &noop_ok)
     }
 }
 
-impl AllocVar<Transaction, ConstraintF> for TransactionVar {
+impl<S, SG> AllocVar<Transaction<S>, ConstraintF> for TransactionVar<S, SG>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+{
     #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
-    fn new_variable<T: Borrow<Transaction>>(
+    fn new_variable<T: Borrow<Transaction<S>>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
         let cs = cs.into();
         f().and_then(|tx| {
-            let tx: &Transaction = tx.borrow();
+            let tx: &Transaction<S> = tx.borrow();
             let sender = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?;
             let recipient = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?;
             let amount = AmountVar::new_variable(cs.clone(), || Ok(&tx.amount), mode)?;
-            let signature = SignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            let nonce = NonceVar::new_variable(cs.clone(), || Ok(&tx.nonce), mode)?;
+            let signature = SG::SignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            let is_real = Boolean::new_variable(cs.clone(), || Ok(!tx.is_noop()), mode)?;
             Ok(Self {
                 sender,
                 recipient,
                 amount,
+                nonce,
                 signature,
+                is_real,
             })
         })
     }
-}
\ No newline at end of file
+}
@@ -1,59 +1,110 @@
-use crate::account::AccountInformationVar;
-use crate::ledger::{self, AccPathVar, AccRootVar, AmountVar};
+use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar};
+use crate::ledger::{
+    self, AccIndexedPathVar, AccMerkleConfigGadget, AccRootVar, AccUpdateVar, AmountVar,
+    DefaultSigVerifyGadget,
+};
 use crate::transaction::TransactionVar;
 use crate::ConstraintF;
-
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::SynthesisError;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_simple_payments::ledger::DefaultSignatureScheme;
+use ark_simple_payments::signature::{SigVerifyGadget, SignatureScheme};
+use ark_simple_payments::transaction::Transaction;
+use std::borrow::Borrow;
 
 /// Wraps an existing TransactionVar and a fee amount.
-pub struct TransactionWithFeeVar {
-    pub inner: TransactionVar, // (sender, recipient, amount, signature)
-    pub fee: AmountVar,        // fee charged to sender, paid to fee-collector
+pub struct TransactionWithFeeVar<
+    S: SignatureScheme = DefaultSignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF> = DefaultSigVerifyGadget,
+> {
+    pub inner: TransactionVar<S, SG>, // (sender, recipient, amount, nonce, signature)
+    pub fee: AmountVar,               // fee charged to sender, paid to fee-collector
 }
 
-impl TransactionWithFeeVar {
-    pub fn new(inner: TransactionVar, fee: AmountVar) -> Self {
+impl<S: SignatureScheme, SG: SigVerifyGadget<S, ConstraintF>> TransactionWithFeeVar<S, SG> {
+    pub fn new(inner: TransactionVar<S, SG>, fee: AmountVar) -> Self {
         Self { inner, fee }
     }
 
+    /// Verify just the signature in the transaction. Unlike
+    /// `TransactionVar::verify_signature`, the signed message includes `self.fee`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, pub_key))]
+    pub fn verify_signature<M: AccMerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        pub_key: &AccountPublicKeyVar<S, SG>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let message = self.inner.signed_message(parameters, &self.fee);
+        SG::verify(
+            &parameters.sig_params,
+            pub_key,
+            &message,
+            &self.inner.signature,
+        )
+    }
+
     /// Validate the tx + fee against the ledger state.
     /// Checks:
-    ///   - signature verifies (same as TransactionVar),
+    ///   - signature over the transaction and the fee verifies,
+    ///   - the transaction nonce matches the sender's nonce, which is incremented,
     ///   - sender balance decreases by amount + fee,
     ///   - recipient balance increases by amount,
     ///   - fee-collector balance increases by fee,
-    ///   - all 3 membership proofs (pre) and 3 membership proofs (post) are correct.
-    pub fn validate_with_fee(
+    ///   - updating the 3 accounts in turn moves the ledger from `pre_root` to `post_root`.
+    /// As in `TransactionVar::validate`, each account is updated in the root left by
+    /// the previous update, at the leaf given by its identifier: the sender first,
+    /// then the recipient, and then the fee collector, whose identifier is
+    /// `fee_collector`. So the account information and path of the recipient are
+    /// those once the sender is debited, and those of the fee collector are those
+    /// once the recipient is credited. The accounts may coincide, as in the native
+    /// ledger.
+    /// For no-op transactions, this only checks that the state is unchanged.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(
+            self,
+            parameters,
+            fee_collector,
+            pre_sender_acc_info,
+            sender_path,
+            pre_recipient_acc_info,
+            recipient_path,
+            pre_fee_acc_info,
+            fee_path,
+            pre_root,
+            post_root
+        )
+    )]
+    #[allow(clippy::too_many_arguments)]
+    pub fn validate_with_fee<M: AccMerkleConfigGadget>(
         &self,
-        parameters: &ledger::ParametersVar,
+        parameters: &ledger::ParametersVar<S, SG, M>,
+        fee_collector: &AccountIdVar,
         // sender
-        pre_sender_acc_info: &AccountInformationVar,
-        pre_sender_path: &AccPathVar,
-        post_sender_path: &AccPathVar,
+        pre_sender_acc_info: &AccountInformationVar<S, SG>,
+        sender_path: &AccIndexedPathVar,
         // recipient
-        pre_recipient_acc_info: &AccountInformationVar,
-        pre_recipient_path: &AccPathVar,
-        post_recipient_path: &AccPathVar,
+        pre_recipient_acc_info: &AccountInformationVar<S, SG>,
+        recipient_path: &AccIndexedPathVar,
         // fee-collector
-        pre_fee_acc_info: &AccountInformationVar,
-        pre_fee_path: &AccPathVar,
-        post_fee_path: &AccPathVar,
+        pre_fee_acc_info: &AccountInformationVar<S, SG>,
+        fee_path: &AccIndexedPathVar,
         // roots
         pre_root: &AccRootVar,
         post_root: &AccRootVar,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // 1) Signature (note: this binds the original message; if you want to bind `fee`,
-        //    extend the message in TransactionVar.verify_signature to include it).
-        let sig_ok = self
-            .inner
-            .verify_signature(&parameters.sig_params, &pre_sender_acc_info.public_key)?;
+        // 1) Signature, binding the fee as well as the transaction.
+        let sig_ok = self.verify_signature(parameters, &pre_sender_acc_info.public_key)?;
+
+        // 2) Replay protection: the transaction carries the sender's current nonce.
+        let nonce_ok = self.inner.nonce.is_eq(&pre_sender_acc_info.nonce)?;
 
-        // 2) Balances after tx+fee
-        // sender: −amount −fee
+        // 3) Balances after tx+fee
+        // sender: −amount −fee, and the nonce is incremented
         let mut post_sender = pre_sender_acc_info.clone();
         let after_amt = post_sender.balance.checked_sub(&self.inner.amount)?;
         post_sender.balance = after_amt.checked_sub(&self.fee)?;
+        post_sender.nonce = post_sender.nonce.checked_increment()?;
 
         // recipient: +amount
         let mut post_recipient = pre_recipient_acc_info.clone();
@@ -63,53 +114,57 @@ impl TransactionWithFeeVar {
         let mut post_fee = pre_fee_acc_info.clone();
         post_fee.balance = post_fee.balance.checked_add(&self.fee)?;
 
-        // 3) Merkle membership checks (pre)
-        let sender_exists = pre_sender_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            pre_root,
-            &pre_sender_acc_info.to_bytes_le().as_slice(),
-        )?;
-        let recipient_exists = pre_recipient_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            pre_root,
-            &pre_recipient_acc_info.to_bytes_le().as_slice(),
-        )?;
-        let fee_exists = pre_fee_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            pre_root,
-            &pre_fee_acc_info.to_bytes_le().as_slice(),
-        )?;
-
-        // 4) Merkle membership checks (post)
-        let sender_updated = post_sender_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            post_root,
-            &post_sender.to_bytes_le().as_slice(),
-        )?;
-        let recipient_updated = post_recipient_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            post_root,
-            &post_recipient.to_bytes_le().as_slice(),
-        )?;
-        let fee_updated = post_fee_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            post_root,
-            &post_fee.to_bytes_le().as_slice(),
-        )?;
+        // 4) Merkle updates: sender, then recipient, then fee-collector
+        let updates = [
+            AccUpdateVar {
+                index: self.inner.sender.to_bits_le(),
+                path: sender_path,
+                old_leaf: pre_sender_acc_info.to_bytes_le(),
+                new_leaf: post_sender.to_bytes_le(),
+            },
+            AccUpdateVar {
+                index: self.inner.recipient.to_bits_le(),
+                path: recipient_path,
+                old_leaf: pre_recipient_acc_info.to_bytes_le(),
+                new_leaf: post_recipient.to_bytes_le(),
+            },
+            AccUpdateVar {
+                index: fee_collector.to_bits_le(),
+                path: fee_path,
+                old_leaf: pre_fee_acc_info.to_bytes_le(),
+                new_leaf: post_fee.to_bytes_le(),
+            },
+        ];
+        let (accounts_exist, root) = AccUpdateVar::apply_in_turn(parameters, pre_root, &updates)?;
+        let updated_correctly = root.is_eq(post_root)?;
 
         // 5) Combine all conditions
-        sender_exists
-            .and(&sender_updated)?
-            .and(&recipient_exists)?
-            .and(&recipient_updated)?
-            .and(&fee_exists)?
-            .and(&fee_updated)?
-            .and(&sig_ok)
+        let real_tx_ok = accounts_exist
+            .and(&updated_correctly)?
+            .and(&nonce_ok)?
+            .and(&sig_ok)?;
+        let noop_ok = pre_root.is_eq(post_root)?;
+        self.inner.is_real.select(&real_tx_ok, &noop_ok)
+    }
+}
+
+impl<S, SG> AllocVar<Transaction<S>, ConstraintF> for TransactionWithFeeVar<S, SG>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, ConstraintF>,
+{
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Transaction<S>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|tx| {
+            let tx: &Transaction<S> = tx.borrow();
+            let inner = TransactionVar::new_variable(cs.clone(), || Ok(tx), mode)?;
+            let fee = AmountVar::new_variable(cs.clone(), || Ok(&tx.fee), mode)?;
+            Ok(Self::new(inner, fee))
+        })
     }
 }
@@ -0,0 +1,54 @@
+use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+use ark_simple_payments::signature::{schnorr::Schnorr, SignatureScheme};
+use ark_std::test_rng;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+type S = Schnorr<JubJub>;
+
+/// Compare verifying Schnorr signatures one at a time with verifying them in a batch.
+fn batch_verification(c: &mut Criterion) {
+    let rng = &mut test_rng();
+    let parameters = S::setup(rng).unwrap();
+    let mut group = c.benchmark_group("Schnorr verification");
+    for &num_signatures in &[16usize, 64, 256] {
+        let messages = (0..num_signatures)
+            .map(|i| (i as u64).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut pks = Vec::new();
+        let mut signatures = Vec::new();
+        for message in &messages {
+            let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+            signatures.push(S::sign(&parameters, &sk, message, rng).unwrap());
+            pks.push(pk);
+        }
+
+        group.throughput(Throughput::Elements(num_signatures as u64));
+        group.bench_with_input(
+            BenchmarkId::new("one at a time", num_signatures),
+            &num_signatures,
+            |b, _| {
+                b.iter(|| {
+                    for ((pk, message), signature) in pks.iter().zip(&messages).zip(&signatures) {
+                        assert!(S::verify(&parameters, pk, message, signature).unwrap());
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("batch", num_signatures),
+            &num_signatures,
+            |b, _| {
+                b.iter(|| {
+                    assert_eq!(
+                        S::batch_verify(&parameters, &pks, &messages, &signatures).unwrap(),
+                        None
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batch_verification);
+criterion_main!(benches);
@@ -1,20 +1,57 @@
 use crate::ledger::*;
-use crate::signature::schnorr;
-use ark_ed_on_bls12_381::EdwardsProjective;
+use crate::signature::SignatureScheme;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use derivative::Derivative;
+use std::convert::TryFrom;
 
 /// Account public key used to verify transaction signatures.
-pub type AccountPublicKey = schnorr::PublicKey<EdwardsProjective>;
+pub type AccountPublicKey<S = DefaultSignatureScheme> = <S as SignatureScheme>::PublicKey;
 /// Account secret key used to create transaction signatures.
-pub type AccountSecretKey = schnorr::SecretKey<EdwardsProjective>;
+pub type AccountSecretKey<S = DefaultSignatureScheme> = <S as SignatureScheme>::SecretKey;
 
-/// Account identifier. This prototype supports only 256 accounts at a time.
-#[derive(Hash, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Debug)]
-pub struct AccountId(pub u8);
+/// The integer type underlying `AccountId`. Account identifiers are 32 bits wide by
+/// default, and 64 bits wide if the `account-id-u64` feature is enabled.
+#[cfg(not(feature = "account-id-u64"))]
+pub type AccountIdRepr = u32;
+/// The integer type underlying `AccountId`. Account identifiers are 32 bits wide by
+/// default, and 64 bits wide if the `account-id-u64` feature is enabled.
+#[cfg(feature = "account-id-u64")]
+pub type AccountIdRepr = u64;
+
+/// Account identifier. The identifier of an account is also the index of the leaf
+/// that stores the account's information in the account Merkle tree.
+#[derive(
+    Hash,
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Ord,
+    PartialOrd,
+    Debug,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+)]
+pub struct AccountId(pub AccountIdRepr);
 
 impl AccountId {
+    /// The number of bits in an account identifier.
+    pub const BITS: u32 = AccountIdRepr::BITS;
+
+    /// The height of a Merkle tree that has a leaf for every account identifier.
+    /// No account tree can be taller than this.
+    pub const MAX_TREE_HEIGHT: usize = Self::BITS as usize + 1;
+
     /// Convert the account identifier to bytes.
     pub fn to_bytes_le(&self) -> Vec<u8> {
-        vec![self.0]
+        self.0.to_le_bytes().to_vec()
+    }
+
+    /// The index of the leaf that stores this account in the account Merkle tree.
+    /// Returns `None` if the identifier does not fit in a `usize`, which can happen
+    /// for 64-bit identifiers on 32-bit targets. No tree can store such an account.
+    pub fn leaf_index(&self) -> Option<usize> {
+        usize::try_from(self.0).ok()
     }
 }
 
@@ -25,18 +62,61 @@ impl AccountId {
     }
 }
 
+/// Per-account transaction counter. A transaction is only valid if it carries the
+/// sender's current nonce, which prevents signed transactions from being replayed.
+#[derive(
+    Hash,
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Ord,
+    PartialOrd,
+    Debug,
+    Default,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+)]
+pub struct Nonce(pub u64);
+
+impl Nonce {
+    /// Convert the nonce to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+
+    /// Return the nonce that follows `self`, or `None` on overflow.
+    pub fn checked_increment(self) -> Option<Self> {
+        self.0.checked_add(1).map(Self)
+    }
+}
+
 /// Information about the account, such as the balance and the associated public key.
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
-pub struct AccountInformation {
+#[derive(Derivative, CanonicalSerialize, CanonicalDeserialize)]
+#[derivative(
+    Hash(bound = ""),
+    Eq(bound = ""),
+    PartialEq(bound = ""),
+    Clone(bound = ""),
+    Debug(bound = "")
+)]
+pub struct AccountInformation<S: SignatureScheme = DefaultSignatureScheme> {
     /// The account public key.
-    pub public_key: AccountPublicKey,
+    pub public_key: AccountPublicKey<S>,
     /// The balance associated with this this account.
     pub balance: Amount,
+    /// The nonce that the next transaction sent from this account must carry.
+    pub nonce: Nonce,
 }
 
-impl AccountInformation {
+impl<S: SignatureScheme> AccountInformation<S> {
     /// Convert the account information to bytes.
     pub fn to_bytes_le(&self) -> Vec<u8> {
-        ark_ff::to_bytes![self.public_key, self.balance.to_bytes_le()].unwrap()
+        ark_ff::to_bytes![
+            self.public_key,
+            self.balance.to_bytes_le(),
+            self.nonce.to_bytes_le()
+        ]
+        .unwrap()
     }
 }
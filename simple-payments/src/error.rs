@@ -0,0 +1,80 @@
+use crate::account::AccountId;
+use std::fmt;
+
+/// The reasons for which the ledger rejects a transaction, an operation or an
+/// update of its state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The sender of a transaction has no account.
+    UnknownSender(AccountId),
+    /// The recipient of a transaction has no account.
+    UnknownRecipient(AccountId),
+    /// An account that an operation or update refers to does not exist.
+    UnknownAccount(AccountId),
+    /// A transaction pays a fee, but the ledger has no fee collector.
+    NoFeeCollector,
+    /// The signature does not verify under the public key of the account.
+    BadSignature,
+    /// The nonce differs from the account's current nonce, for example because
+    /// the transaction or withdrawal was already applied.
+    BadNonce,
+    /// The account cannot pay for the amount, and the fee if any.
+    InsufficientBalance,
+    /// A balance, a nonce or the sum of an amount and a fee does not fit in 64 bits.
+    Overflow,
+    /// There is no identifier left for a new account.
+    LedgerFull,
+    /// A new account would have the same public key as an existing account.
+    DuplicatePublicKey,
+    /// A new account would have a public key under which no signature verifies.
+    InvalidPublicKey,
+    /// The account information does not match the account Merkle tree.
+    MerklePathInvalid,
+    /// A `StateDiff` is committed to a state other than the one it was made for.
+    StaleDiff,
+    /// A batch has `got` registrations, but its circuit registers exactly `expected`
+    /// accounts.
+    WrongRegistrationCount { expected: usize, got: usize },
+    /// A batch has `got` deposits and withdrawals, but its circuit applies exactly
+    /// `expected` of them.
+    WrongOperationCount { expected: usize, got: usize },
+    /// A batch has `got` transactions, but its circuit has room for at most `max`.
+    BatchTooLarge { max: usize, got: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownSender(id) => write!(f, "unknown sender account {}", id.0),
+            Error::UnknownRecipient(id) => write!(f, "unknown recipient account {}", id.0),
+            Error::UnknownAccount(id) => write!(f, "unknown account {}", id.0),
+            Error::NoFeeCollector => write!(f, "the ledger has no fee collector"),
+            Error::BadSignature => write!(f, "invalid signature"),
+            Error::BadNonce => write!(f, "unexpected nonce"),
+            Error::InsufficientBalance => write!(f, "insufficient balance"),
+            Error::Overflow => write!(f, "arithmetic overflow"),
+            Error::LedgerFull => write!(f, "the ledger has no room for more accounts"),
+            Error::DuplicatePublicKey => write!(f, "the public key already has an account"),
+            Error::InvalidPublicKey => write!(f, "the public key is invalid"),
+            Error::MerklePathInvalid => write!(f, "account does not match the Merkle tree"),
+            Error::StaleDiff => write!(f, "the diff was made for another state"),
+            Error::WrongRegistrationCount { expected, got } => write!(
+                f,
+                "the batch has {} registrations instead of {}",
+                got, expected
+            ),
+            Error::WrongOperationCount { expected, got } => write!(
+                f,
+                "the batch has {} deposits and withdrawals instead of {}",
+                got, expected
+            ),
+            Error::BatchTooLarge { max, got } => write!(
+                f,
+                "the batch has {} transactions, but room for at most {}",
+                got, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
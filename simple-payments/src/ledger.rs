@@ -1,17 +1,39 @@
-use crate::account::{AccountId, AccountInformation, AccountPublicKey, AccountSecretKey};
+use crate::account::{
+    AccountId, AccountIdRepr, AccountInformation, AccountPublicKey, AccountSecretKey,
+};
+use crate::curve::{EdwardsProjective, Fq};
+use crate::message::{self, DomainTag};
+use crate::operation::Operation;
+use crate::overlay::{StateDiff, StateOverlay};
+use crate::poseidon;
 use crate::signature::{schnorr, SignatureScheme};
+use crate::sparse_merkle_tree::SparseMerkleTree;
 use crate::transaction::Transaction;
+use crate::Error;
 use ark_crypto_primitives::crh::{
     injective_map::{PedersenCRHCompressor, TECompressor},
     pedersen, TwoToOneCRH, CRH,
 };
-use ark_crypto_primitives::merkle_tree::{self, MerkleTree, Path};
-use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_crypto_primitives::merkle_tree::{self, Path};
+use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
 use ark_std::rand::Rng;
+use derivative::Derivative;
 use std::collections::HashMap;
 
 /// Represents transaction amounts and account balances.
-#[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug)]
+#[derive(
+    Hash,
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    PartialOrd,
+    Ord,
+    Debug,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+)]
 pub struct Amount(pub u64);
 
 impl Amount {
@@ -28,24 +50,161 @@ impl Amount {
     }
 }
 
-/// The parameters that are used in transaction creation and validation.
-#[derive(Clone)]
-pub struct Parameters {
-    pub sig_params: schnorr::Parameters<EdwardsProjective>,
-    pub leaf_crh_params: <TwoToOneHash as CRH>::Parameters,
-    pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
+/// Identifies a chain, so that ledgers that share their other parameters, such as a
+/// test ledger and a production ledger, do not accept each other's signatures.
+#[derive(
+    Hash, Eq, PartialEq, Copy, Clone, Debug, Default, CanonicalSerialize, CanonicalDeserialize,
+)]
+pub struct ChainId(pub u64);
+
+/// The signature scheme that authorizes transactions unless another one is chosen:
+/// Schnorr signatures over Jubjub, with Blake2s challenges.
+pub type DefaultSignatureScheme = schnorr::Schnorr<EdwardsProjective>;
+
+/// The parameters that are used in transaction creation and validation, where
+/// transactions are signed with the signature scheme `S`, and the account tree
+/// hashes as configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct Parameters<
+    S: SignatureScheme = DefaultSignatureScheme,
+    M: AccMerkleConfig = MerkleConfig,
+> {
+    /// The chain that the ledger belongs to. Signed messages commit to it.
+    pub chain_id: ChainId,
+    pub sig_params: S::Parameters,
+    pub leaf_crh_params: <M::LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <M::TwoToOneHash as TwoToOneCRH>::Parameters,
+    /// The domain tag of the ledger, which signed messages start with. It is
+    /// computed by `message::domain_tag` when the parameters are sampled or
+    /// deserialized, and must be recomputed if any of the other fields change.
+    pub domain_tag: DomainTag,
 }
 
-impl Parameters {
+impl<S: SignatureScheme, M: AccMerkleConfig> Parameters<S, M> {
+    /// Sample parameters for the chain `ChainId(0)`.
     pub fn sample<R: Rng>(rng: &mut R) -> Self {
-        let sig_params = schnorr::Schnorr::setup(rng).unwrap();
-        let leaf_crh_params = <LeafHash as CRH>::setup(rng).unwrap();
-        let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(rng).unwrap();
-        Self {
+        Self::sample_for_chain(ChainId(0), rng)
+    }
+
+    /// Sample parameters for the chain `chain_id`.
+    pub fn sample_for_chain<R: Rng>(chain_id: ChainId, rng: &mut R) -> Self {
+        let sig_params = S::setup(rng).unwrap();
+        let leaf_crh_params = <M::LeafHash as CRH>::setup(rng).unwrap();
+        let two_to_one_crh_params = <M::TwoToOneHash as TwoToOneCRH>::setup(rng).unwrap();
+        Self::with_domain_tag(chain_id, sig_params, leaf_crh_params, two_to_one_crh_params)
+    }
+
+    /// Assemble the parameters, and compute their domain tag.
+    fn with_domain_tag(
+        chain_id: ChainId,
+        sig_params: S::Parameters,
+        leaf_crh_params: <M::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: <M::TwoToOneHash as TwoToOneCRH>::Parameters,
+    ) -> Self {
+        let mut parameters = Self {
+            chain_id,
             sig_params,
             leaf_crh_params,
             two_to_one_crh_params,
-        }
+            domain_tag: DomainTag::default(),
+        };
+        parameters.domain_tag = message::domain_tag(&parameters);
+        parameters
+    }
+}
+
+/// Check that the Pedersen parameters `params` contain exactly the generators
+/// required by the window `W`.
+fn check_crh_params<W: pedersen::Window>(
+    params: &pedersen::Parameters<EdwardsProjective>,
+) -> Result<(), SerializationError> {
+    let well_formed = params.generators.len() == W::NUM_WINDOWS
+        && params
+            .generators
+            .iter()
+            .all(|window| window.len() == W::WINDOW_SIZE);
+    if well_formed {
+        Ok(())
+    } else {
+        Err(SerializationError::InvalidData)
+    }
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> CanonicalSerialize for Parameters<S, M> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.chain_id.serialize(&mut writer)?;
+        self.sig_params.serialize(&mut writer)?;
+        self.serialized_crh_params().serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.chain_id.serialized_size()
+            + self.sig_params.serialized_size()
+            + self.serialized_crh_params().serialized_size()
+    }
+
+    fn serialize_uncompressed<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.chain_id.serialize_uncompressed(&mut writer)?;
+        self.sig_params.serialize_uncompressed(&mut writer)?;
+        self.serialized_crh_params().serialize_uncompressed(writer)
+    }
+
+    fn serialize_unchecked<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.chain_id.serialize_unchecked(&mut writer)?;
+        self.sig_params.serialize_unchecked(&mut writer)?;
+        self.serialized_crh_params().serialize_unchecked(writer)
+    }
+
+    fn uncompressed_size(&self) -> usize {
+        self.chain_id.uncompressed_size()
+            + self.sig_params.uncompressed_size()
+            + self.serialized_crh_params().uncompressed_size()
+    }
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> CanonicalDeserialize for Parameters<S, M> {
+    /// Deserialize the parameters, checking that all group elements are valid and
+    /// that the hash parameters are well-formed.
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let chain_id = ChainId::deserialize(&mut reader)?;
+        let sig_params = S::Parameters::deserialize(&mut reader)?;
+        let crh_params = M::SerializedParameters::deserialize(reader)?;
+        Self::from_parts(chain_id, sig_params, crh_params)
+    }
+
+    fn deserialize_uncompressed<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let chain_id = ChainId::deserialize_uncompressed(&mut reader)?;
+        let sig_params = S::Parameters::deserialize_uncompressed(&mut reader)?;
+        let crh_params = M::SerializedParameters::deserialize_uncompressed(reader)?;
+        Self::from_parts(chain_id, sig_params, crh_params)
+    }
+
+    fn deserialize_unchecked<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let chain_id = ChainId::deserialize_unchecked(&mut reader)?;
+        let sig_params = S::Parameters::deserialize_unchecked(&mut reader)?;
+        let crh_params = M::SerializedParameters::deserialize_unchecked(reader)?;
+        Self::from_parts(chain_id, sig_params, crh_params)
+    }
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> Parameters<S, M> {
+    fn serialized_crh_params(&self) -> M::SerializedParameters {
+        M::serialize_crh_params(&self.leaf_crh_params, &self.two_to_one_crh_params)
+    }
+
+    fn from_parts(
+        chain_id: ChainId,
+        sig_params: S::Parameters,
+        crh_params: M::SerializedParameters,
+    ) -> Result<Self, SerializationError> {
+        let (leaf_crh_params, two_to_one_crh_params) = M::deserialize_crh_params(crh_params)?;
+        Ok(Self::with_domain_tag(
+            chain_id,
+            sig_params,
+            leaf_crh_params,
+            two_to_one_crh_params,
+        ))
     }
 }
 
@@ -54,10 +213,14 @@ pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, T
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct TwoToOneWindow;
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing two outputs.
+/// The number of bits in the byte encoding of an element of `Fq`: 256 bits for
+/// Jubjub, and 320 bits with the `mnt4-298` feature.
+const FQ_BITS: usize = <Fq as PrimeField>::BigInt::NUM_LIMBS * 64;
+
+// `WINDOW_SIZE * NUM_WINDOWS` >= 2 * `FQ_BITS` = enough for hashing two outputs.
 impl pedersen::Window for TwoToOneWindow {
     const WINDOW_SIZE: usize = 128;
-    const NUM_WINDOWS: usize = 4;
+    const NUM_WINDOWS: usize = (2 * FQ_BITS + 127) / 128;
 }
 
 pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
@@ -65,12 +228,53 @@ pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafW
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct LeafWindow;
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing two outputs.
+// `WINDOW_SIZE * NUM_WINDOWS` = 720 bits for Jubjub = enough for hashing the account
+// information (a public key of two coordinates, a 64-bit balance and a 64-bit nonce).
 impl pedersen::Window for LeafWindow {
     const WINDOW_SIZE: usize = 144;
-    const NUM_WINDOWS: usize = 4;
+    const NUM_WINDOWS: usize = (2 * FQ_BITS + 128 + 143) / 144;
+}
+
+/// The number of bytes that `LeafHash` and `PoseidonLeafHash` can hash.
+pub const LEAF_HASH_INPUT_BYTES: usize = <LeafWindow as pedersen::Window>::WINDOW_SIZE
+    * <LeafWindow as pedersen::Window>::NUM_WINDOWS
+    / 8;
+
+/// A configuration of the account Merkle tree. Its hashes output elements of `Fq`,
+/// the field of the rollup circuits, so that roots and leaf digests are single field
+/// elements whichever hashes are used. `Parameters` and `State` serialize the
+/// parameters of the hashes through `SerializedParameters`.
+pub trait AccMerkleConfig:
+    merkle_tree::Config<
+        LeafHash = <Self as AccMerkleConfig>::AccLeafHash,
+        TwoToOneHash = <Self as AccMerkleConfig>::AccTwoToOneHash,
+    > + Clone
+    + 'static
+{
+    type AccLeafHash: CRH<Output = Fq>;
+    type AccTwoToOneHash: TwoToOneCRH<Output = Fq>;
+    /// The serialized form of the parameters of both hashes.
+    type SerializedParameters: CanonicalSerialize + CanonicalDeserialize;
+
+    fn serialize_crh_params(
+        leaf_crh_params: &<Self::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+    ) -> Self::SerializedParameters;
+
+    /// Rebuild the parameters of both hashes, checking that they are well-formed.
+    #[allow(clippy::type_complexity)]
+    fn deserialize_crh_params(
+        params: Self::SerializedParameters,
+    ) -> Result<
+        (
+            <Self::LeafHash as CRH>::Parameters,
+            <Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+        ),
+        SerializationError,
+    >;
 }
 
+/// The account tree configuration that hashes with Pedersen, which is the default.
 #[derive(Clone)]
 pub struct MerkleConfig;
 impl merkle_tree::Config for MerkleConfig {
@@ -78,43 +282,159 @@ impl merkle_tree::Config for MerkleConfig {
     type TwoToOneHash = TwoToOneHash;
 }
 
-/// A Merkle tree containing account information.
-pub type AccMerkleTree = MerkleTree<MerkleConfig>;
+/// The Pedersen parameters are serialized as their generators.
+impl AccMerkleConfig for MerkleConfig {
+    type AccLeafHash = LeafHash;
+    type AccTwoToOneHash = TwoToOneHash;
+    type SerializedParameters = (Vec<Vec<EdwardsProjective>>, Vec<Vec<EdwardsProjective>>);
+
+    fn serialize_crh_params(
+        leaf_crh_params: &pedersen::Parameters<EdwardsProjective>,
+        two_to_one_crh_params: &pedersen::Parameters<EdwardsProjective>,
+    ) -> Self::SerializedParameters {
+        (
+            leaf_crh_params.generators.clone(),
+            two_to_one_crh_params.generators.clone(),
+        )
+    }
+
+    fn deserialize_crh_params(
+        (leaf_crh_generators, two_to_one_crh_generators): Self::SerializedParameters,
+    ) -> Result<
+        (
+            pedersen::Parameters<EdwardsProjective>,
+            pedersen::Parameters<EdwardsProjective>,
+        ),
+        SerializationError,
+    > {
+        let leaf_crh_params = pedersen::Parameters {
+            generators: leaf_crh_generators,
+        };
+        let two_to_one_crh_params = pedersen::Parameters {
+            generators: two_to_one_crh_generators,
+        };
+        check_crh_params::<LeafWindow>(&leaf_crh_params)?;
+        check_crh_params::<TwoToOneWindow>(&two_to_one_crh_params)?;
+        Ok((leaf_crh_params, two_to_one_crh_params))
+    }
+}
+
+/// A Poseidon-based alternative to `LeafHash`, which is much cheaper to evaluate
+/// in a circuit. Its input has the same size as that of `LeafHash`.
+pub type PoseidonLeafHash = poseidon::CRH<Fq, LEAF_HASH_INPUT_BYTES>;
+/// A Poseidon-based alternative to `TwoToOneHash`.
+pub type PoseidonTwoToOneHash = poseidon::TwoToOneCRH<Fq>;
+
+/// A Merkle tree configuration that hashes with Poseidon instead of Pedersen. It
+/// can be used in place of `MerkleConfig`, for example in a `SparseMerkleTree`, or
+/// as the account tree configuration of `Parameters` and `State`.
+#[derive(Clone)]
+pub struct PoseidonMerkleConfig;
+impl merkle_tree::Config for PoseidonMerkleConfig {
+    type LeafHash = PoseidonLeafHash;
+    type TwoToOneHash = PoseidonTwoToOneHash;
+}
+
+/// The Poseidon parameters are fixed, so nothing is serialized.
+impl AccMerkleConfig for PoseidonMerkleConfig {
+    type AccLeafHash = PoseidonLeafHash;
+    type AccTwoToOneHash = PoseidonTwoToOneHash;
+    type SerializedParameters = ();
+
+    fn serialize_crh_params(
+        _leaf_crh_params: &poseidon::PoseidonParameters<Fq>,
+        _two_to_one_crh_params: &poseidon::PoseidonParameters<Fq>,
+    ) {
+    }
+
+    fn deserialize_crh_params(
+        _: (),
+    ) -> Result<
+        (
+            poseidon::PoseidonParameters<Fq>,
+            poseidon::PoseidonParameters<Fq>,
+        ),
+        SerializationError,
+    > {
+        let params = poseidon::PoseidonParameters::default();
+        Ok((params.clone(), params))
+    }
+}
+
+/// A Merkle tree containing account information. The tree is sparse, so that its
+/// size grows with the number of accounts rather than with its capacity.
+pub type AccMerkleTree<M = MerkleConfig> = SparseMerkleTree<M>;
 /// The root of the account Merkle tree.
 pub type AccRoot = <TwoToOneHash as TwoToOneCRH>::Output;
 /// A membership proof for a given account.
-pub type AccPath = Path<MerkleConfig>;
+pub type AccPath<M = MerkleConfig> = Path<M>;
 
-#[derive(Clone)]
-pub struct State {
-    /// What is the next available account identifier?
+/// The index of the leaf of the account tree that stores the next available
+/// account identifier. Account identifiers start at 1, so no account uses it.
+pub const NEXT_ACCOUNT_LEAF_INDEX: usize = 0;
+
+/// Encode the next available account identifier as the contents of the leaf
+/// `NEXT_ACCOUNT_LEAF_INDEX`. Running out of identifiers is encoded as
+/// identifier 0, which no account uses.
+pub fn next_account_leaf(next_available_account: Option<AccountId>) -> Vec<u8> {
+    next_available_account.unwrap_or(AccountId(0)).to_bytes_le()
+}
+
+/// The state of a ledger whose transactions are signed with the signature scheme `S`,
+/// and whose account tree is configured by `M`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct State<S: SignatureScheme = DefaultSignatureScheme, M: AccMerkleConfig = MerkleConfig> {
+    /// What is the next available account identifier? The account tree commits to
+    /// this in leaf `NEXT_ACCOUNT_LEAF_INDEX`, so it should only be changed through
+    /// `register`.
     pub next_available_account: Option<AccountId>,
     /// A merkle tree mapping where the i-th leaf corresponds to the i-th account's
-    /// information (= balance and public key).
-    pub account_merkle_tree: AccMerkleTree,
+    /// information (= balance and public key). Leaf 0, which no account uses,
+    /// stores the next available account identifier instead.
+    pub account_merkle_tree: AccMerkleTree<M>,
     /// A mapping from an account's identifier to its information (= balance and public key).
-    pub id_to_account_info: HashMap<AccountId, AccountInformation>,
-    /// A mapping from a public key to an account's identifier.
-    pub pub_key_to_id: HashMap<schnorr::PublicKey<EdwardsProjective>, AccountId>,
+    pub id_to_account_info: HashMap<AccountId, AccountInformation<S>>,
+    /// A mapping from a public key to an account's identifier. Accounts have
+    /// distinct public keys, so this is the inverse of `id_to_account_info`.
+    pub pub_key_to_id: HashMap<AccountPublicKey<S>, AccountId>,
+    /// The account that receives transaction fees, if any.
+    pub fee_collector: Option<AccountId>,
+    /// The parameters of the leaf hash of `account_merkle_tree`. These are kept so
+    /// that the tree can be rebuilt when the state is deserialized.
+    pub leaf_crh_params: <M::LeafHash as CRH>::Parameters,
+    /// The parameters of the two-to-one hash of `account_merkle_tree`.
+    pub two_to_one_crh_params: <M::TwoToOneHash as TwoToOneCRH>::Parameters,
 }
 
-impl State {
+impl<S: SignatureScheme, M: AccMerkleConfig> State<S, M> {
     /// Create an empty ledger that supports `num_accounts` accounts.
-    pub fn new(num_accounts: usize, parameters: &Parameters) -> Self {
-        let height = ark_std::log2(num_accounts);
-        let account_merkle_tree = MerkleTree::blank(
+    /// The height of the account tree is capped at `AccountId::MAX_TREE_HEIGHT`.
+    pub fn new(num_accounts: usize, parameters: &Parameters<S, M>) -> Self {
+        let height = (ark_std::log2(num_accounts) as usize).min(AccountId::MAX_TREE_HEIGHT);
+        let mut account_merkle_tree = AccMerkleTree::<M>::blank(
             &parameters.leaf_crh_params,
             &parameters.two_to_one_crh_params,
-            height as usize,
+            height,
         )
         .unwrap();
-        let pub_key_to_id = HashMap::with_capacity(num_accounts);
-        let id_to_account_info = HashMap::with_capacity(num_accounts);
+        let next_available_account = Some(AccountId(1));
+        account_merkle_tree
+            .update(
+                NEXT_ACCOUNT_LEAF_INDEX,
+                &next_account_leaf(next_available_account),
+            )
+            .expect("should exist");
+        let pub_key_to_id = HashMap::new();
+        let id_to_account_info = HashMap::new();
         Self {
-            next_available_account: Some(AccountId(1)),
+            next_available_account,
             account_merkle_tree,
             id_to_account_info,
             pub_key_to_id,
+            fee_collector: None,
+            leaf_crh_params: parameters.leaf_crh_params.clone(),
+            two_to_one_crh_params: parameters.two_to_one_crh_params.clone(),
         }
     }
 
@@ -123,78 +443,410 @@ impl State {
         self.account_merkle_tree.root()
     }
 
+    /// Check that `path` authenticates `old_info` as the information of the account
+    /// `id` under the current root, and return the root of the tree in which
+    /// `new_info` replaces it, computed over the same siblings. This is the native
+    /// counterpart of the rollup's `MerkleUpdateGadget`. Returns
+    /// `Error::MerklePathInvalid` if `path` does not authenticate `old_info` at the
+    /// leaf of `id`.
+    pub fn updated_root(
+        &self,
+        id: AccountId,
+        path: &AccPath<M>,
+        old_info: &AccountInformation<S>,
+        new_info: &AccountInformation<S>,
+    ) -> Result<AccRoot, Error> {
+        let tree = &self.account_merkle_tree;
+        let old_root = tree
+            .root_from_path(path, &old_info.to_bytes_le())
+            .map_err(|_| Error::MerklePathInvalid)?;
+        if Some(path.leaf_index) != id.leaf_index() || old_root != self.root() {
+            return Err(Error::MerklePathInvalid);
+        }
+        tree.root_from_path(path, &new_info.to_bytes_le())
+            .map_err(|_| Error::MerklePathInvalid)
+    }
+
+    /// Return the number of leaves in the account Merkle tree. Only accounts whose
+    /// identifier is smaller than this can be stored in the ledger.
+    pub fn capacity(&self) -> usize {
+        let height = self.account_merkle_tree.height() as u32;
+        1usize.checked_shl(height - 1).unwrap_or(usize::MAX)
+    }
+
     /// Create a new account with public key `pub_key`. Returns a fresh account identifier
-    /// if there is space for a new account, and returns `None` otherwise.
+    /// if there is space for a new account, and returns `Error::LedgerFull` otherwise.
+    /// Returns `Error::DuplicatePublicKey` if an account already has `pub_key`.
+    /// Returns `Error::InvalidPublicKey` if `S::is_valid_public_key` rejects `pub_key`.
     /// The initial balance of the new account is 0.
-    pub fn register(&mut self, public_key: AccountPublicKey) -> Option<AccountId> {
-        self.next_available_account.and_then(|id| {
-            // Construct account information for the new account.
-            let account_info = AccountInformation {
-                public_key,
-                balance: Amount(0),
-            };
-            // Insert information into the relevant accounts.
-            self.pub_key_to_id.insert(public_key, id);
-            self.account_merkle_tree
-                .update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
-            self.id_to_account_info.insert(id, account_info);
-            // Increment the next account identifier.
-            self.next_available_account
-                .as_mut()
-                .and_then(|cur| cur.checked_increment())?;
-            Some(id)
-        })
+    pub fn register(&mut self, public_key: AccountPublicKey<S>) -> Result<AccountId, Error> {
+        let mut overlay = self.overlay();
+        let id = overlay.register(public_key)?;
+        let diff = overlay.into_diff();
+        self.commit(diff).expect("the diff was made for this state");
+        Ok(id)
     }
 
     /// Samples keys and registers these in the ledger.
     pub fn sample_keys_and_register<R: Rng>(
         &mut self,
-        ledger_params: &Parameters,
+        ledger_params: &Parameters<S, M>,
         rng: &mut R,
-    ) -> Option<(AccountId, AccountPublicKey, AccountSecretKey)> {
-        let (pub_key, secret_key) =
-            schnorr::Schnorr::keygen(&ledger_params.sig_params, rng).unwrap();
-        self.register(pub_key).map(|id| (id, pub_key, secret_key))
+    ) -> Result<(AccountId, AccountPublicKey<S>, AccountSecretKey<S>), Error> {
+        let (pub_key, secret_key) = S::keygen(&ledger_params.sig_params, rng).unwrap();
+        self.register(pub_key.clone())
+            .map(|id| (id, pub_key, secret_key))
+    }
+
+    /// Make `id` the account that receives transaction fees.
+    /// Returns `Error::UnknownAccount` if no account has identifier `id`.
+    pub fn set_fee_collector(&mut self, id: AccountId) -> Result<(), Error> {
+        if !self.id_to_account_info.contains_key(&id) {
+            return Err(Error::UnknownAccount(id));
+        }
+        self.fee_collector = Some(id);
+        Ok(())
     }
 
     /// Update the balance of `id` to `new_amount`.
-    /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
-    /// otherwise.
-    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Option<()> {
+    /// Returns `Error::UnknownAccount` if no account has identifier `id`.
+    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Result<(), Error> {
         let tree = &mut self.account_merkle_tree;
-        self.id_to_account_info.get_mut(&id).map(|account_info| {
-            account_info.balance = new_amount;
-            tree.update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
-        })
+        let account_info = self
+            .id_to_account_info
+            .get_mut(&id)
+            .ok_or(Error::UnknownAccount(id))?;
+        let index = id.leaf_index().ok_or(Error::UnknownAccount(id))?;
+        account_info.balance = new_amount;
+        tree.update(index, &account_info.to_bytes_le())
+            .expect("should exist");
+        Ok(())
+    }
+
+    /// Return a view of the state to which changes can be made without changing
+    /// the state itself. See `StateOverlay`.
+    pub fn overlay(&self) -> StateOverlay<'_, S, M> {
+        StateOverlay::new(self)
+    }
+
+    /// Apply the changes in `diff`, which were made through an overlay of this state.
+    /// Returns `Error::StaleDiff`, and leaves the state unchanged, if the state has
+    /// changed since the overlay was created.
+    pub fn commit(&mut self, diff: StateDiff<S, M>) -> Result<(), Error> {
+        if diff.base_root != self.root() {
+            return Err(Error::StaleDiff);
+        }
+        for (id, account_info) in diff.accounts {
+            let public_key = account_info.public_key.clone();
+            if let Some(old_info) = self.id_to_account_info.insert(id, account_info) {
+                self.pub_key_to_id.remove(&old_info.public_key);
+            }
+            self.pub_key_to_id.insert(public_key, id);
+        }
+        self.next_available_account = diff.next_available_account;
+        self.account_merkle_tree.commit(diff.account_merkle_tree);
+        Ok(())
     }
 
     /// Update the state by applying the transaction `tx`, if `tx` is valid.
-    pub fn apply_transaction(&mut self, pp: &Parameters, tx: &Transaction) -> Option<()> {
-        if tx.validate(pp, self) {
-            let old_sender_bal = self.id_to_account_info.get(&tx.sender)?.balance;
-            let old_receiver_bal = self.id_to_account_info.get(&tx.recipient)?.balance;
-            let new_sender_bal = old_sender_bal.checked_sub(tx.amount)?;
-            let new_receiver_bal = old_receiver_bal.checked_add(tx.amount)?;
-            self.update_balance(tx.sender, new_sender_bal);
-            self.update_balance(tx.recipient, new_receiver_bal);
-            Some(())
-        } else {
-            None
+    /// Applying a transaction increments the sender's nonce, and pays the
+    /// transaction fee to the fee collector. If `tx` is invalid, the state is
+    /// unchanged and the reason is returned.
+    pub fn apply_transaction(
+        &mut self,
+        pp: &Parameters<S, M>,
+        tx: &Transaction<S>,
+    ) -> Result<(), Error> {
+        let mut overlay = self.overlay();
+        overlay.apply_transaction(pp, tx)?;
+        let diff = overlay.into_diff();
+        self.commit(diff).expect("the diff was made for this state");
+        Ok(())
+    }
+
+    /// Update the state by applying `transactions`, in order. The signatures of
+    /// all the transactions are verified together, before any is applied. With
+    /// Schnorr signatures, this takes two multi-scalar multiplications rather than
+    /// two scalar multiplications per transaction.
+    /// If a transaction is invalid, the transactions before it remain applied,
+    /// and its index is returned together with the reason.
+    pub fn apply_transactions(
+        &mut self,
+        pp: &Parameters<S, M>,
+        transactions: &[Transaction<S>],
+    ) -> Result<(), (usize, Error)> {
+        let mut overlay = self.overlay();
+        let result = overlay.apply_transactions(pp, transactions);
+        let diff = overlay.into_diff();
+        self.commit(diff).expect("the diff was made for this state");
+        result
+    }
+
+    /// Update the state by applying the deposit or withdrawal `operation`, if
+    /// `operation` is valid. Applying a withdrawal increments the account's nonce.
+    /// If `operation` is invalid, the state is unchanged and the reason is returned.
+    pub fn apply_operation(
+        &mut self,
+        pp: &Parameters<S, M>,
+        operation: &Operation<S>,
+    ) -> Result<(), Error> {
+        let mut overlay = self.overlay();
+        overlay.apply_operation(pp, operation)?;
+        let diff = overlay.into_diff();
+        self.commit(diff).expect("the diff was made for this state");
+        Ok(())
+    }
+}
+
+/// The serialized form of `State`. The Merkle tree and the public key index are
+/// not serialized, as they are recomputed from the accounts.
+#[derive(CanonicalSerialize, CanonicalDeserialize)]
+struct SerializedState<S: SignatureScheme, M: AccMerkleConfig> {
+    crh_params: M::SerializedParameters,
+    tree_height: u64,
+    next_available_account: Option<AccountId>,
+    fee_collector: Option<AccountId>,
+    /// The accounts, sorted by identifier.
+    accounts: Vec<(AccountId, AccountInformation<S>)>,
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> From<&State<S, M>> for SerializedState<S, M> {
+    fn from(state: &State<S, M>) -> Self {
+        let mut accounts = state
+            .id_to_account_info
+            .iter()
+            .map(|(id, account_info)| (*id, account_info.clone()))
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|(id, _)| *id);
+        Self {
+            crh_params: M::serialize_crh_params(
+                &state.leaf_crh_params,
+                &state.two_to_one_crh_params,
+            ),
+            tree_height: state.account_merkle_tree.height() as u64,
+            next_available_account: state.next_available_account,
+            fee_collector: state.fee_collector,
+            accounts,
         }
     }
 }
 
+impl<S: SignatureScheme, M: AccMerkleConfig> SerializedState<S, M> {
+    /// Rebuild the ledger, checking that the serialized parts are consistent:
+    /// the hash parameters must be well-formed, accounts must be sorted, have
+    /// distinct public keys and fit in the tree, the next available account
+    /// identifier must follow all of them, and the fee collector must exist.
+    fn into_state(self) -> Result<State<S, M>, SerializationError> {
+        let (leaf_crh_params, two_to_one_crh_params) = M::deserialize_crh_params(self.crh_params)?;
+
+        // A taller tree would only add leaves that no account identifier can address.
+        // As the tree is sparse, its height does not affect its size.
+        if self.tree_height < 2 || self.tree_height > AccountId::MAX_TREE_HEIGHT as u64 {
+            return Err(SerializationError::InvalidData);
+        }
+        let tree_height = self.tree_height as usize;
+        let mut account_merkle_tree =
+            AccMerkleTree::<M>::blank(&leaf_crh_params, &two_to_one_crh_params, tree_height)
+                .map_err(|_| SerializationError::InvalidData)?;
+
+        account_merkle_tree
+            .update(
+                NEXT_ACCOUNT_LEAF_INDEX,
+                &next_account_leaf(self.next_available_account),
+            )
+            .map_err(|_| SerializationError::InvalidData)?;
+
+        let mut id_to_account_info = HashMap::with_capacity(self.accounts.len());
+        let mut pub_key_to_id = HashMap::with_capacity(self.accounts.len());
+        let mut previous_id = AccountId(0);
+        for (id, account_info) in self.accounts {
+            // Identifiers start at 1, and must be strictly increasing. Identifiers
+            // that do not fit in the tree are rejected by `update` below.
+            if id <= previous_id {
+                return Err(SerializationError::InvalidData);
+            }
+            previous_id = id;
+            if pub_key_to_id
+                .insert(account_info.public_key.clone(), id)
+                .is_some()
+            {
+                return Err(SerializationError::InvalidData);
+            }
+            account_merkle_tree
+                .update(
+                    id.leaf_index().ok_or(SerializationError::InvalidData)?,
+                    &account_info.to_bytes_le(),
+                )
+                .map_err(|_| SerializationError::InvalidData)?;
+            id_to_account_info.insert(id, account_info);
+        }
+        // `register` hands out identifiers in order, so the next one is greater than
+        // every stored identifier, and there is none only once the last identifier
+        // is taken. Otherwise, the next registration would overwrite an account.
+        let next_ok = match self.next_available_account {
+            Some(next_id) => next_id > previous_id,
+            None => previous_id == AccountId(AccountIdRepr::MAX),
+        };
+        if !next_ok {
+            return Err(SerializationError::InvalidData);
+        }
+        if let Some(fee_collector) = self.fee_collector {
+            if !id_to_account_info.contains_key(&fee_collector) {
+                return Err(SerializationError::InvalidData);
+            }
+        }
+
+        Ok(State {
+            next_available_account: self.next_available_account,
+            account_merkle_tree,
+            id_to_account_info,
+            pub_key_to_id,
+            fee_collector: self.fee_collector,
+            leaf_crh_params,
+            two_to_one_crh_params,
+        })
+    }
+}
+
+// `State` is serialized as its hash parameters, the tree height, the account
+// counters and the sorted list of accounts.
+impl<S: SignatureScheme, M: AccMerkleConfig> CanonicalSerialize for State<S, M> {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        SerializedState::from(self).serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        SerializedState::from(self).serialized_size()
+    }
+
+    fn serialize_uncompressed<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        SerializedState::from(self).serialize_uncompressed(writer)
+    }
+
+    fn serialize_unchecked<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        SerializedState::from(self).serialize_unchecked(writer)
+    }
+
+    fn uncompressed_size(&self) -> usize {
+        SerializedState::from(self).uncompressed_size()
+    }
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> CanonicalDeserialize for State<S, M> {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        SerializedState::<S, M>::deserialize(reader)?.into_state()
+    }
+
+    fn deserialize_uncompressed<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        SerializedState::<S, M>::deserialize_uncompressed(reader)?.into_state()
+    }
+
+    fn deserialize_unchecked<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        SerializedState::<S, M>::deserialize_unchecked(reader)?.into_state()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{AccountId, Amount, Parameters, State};
+    use super::{
+        next_account_leaf, AccMerkleConfig, AccountId, Amount, ChainId, DefaultSignatureScheme,
+        MerkleConfig, Parameters, PoseidonMerkleConfig, State, NEXT_ACCOUNT_LEAF_INDEX,
+    };
+    use crate::account::Nonce;
+    use crate::curve::{EdwardsAffine, EdwardsProjective, Fq};
+    use crate::message;
+    use crate::operation::{Operation, Withdrawal};
+    use crate::random_oracle::poseidon;
+    use crate::signature::{schnorr::Schnorr, threshold, SignatureScheme};
     use crate::transaction::Transaction;
+    use crate::Error;
+    use ark_ff::{One, Zero};
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 
     #[test]
     fn end_to_end() {
+        transfers::<DefaultSignatureScheme, MerkleConfig>();
+    }
+
+    #[test]
+    fn end_to_end_with_another_signature_scheme() {
+        type PoseidonSchnorr = Schnorr<EdwardsProjective, poseidon::RO<Fq>>;
+        let state = transfers::<PoseidonSchnorr, MerkleConfig>();
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        let restored = State::<PoseidonSchnorr>::deserialize(&bytes[..]).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.pub_key_to_id, state.pub_key_to_id);
+    }
+
+    #[test]
+    fn end_to_end_with_a_poseidon_account_tree() {
+        let state = transfers::<DefaultSignatureScheme, PoseidonMerkleConfig>();
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        let restored =
+            State::<DefaultSignatureScheme, PoseidonMerkleConfig>::deserialize(&bytes[..]).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.id_to_account_info, state.id_to_account_info);
+    }
+
+    #[test]
+    fn two_of_three_treasury() {
+        type Threshold = threshold::Threshold<DefaultSignatureScheme, Fq, 3>;
+        transfers::<Threshold, MerkleConfig>();
+
         let mut rng = ark_std::test_rng();
-        let pp = Parameters::sample(&mut rng);
+        let pp = Parameters::<Threshold>::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        // The treasury is controlled by three officers, any two of which can spend.
+        let (pk, sk) = Threshold::keygen(&pp.sig_params, &mut rng).unwrap();
+        let pk = threshold::PublicKey::new(2, pk.keys).unwrap();
+        let officer_sks = (0..3)
+            .map(|i| {
+                let mut officer_sk = threshold::SecretKey::default();
+                officer_sk.keys[i] = sk.keys[i].clone();
+                officer_sk
+            })
+            .collect::<Vec<_>>();
+        let treasury_id = state.register(pk).unwrap();
+        state.update_balance(treasury_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let partial_txs = officer_sks
+            .iter()
+            .map(|officer_sk| {
+                Transaction::create(
+                    &pp,
+                    treasury_id,
+                    bob_id,
+                    Amount(5),
+                    Nonce(0),
+                    officer_sk,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+        // A single officer cannot spend.
+        for tx in &partial_txs {
+            assert_eq!(tx.validate(&pp, &state), Err(Error::BadSignature));
+            assert_eq!(state.apply_transaction(&pp, tx), Err(Error::BadSignature));
+        }
+        // Two officers can, by combining their signatures.
+        let mut tx = partial_txs[0].clone();
+        tx.signature = threshold::Signature::combine(&[
+            partial_txs[0].signature.clone(),
+            partial_txs[2].signature.clone(),
+        ]);
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+        assert_eq!(state.id_to_account_info[&treasury_id].nonce, Nonce(1));
+    }
+
+    /// Register Alice and Bob in a ledger whose transactions are signed with `S`,
+    /// and check which transfers between them are accepted.
+    fn transfers<S: SignatureScheme, M: AccMerkleConfig>() -> State<S, M> {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<S, M>::sample(&mut rng);
         let mut state = State::new(32, &pp);
         // Let's make an account for Alice.
         let (alice_id, _alice_pk, alice_sk) =
@@ -207,23 +859,675 @@ mod test {
         let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
-        assert!(tx1.validate(&pp, &state));
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(tx1.validate(&pp, &state).is_ok());
         state.apply_transaction(&pp, &tx1).expect("should work");
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
-        assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(6),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            bad_tx.validate(&pp, &state),
+            Err(Error::InsufficientBalance)
+        );
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(Error::InsufficientBalance)
+        );
         // Next, let's try a transaction where the signature is incorrect:
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
-        assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(1),
+            &bob_sk,
+            &mut rng,
+        );
+        assert_eq!(bad_tx.validate(&pp, &state), Err(Error::BadSignature));
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(Error::BadSignature)
+        );
 
         // Finally, let's try a transaction to an non-existant account:
-        let bad_tx =
-            Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &mut rng);
-        assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            AccountId(10),
+            Amount(5),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            bad_tx.validate(&pp, &state),
+            Err(Error::UnknownRecipient(AccountId(10)))
+        );
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(Error::UnknownRecipient(AccountId(10)))
+        );
+        state
+    }
+
+    #[test]
+    fn batches_of_transactions() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let mut txs = (0..4)
+            .map(|i| {
+                Transaction::create(
+                    &pp,
+                    alice_id,
+                    bob_id,
+                    Amount(1),
+                    Nonce(i),
+                    &alice_sk,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+        // Bob pays Alice back with funds he receives earlier in the batch.
+        txs.push(Transaction::create(
+            &pp,
+            bob_id,
+            alice_id,
+            Amount(3),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        ));
+        let mut sequential_state = state.clone();
+        for tx in &txs {
+            sequential_state.apply_transaction(&pp, tx).unwrap();
+        }
+        let mut batch_state = state.clone();
+        batch_state.apply_transactions(&pp, &txs).unwrap();
+        assert_eq!(batch_state.root(), sequential_state.root());
+
+        // A bad signature is reported, and the transactions before it are applied.
+        let mut bad_txs = txs.clone();
+        bad_txs[2] = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(1),
+            Nonce(2),
+            &bob_sk,
+            &mut rng,
+        );
+        let mut batch_state = state.clone();
+        assert_eq!(
+            batch_state.apply_transactions(&pp, &bad_txs),
+            Err((2, Error::BadSignature))
+        );
+        assert_eq!(batch_state.id_to_account_info[&bob_id].balance, Amount(2));
+        // So are other invalid transactions: Bob cannot pay 3 with only 2 units.
+        let bad_txs = [txs[0].clone(), txs[1].clone(), txs[4].clone()];
+        let mut batch_state = state.clone();
+        assert_eq!(
+            batch_state.apply_transactions(&pp, &bad_txs),
+            Err((2, Error::InsufficientBalance))
+        );
+        // Replayed transactions have a valid signature, but are still rejected.
+        assert_eq!(
+            state.apply_transactions(&pp, &[txs[0].clone(), txs[0].clone()]),
+            Err((1, Error::BadNonce))
+        );
+    }
+
+    #[test]
+    fn replayed_transactions_are_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(2),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&alice_id].nonce, Nonce(1));
+        assert_eq!(state.id_to_account_info[&bob_id].nonce, Nonce(0));
+
+        // Applying the same signed transaction a second time must fail.
+        assert_eq!(tx.validate(&pp, &state), Err(Error::BadNonce));
+        assert_eq!(state.apply_transaction(&pp, &tx), Err(Error::BadNonce));
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(8));
+
+        // So must a transaction that skips ahead of the current nonce.
+        let future_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(2),
+            Nonce(2),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(future_tx.validate(&pp, &state), Err(Error::BadNonce));
+
+        // A fresh transaction with the next nonce goes through.
+        let next_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(2),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &next_tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(6));
+        assert_eq!(state.id_to_account_info[&alice_id].nonce, Nonce(2));
+    }
+
+    #[test]
+    fn signatures_are_bound_to_the_chain() {
+        let mut rng = ark_std::test_rng();
+        let test_pp: Parameters = Parameters::sample(&mut rng);
+        let mut prod_pp = test_pp.clone();
+        prod_pp.chain_id = ChainId(1);
+        prod_pp.domain_tag = message::domain_tag(&prod_pp);
+        assert_eq!(test_pp.domain_tag, message::domain_tag(&test_pp));
+        assert_ne!(test_pp.domain_tag, prod_pp.domain_tag);
+
+        // Alice and Bob use the same keys on both ledgers.
+        let mut test_state = State::new(32, &test_pp);
+        let (alice_id, alice_pk, alice_sk) = test_state
+            .sample_keys_and_register(&test_pp, &mut rng)
+            .unwrap();
+        test_state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = test_state
+            .sample_keys_and_register(&test_pp, &mut rng)
+            .unwrap();
+        let mut prod_state = State::new(32, &prod_pp);
+        assert_eq!(prod_state.register(alice_pk).unwrap(), alice_id);
+        prod_state.update_balance(alice_id, Amount(10)).unwrap();
+        assert_eq!(prod_state.register(bob_pk).unwrap(), bob_id);
+
+        // A transaction signed for the test ledger cannot be replayed on the
+        // production ledger, and vice versa.
+        let test_tx = Transaction::create(
+            &test_pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(test_tx.validate(&test_pp, &test_state).is_ok());
+        assert_eq!(
+            test_tx.validate(&prod_pp, &prod_state),
+            Err(Error::BadSignature)
+        );
+        let prod_tx = Transaction::create(
+            &prod_pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            prod_tx.validate(&test_pp, &test_state),
+            Err(Error::BadSignature)
+        );
+        assert!(prod_tx.validate(&prod_pp, &prod_state).is_ok());
+        assert_eq!(
+            prod_state.apply_transactions(&prod_pp, &[test_tx]),
+            Err((0, Error::BadSignature))
+        );
+
+        // Neither can a withdrawal.
+        let withdrawal = Operation::Withdrawal(Withdrawal::create(
+            &test_pp,
+            alice_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        ));
+        assert_eq!(
+            prod_state.apply_operation(&prod_pp, &withdrawal),
+            Err(Error::BadSignature)
+        );
+        test_state.apply_operation(&test_pp, &withdrawal).unwrap();
+
+        // The chain identifier survives serialization.
+        let mut bytes = Vec::new();
+        prod_pp.serialize(&mut bytes).unwrap();
+        let restored = Parameters::<DefaultSignatureScheme>::deserialize(&bytes[..]).unwrap();
+        assert_eq!(restored.chain_id, ChainId(1));
+        assert_eq!(restored.domain_tag, prod_pp.domain_tag);
+    }
+
+    #[test]
+    fn fees_are_paid_to_the_fee_collector() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (collector_id, _c_pk, _c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        // Without a fee collector, nobody can receive the fee.
+        assert_eq!(tx.validate(&pp, &state), Err(Error::NoFeeCollector));
+        assert_eq!(
+            state.set_fee_collector(AccountId(10)),
+            Err(Error::UnknownAccount(AccountId(10)))
+        );
+        state.set_fee_collector(collector_id).unwrap();
+        assert!(tx.validate(&pp, &state).is_ok());
+
+        // The fee is covered by the signature.
+        let mut bad_tx = tx.clone();
+        bad_tx.fee = Amount(0);
+        assert_eq!(bad_tx.validate(&pp, &state), Err(Error::BadSignature));
+
+        // The sender must be able to pay for both the amount and the fee.
+        let bad_tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            Amount(6),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            bad_tx.validate(&pp, &state),
+            Err(Error::InsufficientBalance)
+        );
+
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(4));
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+        assert_eq!(state.id_to_account_info[&collector_id].balance, Amount(1));
+
+        // Paying the collector directly only charges the sender once.
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            collector_id,
+            Amount(2),
+            Nonce(1),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(1));
+        assert_eq!(state.id_to_account_info[&collector_id].balance, Amount(4));
+    }
+
+    #[test]
+    fn self_transfers_only_pay_the_fee() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (collector_id, _c_pk, _c_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(collector_id).unwrap();
+
+        // The amount is debited and credited back, so only the fee and the nonce
+        // change. It must still be covered by the balance.
+        let tx = |amount, nonce, rng: &mut _| {
+            Transaction::create_with_fee(
+                &pp,
+                alice_id,
+                alice_id,
+                Amount(amount),
+                Nonce(nonce),
+                Amount(1),
+                &alice_sk,
+                rng,
+            )
+        };
+        assert_eq!(
+            state.apply_transaction(&pp, &tx(10, 0, &mut rng)),
+            Err(Error::InsufficientBalance)
+        );
+        let txs = [tx(9, 0, &mut rng), tx(8, 1, &mut rng)];
+        state.apply_transactions(&pp, &txs).unwrap();
+        let alice_info = &state.id_to_account_info[&alice_id];
+        assert_eq!(alice_info.balance, Amount(8));
+        assert_eq!(alice_info.nonce, Nonce(2));
+        assert_eq!(state.id_to_account_info[&collector_id].balance, Amount(2));
+    }
+
+    #[test]
+    fn more_than_256_accounts() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(1024, &pp);
+        assert_eq!(state.capacity(), 512);
+        let mut accounts = Vec::new();
+        for _ in 0..300 {
+            accounts.push(state.sample_keys_and_register(&pp, &mut rng).unwrap());
+        }
+        let (alice_id, _alice_pk, alice_sk) = accounts.pop().unwrap();
+        assert_eq!(alice_id, AccountId(300));
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let bob_id = accounts[256].0;
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+
+        // The identifiers are signed in full, so the transaction cannot be
+        // redirected to an account whose identifier shares its low byte.
+        let mut bad_tx = tx.clone();
+        bad_tx.nonce = Nonce(1);
+        bad_tx.recipient = AccountId(bob_id.0 - 256);
+        assert_eq!(bad_tx.validate(&pp, &state), Err(Error::BadSignature));
+    }
+
+    #[test]
+    fn large_id_spaces_are_cheap() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(1 << 33, &pp);
+        assert_eq!(state.capacity(), 1 << 32);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Only the leaves of registered accounts and the account counter are stored.
+        assert_eq!(state.account_merkle_tree.num_non_empty_leaves(), 3);
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+    }
+
+    #[test]
+    fn registration_stops_when_the_tree_is_full() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(8, &pp);
+        // Leaf 0 holds the account counter, so only three of the four leaves hold accounts.
+        for i in 1..4 {
+            let (id, _pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            assert_eq!(id, AccountId(i));
+        }
+        assert!(matches!(
+            state.sample_keys_and_register(&pp, &mut rng),
+            Err(Error::LedgerFull)
+        ));
+    }
+
+    #[test]
+    fn public_keys_are_registered_once() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(state.register(alice_pk), Err(Error::DuplicatePublicKey));
+        // Nor can a key be registered twice in the same batch.
+        let (bob_pk, _bob_sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut overlay = state.overlay();
+        overlay.register(bob_pk).unwrap();
+        assert_eq!(overlay.register(bob_pk), Err(Error::DuplicatePublicKey));
+        // Nor can a key outside the prime-order subgroup, whose signatures the
+        // circuits would check against its projection onto the subgroup.
+        let order_two_point = EdwardsAffine::new(Fq::zero(), -Fq::one());
+        assert_eq!(
+            overlay.register(bob_pk + order_two_point),
+            Err(Error::InvalidPublicKey)
+        );
+
+        // Every state that registration can reach round-trips through serialization.
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        let restored = State::<DefaultSignatureScheme>::deserialize(&bytes[..]).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.pub_key_to_id[&alice_pk], alice_id);
+    }
+
+    #[test]
+    fn root_commits_to_the_account_counter() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(8, &pp);
+        let counter_leaf = next_account_leaf(Some(AccountId(1)));
+        let path = state
+            .account_merkle_tree
+            .generate_proof(NEXT_ACCOUNT_LEAF_INDEX)
+            .unwrap();
+        assert!(path
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &counter_leaf
+            )
+            .unwrap());
+
+        // Registering an account updates the counter leaf.
+        let (_id, _pk, _sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let path = state
+            .account_merkle_tree
+            .generate_proof(NEXT_ACCOUNT_LEAF_INDEX)
+            .unwrap();
+        assert!(!path
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &counter_leaf
+            )
+            .unwrap());
+        assert!(path
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &next_account_leaf(Some(AccountId(2)))
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn updated_roots_match_applied_changes() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let path = state
+            .account_merkle_tree
+            .generate_proof(alice_id.leaf_index().unwrap())
+            .unwrap();
+        let old_info = state.id_to_account_info[&alice_id].clone();
+        let mut new_info = old_info.clone();
+        new_info.balance = Amount(7);
+        let new_root = state
+            .updated_root(alice_id, &path, &old_info, &new_info)
+            .unwrap();
+        // The path is bound to Alice's leaf, and to her current information.
+        assert_eq!(
+            state.updated_root(bob_id, &path, &old_info, &new_info),
+            Err(Error::MerklePathInvalid)
+        );
+        assert_eq!(
+            state.updated_root(alice_id, &path, &new_info, &old_info),
+            Err(Error::MerklePathInvalid)
+        );
+
+        state.update_balance(alice_id, Amount(7)).unwrap();
+        assert_eq!(state.root(), new_root);
+    }
+
+    #[test]
+    fn serialization_round_trips() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(bob_id).unwrap();
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+
+        // Parameters round-trip in both encodings.
+        let mut compressed = Vec::new();
+        pp.serialize(&mut compressed).unwrap();
+        let mut uncompressed = Vec::new();
+        pp.serialize_uncompressed(&mut uncompressed).unwrap();
+        assert_eq!(compressed.len(), pp.serialized_size());
+        assert_eq!(uncompressed.len(), pp.uncompressed_size());
+        assert!(compressed.len() < uncompressed.len());
+        let pp = Parameters::deserialize(&compressed[..]).unwrap();
+        let pp_uncompressed: Parameters =
+            Parameters::deserialize_uncompressed(&uncompressed[..]).unwrap();
+        let mut bytes = Vec::new();
+        pp_uncompressed.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes, compressed);
+
+        // A deserialized transaction is still valid.
+        let mut bytes = Vec::new();
+        tx.serialize(&mut bytes).unwrap();
+        let tx = Transaction::deserialize(&bytes[..]).unwrap();
+        assert!(tx.validate(&pp, &state).is_ok());
+        state.apply_transaction(&pp, &tx).unwrap();
+
+        // The state round-trips in both encodings, including its Merkle root.
+        let mut compressed = Vec::new();
+        state.serialize(&mut compressed).unwrap();
+        let mut uncompressed = Vec::new();
+        state.serialize_uncompressed(&mut uncompressed).unwrap();
+        assert!(compressed.len() < uncompressed.len());
+        for restored in [
+            State::<DefaultSignatureScheme>::deserialize(&compressed[..]).unwrap(),
+            State::deserialize_uncompressed(&uncompressed[..]).unwrap(),
+        ] {
+            assert_eq!(restored.root(), state.root());
+            assert_eq!(restored.id_to_account_info, state.id_to_account_info);
+            assert_eq!(restored.pub_key_to_id, state.pub_key_to_id);
+            assert_eq!(
+                restored.next_available_account,
+                state.next_available_account
+            );
+            assert_eq!(restored.fee_collector, Some(bob_id));
+        }
+    }
+
+    #[test]
+    fn invalid_serializations_are_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let _alice = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let mut bytes = Vec::new();
+        pp.serialize(&mut bytes).unwrap();
+        assert!(
+            Parameters::<DefaultSignatureScheme>::deserialize(&bytes[..bytes.len() - 1]).is_err()
+        );
+
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        assert!(State::<DefaultSignatureScheme>::deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+        // The fee collector must be a registered account.
+        state.fee_collector = Some(AccountId(9));
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        assert!(State::<DefaultSignatureScheme>::deserialize(&bytes[..]).is_err());
+        state.fee_collector = None;
+
+        // The next account identifier must follow every registered account, or the
+        // next registration would overwrite one of them, or the account counter.
+        for next_available_account in [None, Some(AccountId(0)), Some(AccountId(1))] {
+            let mut invalid_state = state.clone();
+            invalid_state.next_available_account = next_available_account;
+            let mut bytes = Vec::new();
+            invalid_state.serialize(&mut bytes).unwrap();
+            assert!(State::<DefaultSignatureScheme>::deserialize(&bytes[..]).is_err());
+        }
+
+        // Hash parameters must have the right number of generators.
+        state.leaf_crh_params.generators.pop();
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        assert!(State::<DefaultSignatureScheme>::deserialize(&bytes[..]).is_err());
     }
 }
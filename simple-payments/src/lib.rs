@@ -1,8 +1,30 @@
 pub mod account;
+pub mod error;
 pub mod ledger;
+pub mod message;
+pub mod operation;
+pub mod overlay;
+pub mod poseidon;
+pub mod sparse_merkle_tree;
+pub mod store;
 pub mod transaction;
 
 pub mod random_oracle;
 pub mod signature;
 
+pub use error::Error;
+
+/// The twisted Edwards curve of the ledger, whose base field is the field of the
+/// account tree hashes and of the rollup circuits. It is Jubjub by default, and
+/// the Edwards curve over the scalar field of MNT4-298 if the `mnt4-298` feature
+/// is enabled.
+#[cfg(not(feature = "mnt4-298"))]
+pub use ark_ed_on_bls12_381 as curve;
+/// The twisted Edwards curve of the ledger, whose base field is the field of the
+/// account tree hashes and of the rollup circuits. It is Jubjub by default, and
+/// the Edwards curve over the scalar field of MNT4-298 if the `mnt4-298` feature
+/// is enabled.
+#[cfg(feature = "mnt4-298")]
+pub use ark_ed_on_mnt4_298 as curve;
+
 extern crate derivative;
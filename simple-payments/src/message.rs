@@ -0,0 +1,50 @@
+use crate::ledger::{AccMerkleConfig, Parameters};
+use crate::signature::SignatureScheme;
+use ark_serialize::CanonicalSerialize;
+use blake2::Blake2s;
+use digest::Digest;
+
+/// The version of the encoding of signed messages. It changes whenever the
+/// encoding does, so that a signature never verifies under two encodings.
+pub const MESSAGE_VERSION: u8 = 1;
+
+/// Identifies the ledger that a message is signed for. See `domain_tag`.
+pub type DomainTag = [u8; 32];
+
+/// The kinds of messages that account owners sign.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// Authorizes a `Transaction`.
+    Transaction = 0,
+    /// Authorizes a `Withdrawal`.
+    Withdrawal = 1,
+}
+
+impl MessageKind {
+    /// The bytes that precede the fields of a message of this kind, on the ledger
+    /// identified by `domain_tag`. Every signed message consists of
+    /// (Version || Kind || DomainTag || Fields), so a signature for one ledger, or
+    /// for one kind of message, is never valid for another.
+    pub fn prefix(self, domain_tag: &DomainTag) -> Vec<u8> {
+        let mut prefix = vec![MESSAGE_VERSION, self as u8];
+        prefix.extend_from_slice(domain_tag);
+        prefix
+    }
+}
+
+/// Compute the domain tag of the ledger with parameters `parameters`. It is the
+/// Blake2s hash of the serialized parameters, which include the chain identifier,
+/// so ledgers that differ only by their chain identifier still have different tags.
+pub fn domain_tag<S: SignatureScheme, M: AccMerkleConfig>(
+    parameters: &Parameters<S, M>,
+) -> DomainTag {
+    let mut bytes = Vec::new();
+    parameters
+        .serialize(&mut bytes)
+        .expect("serializing to a vector should not fail");
+    let mut h = Blake2s::new();
+    h.update(b"ark-simple-payments domain tag");
+    h.update(&bytes);
+    h.finalize().into()
+}
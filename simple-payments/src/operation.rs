@@ -0,0 +1,286 @@
+use crate::account::{AccountId, AccountPublicKey, AccountSecretKey, Nonce};
+use crate::ledger::{self, AccMerkleConfig, Amount, DefaultSignatureScheme, LeafHash};
+use crate::message::{DomainTag, MessageKind};
+use crate::overlay::StateOverlay;
+use crate::signature::SignatureScheme;
+use crate::Error;
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::Zero;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_std::rand::Rng;
+use derivative::Derivative;
+
+/// A deposit of `amount` into `account`. Deposits are made on the settlement
+/// layer, which authorizes them, so they carry no signature.
+#[derive(Clone, Debug, CanonicalSerialize, CanonicalDeserialize)]
+pub struct Deposit {
+    /// The account that is credited.
+    pub account: AccountId,
+    /// The amount being deposited.
+    pub amount: Amount,
+}
+
+/// A withdrawal of `amount` from `account` to the settlement layer, signed with
+/// the signature scheme `S`.
+#[derive(Derivative, CanonicalSerialize, CanonicalDeserialize)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct Withdrawal<S: SignatureScheme = DefaultSignatureScheme> {
+    /// The account that is debited.
+    pub account: AccountId,
+    /// The amount being withdrawn.
+    pub amount: Amount,
+    /// The account's nonce at the time the withdrawal was created.
+    pub nonce: Nonce,
+    /// The withdrawal authorization is a signature over the ledger's domain tag,
+    /// the account, the amount and the nonce.
+    pub signature: S::Signature,
+}
+
+/// An operation that moves funds between the ledger and an external settlement layer.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub enum Operation<S: SignatureScheme = DefaultSignatureScheme> {
+    Deposit(Deposit),
+    Withdrawal(Withdrawal<S>),
+}
+
+/// A commitment to a list of operations. The settlement layer recomputes it from
+/// the operations it knows about, to check that a batch handled exactly those.
+pub type OperationsCommitment = <LeafHash as CRH>::Output;
+
+/// Construct the message that the account owner signs to authorize a withdrawal
+/// from the ledger identified by `domain_tag`.
+fn signed_message(
+    domain_tag: &DomainTag,
+    account: AccountId,
+    amount: Amount,
+    nonce: Nonce,
+) -> Vec<u8> {
+    // The authorized message consists of
+    // (Version || Kind || DomainTag || AccId || Amount || Nonce).
+    // Its kind differs from that of a transaction, so a signature cannot be used as both.
+    let mut message = MessageKind::Withdrawal.prefix(domain_tag);
+    message.extend(account.to_bytes_le());
+    message.extend(amount.to_bytes_le());
+    message.extend(nonce.to_bytes_le());
+    message
+}
+
+impl<S: SignatureScheme> Withdrawal<S> {
+    /// Verify just the signature in the withdrawal.
+    fn verify_signature<M: AccMerkleConfig>(
+        &self,
+        pp: &ledger::Parameters<S, M>,
+        pub_key: &AccountPublicKey<S>,
+    ) -> bool {
+        let message = signed_message(&pp.domain_tag, self.account, self.amount, self.nonce);
+        S::verify(&pp.sig_params, pub_key, &message, &self.signature).unwrap()
+    }
+
+    /// Create a (possibly invalid) withdrawal.
+    pub fn create<M: AccMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<S, M>,
+        account: AccountId,
+        amount: Amount,
+        nonce: Nonce,
+        account_sk: &AccountSecretKey<S>,
+        rng: &mut R,
+    ) -> Self {
+        let message = signed_message(&parameters.domain_tag, account, amount, nonce);
+        let signature = S::sign(&parameters.sig_params, account_sk, &message, rng).unwrap();
+        Self {
+            account,
+            amount,
+            nonce,
+            signature,
+        }
+    }
+}
+
+impl<S: SignatureScheme> Operation<S> {
+    /// The account that the operation credits or debits.
+    pub fn account(&self) -> AccountId {
+        match self {
+            Operation::Deposit(deposit) => deposit.account,
+            Operation::Withdrawal(withdrawal) => withdrawal.account,
+        }
+    }
+
+    /// The amount that the operation moves.
+    pub fn amount(&self) -> Amount {
+        match self {
+            Operation::Deposit(deposit) => deposit.amount,
+            Operation::Withdrawal(withdrawal) => withdrawal.amount,
+        }
+    }
+
+    /// Is this a withdrawal?
+    pub fn is_withdrawal(&self) -> bool {
+        matches!(self, Operation::Withdrawal(_))
+    }
+
+    /// Convert the operation to the bytes that are committed to. These are
+    /// (Kind || AccId || Amount), where the kind is 0 for deposits and 1 for
+    /// withdrawals.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = vec![self.is_withdrawal() as u8];
+        bytes.extend(self.account().to_bytes_le());
+        bytes.extend(self.amount().to_bytes_le());
+        bytes
+    }
+
+    /// Check that the operation is valid for the given ledger state. This checks
+    /// that the account exists, and that
+    /// 1. for deposits, the new balance does not overflow;
+    /// 2. for withdrawals, the signature is valid with respect to the account's public
+    ///    key, the withdrawal nonce matches the account's current nonce, and the
+    ///    account has sufficient balance.
+    ///
+    /// Returns the first condition that does not hold.
+    pub fn validate<M: AccMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<S, M>,
+        state: &ledger::State<S, M>,
+    ) -> Result<(), Error> {
+        self.validate_inner(parameters, &state.overlay())
+    }
+
+    /// Check that the operation is valid for the given view of the ledger state,
+    /// like `validate`.
+    pub(crate) fn validate_inner<M: AccMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<S, M>,
+        state: &StateOverlay<S, M>,
+    ) -> Result<(), Error> {
+        let account_info = state
+            .account_info(&self.account())
+            .ok_or(Error::UnknownAccount(self.account()))?;
+        match self {
+            Operation::Deposit(deposit) => {
+                account_info
+                    .balance
+                    .checked_add(deposit.amount)
+                    .ok_or(Error::Overflow)?;
+            }
+            Operation::Withdrawal(withdrawal) => {
+                if !withdrawal.verify_signature(parameters, &account_info.public_key) {
+                    return Err(Error::BadSignature);
+                }
+                if withdrawal.nonce != account_info.nonce {
+                    return Err(Error::BadNonce);
+                }
+                if withdrawal.amount > account_info.balance {
+                    return Err(Error::InsufficientBalance);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Commit to `operations`, in order. The commitment is the hash chain
+/// `c_0 = 0, c_{i + 1} = H(c_i || operations[i])`, where `H` is the leaf hash of
+/// the account tree, and the operations are encoded via `Operation::to_bytes_le`.
+pub fn commit_to_operations<S: SignatureScheme, M: AccMerkleConfig>(
+    parameters: &ledger::Parameters<S, M>,
+    operations: &[Operation<S>],
+) -> OperationsCommitment {
+    operations
+        .iter()
+        .fold(OperationsCommitment::zero(), |commitment, operation| {
+            let mut input = ark_ff::to_bytes![commitment].unwrap();
+            input.extend(operation.to_bytes_le());
+            M::LeafHash::evaluate(&parameters.leaf_crh_params, &input).unwrap()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, State};
+
+    #[test]
+    fn deposits_and_withdrawals() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (_bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let deposit = Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(10),
+        });
+        state.apply_operation(&pp, &deposit).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(10));
+
+        // Only Alice can withdraw from her account.
+        let forged = Operation::Withdrawal(Withdrawal::create(
+            &pp,
+            alice_id,
+            Amount(4),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        ));
+        assert_eq!(
+            state.apply_operation(&pp, &forged),
+            Err(Error::BadSignature)
+        );
+        // She cannot withdraw more than her balance.
+        let overdraft = Operation::Withdrawal(Withdrawal::create(
+            &pp,
+            alice_id,
+            Amount(11),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        ));
+        assert_eq!(
+            state.apply_operation(&pp, &overdraft),
+            Err(Error::InsufficientBalance)
+        );
+
+        let withdrawal = Operation::Withdrawal(Withdrawal::create(
+            &pp,
+            alice_id,
+            Amount(4),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        ));
+        state.apply_operation(&pp, &withdrawal).unwrap();
+        let alice_info = &state.id_to_account_info[&alice_id];
+        assert_eq!(alice_info.balance, Amount(6));
+        assert_eq!(alice_info.nonce, Nonce(1));
+        // Withdrawals cannot be replayed.
+        assert_eq!(
+            state.apply_operation(&pp, &withdrawal),
+            Err(Error::BadNonce)
+        );
+
+        // Deposits to unknown accounts are rejected.
+        let deposit_to_nobody = Operation::Deposit(Deposit {
+            account: AccountId(5),
+            amount: Amount(1),
+        });
+        assert_eq!(
+            state.apply_operation(&pp, &deposit_to_nobody),
+            Err(Error::UnknownAccount(AccountId(5)))
+        );
+
+        // The commitment binds the operations and their order.
+        let ops = [deposit.clone(), withdrawal.clone()];
+        let reordered = [withdrawal, deposit];
+        assert_ne!(
+            commit_to_operations(&pp, &ops),
+            commit_to_operations(&pp, &reordered)
+        );
+        assert_ne!(
+            commit_to_operations(&pp, &ops),
+            commit_to_operations(&pp, &ops[..1])
+        );
+        assert_eq!(commit_to_operations(&pp, &[]), OperationsCommitment::zero());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::account::{AccountId, AccountInformation, AccountPublicKey, Nonce};
+use crate::ledger::{
+    next_account_leaf, AccMerkleConfig, AccRoot, Amount, DefaultSignatureScheme, MerkleConfig,
+    Parameters, State, NEXT_ACCOUNT_LEAF_INDEX,
+};
+use crate::operation::Operation;
+use crate::signature::SignatureScheme;
+use crate::sparse_merkle_tree::{SparseMerkleTreeDiff, SparseMerkleTreeOverlay};
+use crate::transaction::{self, Transaction};
+use crate::Error;
+use derivative::Derivative;
+use std::collections::{HashMap, HashSet};
+
+/// A view of a `State` with changes on top, which leaves the `State` itself
+/// untouched. Registrations, transactions and operations are applied to it like
+/// they are to a `State`, and `into_diff` returns the changes, which the caller
+/// can apply with `State::commit` or drop.
+pub struct StateOverlay<
+    'a,
+    S: SignatureScheme = DefaultSignatureScheme,
+    M: AccMerkleConfig = MerkleConfig,
+> {
+    state: &'a State<S, M>,
+    /// The accounts that were registered or changed, with their new information.
+    accounts: HashMap<AccountId, AccountInformation<S>>,
+    /// The public keys of the accounts that were registered through this overlay.
+    registered_keys: HashSet<AccountPublicKey<S>>,
+    next_available_account: Option<AccountId>,
+    account_merkle_tree: SparseMerkleTreeOverlay<'a, M>,
+}
+
+/// The changes that were made to a `State` through a `StateOverlay`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct StateDiff<S: SignatureScheme = DefaultSignatureScheme, M: AccMerkleConfig = MerkleConfig>
+{
+    pub(crate) base_root: AccRoot,
+    pub(crate) root: AccRoot,
+    pub(crate) accounts: HashMap<AccountId, AccountInformation<S>>,
+    pub(crate) next_available_account: Option<AccountId>,
+    pub(crate) account_merkle_tree: SparseMerkleTreeDiff<M>,
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> StateDiff<S, M> {
+    /// The root of the state that the changes were made to.
+    pub fn base_root(&self) -> AccRoot {
+        self.base_root
+    }
+
+    /// The root of the state once the changes are committed.
+    pub fn root(&self) -> AccRoot {
+        self.root
+    }
+
+    /// The accounts that were registered or changed, with their new information.
+    pub fn accounts(&self) -> impl Iterator<Item = (&AccountId, &AccountInformation<S>)> {
+        self.accounts.iter()
+    }
+
+    /// The new information of the account `id`, if it was registered or changed.
+    pub fn account_info(&self, id: &AccountId) -> Option<&AccountInformation<S>> {
+        self.accounts.get(id)
+    }
+}
+
+impl<'a, S: SignatureScheme, M: AccMerkleConfig> StateOverlay<'a, S, M> {
+    /// Create an overlay of `state` without any changes.
+    pub fn new(state: &'a State<S, M>) -> Self {
+        Self {
+            state,
+            accounts: HashMap::new(),
+            registered_keys: HashSet::new(),
+            next_available_account: state.next_available_account,
+            account_merkle_tree: state.account_merkle_tree.overlay(),
+        }
+    }
+
+    /// Return the root of the account Merkle tree, with the changes applied.
+    pub fn root(&self) -> AccRoot {
+        self.account_merkle_tree.root()
+    }
+
+    /// Return the account Merkle tree, with the changes applied.
+    pub fn account_merkle_tree(&self) -> &SparseMerkleTreeOverlay<'a, M> {
+        &self.account_merkle_tree
+    }
+
+    /// Return the information of the account `id`, with the changes applied.
+    pub fn account_info(&self, id: &AccountId) -> Option<&AccountInformation<S>> {
+        self.accounts
+            .get(id)
+            .or_else(|| self.state.id_to_account_info.get(id))
+    }
+
+    /// Return the next available account identifier, with the changes applied.
+    pub fn next_available_account(&self) -> Option<AccountId> {
+        self.next_available_account
+    }
+
+    /// Return the account that receives transaction fees, if any.
+    pub fn fee_collector(&self) -> Option<AccountId> {
+        self.state.fee_collector
+    }
+
+    /// Return the changes made through this overlay.
+    pub fn into_diff(self) -> StateDiff<S, M> {
+        StateDiff {
+            base_root: self.state.root(),
+            root: self.account_merkle_tree.root(),
+            accounts: self.accounts,
+            next_available_account: self.next_available_account,
+            account_merkle_tree: self.account_merkle_tree.into_diff(),
+        }
+    }
+
+    /// Overwrite the information stored for `id` with `account_info`.
+    fn update_account_info(&mut self, id: AccountId, account_info: AccountInformation<S>) {
+        let index = id
+            .leaf_index()
+            .expect("registered accounts fit in the tree");
+        self.account_merkle_tree
+            .update(index, &account_info.to_bytes_le())
+            .expect("should exist");
+        self.accounts.insert(id, account_info);
+    }
+
+    /// Create a new account with public key `public_key`, like `State::register`.
+    pub fn register(&mut self, public_key: AccountPublicKey<S>) -> Result<AccountId, Error> {
+        let id = self.next_available_account.ok_or(Error::LedgerFull)?;
+        match id.leaf_index() {
+            Some(index) if index < self.state.capacity() => {}
+            _ => return Err(Error::LedgerFull),
+        }
+        if !S::is_valid_public_key(&public_key) {
+            return Err(Error::InvalidPublicKey);
+        }
+        if self.state.pub_key_to_id.contains_key(&public_key)
+            || !self.registered_keys.insert(public_key.clone())
+        {
+            return Err(Error::DuplicatePublicKey);
+        }
+        // Construct account information for the new account.
+        let account_info = AccountInformation {
+            public_key,
+            balance: Amount(0),
+            nonce: Nonce::default(),
+        };
+        self.update_account_info(id, account_info);
+        // Increment the next account identifier. If it overflows, we have run out of
+        // identifiers.
+        let mut next_id = id;
+        self.next_available_account = next_id.checked_increment().map(|_| next_id);
+        self.account_merkle_tree
+            .update(
+                NEXT_ACCOUNT_LEAF_INDEX,
+                &next_account_leaf(self.next_available_account),
+            )
+            .expect("should exist");
+        Ok(id)
+    }
+
+    /// Apply the transaction `tx`, if it is valid, like `State::apply_transaction`.
+    pub fn apply_transaction(
+        &mut self,
+        pp: &Parameters<S, M>,
+        tx: &Transaction<S>,
+    ) -> Result<(), Error> {
+        self.apply_transaction_inner(pp, tx, true, |_, _| {})
+    }
+
+    /// Apply the transaction `tx`, if it is valid, like `apply_transaction`. The
+    /// accounts are updated one at a time: the sender first, then the recipient,
+    /// and then the fee collector if the transaction pays a fee. `before_update` is
+    /// called with the overlay and each of these accounts just before the account
+    /// is updated, for example to record its authentication path between updates.
+    pub fn apply_transaction_with(
+        &mut self,
+        pp: &Parameters<S, M>,
+        tx: &Transaction<S>,
+        before_update: impl FnMut(&Self, AccountId),
+    ) -> Result<(), Error> {
+        self.apply_transaction_inner(pp, tx, true, before_update)
+    }
+
+    /// Apply `transactions`, in order, like `State::apply_transactions`.
+    pub fn apply_transactions(
+        &mut self,
+        pp: &Parameters<S, M>,
+        transactions: &[Transaction<S>],
+    ) -> Result<(), (usize, Error)> {
+        // Transactions do not change public keys, so the signatures can be checked
+        // against the state before the batch.
+        let invalid_signature = transaction::first_invalid_signature(pp, self, transactions);
+        for (i, tx) in transactions.iter().enumerate() {
+            if invalid_signature == Some(i) {
+                return Err((i, Error::BadSignature));
+            }
+            self.apply_transaction_inner(pp, tx, false, |_, _| {})
+                .map_err(|e| (i, e))?;
+        }
+        Ok(())
+    }
+
+    fn apply_transaction_inner(
+        &mut self,
+        pp: &Parameters<S, M>,
+        tx: &Transaction<S>,
+        verify_signature: bool,
+        mut before_update: impl FnMut(&Self, AccountId),
+    ) -> Result<(), Error> {
+        tx.validate_inner(pp, self, verify_signature)?;
+        let mut sender_info = self
+            .account_info(&tx.sender)
+            .ok_or(Error::UnknownSender(tx.sender))?
+            .clone();
+        let total = tx.amount.checked_add(tx.fee).ok_or(Error::Overflow)?;
+        sender_info.balance = sender_info
+            .balance
+            .checked_sub(total)
+            .ok_or(Error::InsufficientBalance)?;
+        sender_info.nonce = sender_info
+            .nonce
+            .checked_increment()
+            .ok_or(Error::Overflow)?;
+        let mut credits = vec![(tx.recipient, tx.amount)];
+        if tx.fee > Amount(0) {
+            credits.push((self.fee_collector().ok_or(Error::NoFeeCollector)?, tx.fee));
+        }
+        // Credit each account on top of any update made earlier in this transaction,
+        // so that the balances are correct even if the accounts involved coincide.
+        let mut updates = vec![(tx.sender, sender_info)];
+        for (id, credit) in credits {
+            let mut account_info = updates
+                .iter()
+                .rev()
+                .find(|(updated_id, _)| *updated_id == id)
+                .map(|(_, account_info)| account_info.clone())
+                .or_else(|| self.account_info(&id).cloned())
+                .ok_or(Error::UnknownAccount(id))?;
+            account_info.balance = account_info
+                .balance
+                .checked_add(credit)
+                .ok_or(Error::Overflow)?;
+            updates.push((id, account_info));
+        }
+        for (id, account_info) in updates {
+            before_update(self, id);
+            self.update_account_info(id, account_info);
+        }
+        Ok(())
+    }
+
+    /// Apply the deposit or withdrawal `operation`, if it is valid, like
+    /// `State::apply_operation`.
+    pub fn apply_operation(
+        &mut self,
+        pp: &Parameters<S, M>,
+        operation: &Operation<S>,
+    ) -> Result<(), Error> {
+        operation.validate_inner(pp, self)?;
+        let id = operation.account();
+        let mut account_info = self
+            .account_info(&id)
+            .ok_or(Error::UnknownAccount(id))?
+            .clone();
+        match operation {
+            Operation::Deposit(deposit) => {
+                account_info.balance = account_info
+                    .balance
+                    .checked_add(deposit.amount)
+                    .ok_or(Error::Overflow)?;
+            }
+            Operation::Withdrawal(withdrawal) => {
+                account_info.balance = account_info
+                    .balance
+                    .checked_sub(withdrawal.amount)
+                    .ok_or(Error::InsufficientBalance)?;
+                account_info.nonce = account_info
+                    .nonce
+                    .checked_increment()
+                    .ok_or(Error::Overflow)?;
+            }
+        }
+        self.update_account_info(id, account_info);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::account::Nonce;
+    use crate::ledger::{Amount, DefaultSignatureScheme, Parameters, State};
+    use crate::operation::{Deposit, Operation};
+    use crate::signature::SignatureScheme;
+    use crate::transaction::Transaction;
+    use crate::Error;
+
+    #[test]
+    fn overlays_leave_the_state_untouched() {
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_pk, _bob_sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let deposit = Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(10),
+        });
+
+        // Register Bob, fund Alice, and let her pay Bob, all in an overlay.
+        let root = state.root();
+        let mut overlay = state.overlay();
+        let bob_id = overlay.register(bob_pk).unwrap();
+        overlay.apply_operation(&pp, &deposit).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(4),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        overlay.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(overlay.account_info(&bob_id).unwrap().balance, Amount(4));
+        // Invalid changes are rejected, as they are by the state.
+        assert_eq!(overlay.apply_transaction(&pp, &tx), Err(Error::BadNonce));
+        let diff = overlay.into_diff();
+        assert_eq!(state.root(), root);
+        assert!(!state.id_to_account_info.contains_key(&bob_id));
+
+        // The same changes, applied to a copy of the state, lead to the same root.
+        let mut expected = state.clone();
+        assert_eq!(expected.register(bob_pk).unwrap(), bob_id);
+        expected.apply_operation(&pp, &deposit).unwrap();
+        expected.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(diff.base_root(), root);
+        assert_eq!(diff.root(), expected.root());
+        assert_eq!(diff.accounts().count(), 2);
+
+        // Committing the diff applies the changes.
+        state.commit(diff.clone()).unwrap();
+        assert_eq!(state.root(), expected.root());
+        assert_eq!(state.id_to_account_info, expected.id_to_account_info);
+        assert_eq!(state.pub_key_to_id, expected.pub_key_to_id);
+        assert_eq!(
+            state.next_available_account,
+            expected.next_available_account
+        );
+        // A diff cannot be committed twice, nor to another state.
+        assert_eq!(state.commit(diff), Err(Error::StaleDiff));
+    }
+}
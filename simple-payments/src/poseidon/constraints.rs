@@ -0,0 +1,283 @@
+use crate::poseidon::{
+    bytes_per_field_element, PoseidonParameters, TwoToOneCRH, CRH, RATE, TWO_TO_ONE_DOMAIN_TAG,
+    WIDTH,
+};
+use ark_crypto_primitives::crh::constraints::{
+    CRHGadget as CRHGadgetTrait, TwoToOneCRHGadget as TwoToOneCRHGadgetTrait,
+};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::marker::PhantomData;
+use core::borrow::Borrow;
+
+/// The parameters of the Poseidon permutation. These are always embedded in the
+/// circuit as constants.
+#[derive(Clone)]
+pub struct ParametersVar<F: PrimeField> {
+    parameters: PoseidonParameters<F>,
+}
+
+impl<F: PrimeField> ParametersVar<F> {
+    /// Apply the Poseidon permutation to `state`.
+    pub fn permute(&self, state: &mut [FpVar<F>; WIDTH]) -> Result<(), SynthesisError> {
+        let parameters = &self.parameters;
+        for (round, constants) in parameters.round_constants.iter().enumerate() {
+            for (x, constant) in state.iter_mut().zip(constants) {
+                *x += *constant;
+            }
+            if parameters.is_full_round(round) {
+                for x in state.iter_mut() {
+                    *x = self.sbox(x)?;
+                }
+            } else {
+                state[0] = self.sbox(&state[0])?;
+            }
+            let mixed = parameters
+                .mds
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(state.iter())
+                        .fold(FpVar::zero(), |sum, (entry, x)| sum + x * *entry)
+                })
+                .collect::<Vec<_>>();
+            for (x, mixed) in state.iter_mut().zip(mixed) {
+                *x = mixed;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compute `x^alpha` by square-and-multiply. Unlike `pow_by_constant`, this
+    /// starts from `x` rather than from one, so that `x^5` costs 3 constraints.
+    fn sbox(&self, x: &FpVar<F>) -> Result<FpVar<F>, SynthesisError> {
+        let alpha = self.parameters.alpha;
+        let mut result = x.clone();
+        for i in (0..63 - alpha.leading_zeros()).rev() {
+            result.square_in_place()?;
+            if (alpha >> i) & 1 == 1 {
+                result *= x;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Hash `inputs` like `PoseidonParameters::hash` does natively.
+    pub fn hash(&self, domain_tag: u64, inputs: &[FpVar<F>]) -> Result<FpVar<F>, SynthesisError> {
+        let mut state = [
+            FpVar::constant(F::from(domain_tag)),
+            FpVar::zero(),
+            FpVar::zero(),
+        ];
+        for chunk in inputs.chunks(RATE) {
+            for (x, input) in state[1..].iter_mut().zip(chunk) {
+                *x += input;
+            }
+            self.permute(&mut state)?;
+        }
+        if inputs.is_empty() {
+            self.permute(&mut state)?;
+        }
+        let [_, output, _] = state;
+        Ok(output)
+    }
+}
+
+impl<F: PrimeField> AllocVar<PoseidonParameters<F>, F> for ParametersVar<F> {
+    fn new_variable<T: Borrow<PoseidonParameters<F>>>(
+        _cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let parameters = f()?.borrow().clone();
+        Ok(Self { parameters })
+    }
+}
+
+/// Pack `bytes` into field elements, like `poseidon::bytes_to_field_elements`.
+/// This costs no constraints.
+pub fn bytes_to_field_elements<F: PrimeField>(
+    bytes: &[UInt8<F>],
+) -> Result<Vec<FpVar<F>>, SynthesisError> {
+    bytes
+        .chunks(bytes_per_field_element::<F>())
+        .map(|chunk| Boolean::le_bits_to_fp_var(&chunk.to_bits_le()?))
+        .collect()
+}
+
+/// Pack the encoding of a field element back into the field element. The bits
+/// beyond the modulus size must be zero, but the rest is not checked to be below
+/// the modulus.
+fn bytes_to_field_element<F: PrimeField>(bytes: &[UInt8<F>]) -> Result<FpVar<F>, SynthesisError> {
+    let bits = bytes.to_bits_le()?;
+    let (bits, high_bits) = bits.split_at(F::size_in_bits().min(bits.len()));
+    for bit in high_bits {
+        bit.enforce_equal(&Boolean::FALSE)?;
+    }
+    // `le_bits_to_fp_var` checks that `MODULUS_BITS` bits are below the modulus,
+    // so the top bit is added separately.
+    match bits.split_last() {
+        Some((top_bit, low_bits)) if bits.len() == F::size_in_bits() => {
+            let top_bit = FpVar::from(top_bit.clone()) * F::from(2u64).pow([low_bits.len() as u64]);
+            Ok(Boolean::le_bits_to_fp_var(low_bits)? + top_bit)
+        }
+        _ => Boolean::le_bits_to_fp_var(bits),
+    }
+}
+
+/// The R1CS equivalent of `poseidon::CRH`.
+pub struct CRHGadget<F: PrimeField, const INPUT_SIZE_BYTES: usize> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField, const INPUT_SIZE_BYTES: usize> CRHGadgetTrait<CRH<F, INPUT_SIZE_BYTES>, F>
+    for CRHGadget<F, INPUT_SIZE_BYTES>
+{
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        assert!(input.len() <= INPUT_SIZE_BYTES);
+        let mut padded_input = input.to_vec();
+        padded_input.resize(INPUT_SIZE_BYTES, UInt8::constant(0));
+        parameters.hash(
+            INPUT_SIZE_BYTES as u64,
+            &bytes_to_field_elements(&padded_input)?,
+        )
+    }
+}
+
+/// The R1CS equivalent of `poseidon::TwoToOneCRH`.
+pub struct TwoToOneCRHGadget<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField> TwoToOneCRHGadgetTrait<TwoToOneCRH<F>, F> for TwoToOneCRHGadget<F> {
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    /// Hash two field elements, given by their canonical encodings. Unlike the
+    /// native hash, this does not check that the encodings are canonical, as that
+    /// would cost more than the hash itself. This is sound as long as the inputs
+    /// come from `ToBytesGadget::to_bytes` on `FpVar`s, which is how `PathVar`
+    /// obtains them.
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        left_input: &[UInt8<F>],
+        right_input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let left = bytes_to_field_element(left_input)?;
+        let right = bytes_to_field_element(right_input)?;
+        parameters.hash(TWO_TO_ONE_DOMAIN_TAG, &[left, right])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{LeafWindow, TwoToOneWindow};
+    use ark_crypto_primitives::crh::injective_map::constraints::{
+        PedersenCRHCompressorGadget, TECompressorGadget,
+    };
+    use ark_crypto_primitives::crh::injective_map::{PedersenCRHCompressor, TECompressor};
+    use ark_crypto_primitives::crh::{TwoToOneCRH as TwoToOneCRHTrait, CRH as CRHTrait};
+    use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective, Fq};
+    use ark_ff::to_bytes;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    type PoseidonCRH = CRH<Fq, 90>;
+    type PoseidonCRHGadget = CRHGadget<Fq, 90>;
+    type PedersenCRH = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
+    type PedersenCRHGadget = PedersenCRHCompressorGadget<
+        EdwardsProjective,
+        TECompressor,
+        LeafWindow,
+        EdwardsVar,
+        TECompressorGadget,
+    >;
+    type PedersenTwoToOneCRH =
+        PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
+    type PedersenTwoToOneCRHGadget = PedersenCRHCompressorGadget<
+        EdwardsProjective,
+        TECompressor,
+        TwoToOneWindow,
+        EdwardsVar,
+        TECompressorGadget,
+    >;
+
+    /// Evaluate the hash gadget `H` on `input` as a witness, check that it computes
+    /// `expected`, and return the number of constraints that it costs.
+    fn crh_constraints<H: CRHTrait, HG: CRHGadgetTrait<H, Fq>>(
+        parameters: &H::Parameters,
+        input: &[u8],
+    ) -> usize {
+        let expected = H::evaluate(parameters, input).unwrap();
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters = HG::ParametersVar::new_constant(cs.clone(), parameters).unwrap();
+        let input = UInt8::new_witness_vec(cs.clone(), input).unwrap();
+        let before = cs.num_constraints();
+        let output = HG::evaluate(&parameters, &input).unwrap();
+        let num_constraints = cs.num_constraints() - before;
+        assert_eq!(
+            output.to_bytes().unwrap().value().unwrap(),
+            to_bytes![expected].unwrap()
+        );
+        assert!(cs.is_satisfied().unwrap());
+        num_constraints
+    }
+
+    fn two_to_one_crh_constraints<H: TwoToOneCRHTrait, HG: TwoToOneCRHGadgetTrait<H, Fq>>(
+        parameters: &H::Parameters,
+        left: &[u8],
+        right: &[u8],
+    ) -> usize {
+        let expected = H::evaluate(parameters, left, right).unwrap();
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters = HG::ParametersVar::new_constant(cs.clone(), parameters).unwrap();
+        let left = UInt8::new_witness_vec(cs.clone(), left).unwrap();
+        let right = UInt8::new_witness_vec(cs.clone(), right).unwrap();
+        let before = cs.num_constraints();
+        let output = HG::evaluate(&parameters, &left, &right).unwrap();
+        let num_constraints = cs.num_constraints() - before;
+        assert_eq!(
+            output.to_bytes().unwrap().value().unwrap(),
+            to_bytes![expected].unwrap()
+        );
+        assert!(cs.is_satisfied().unwrap());
+        num_constraints
+    }
+
+    #[test]
+    fn poseidon_is_cheaper_than_pedersen() {
+        let mut rng = ark_std::test_rng();
+        let input = (0..90u8).collect::<Vec<_>>();
+        let pedersen_parameters = <PedersenCRH as CRHTrait>::setup(&mut rng).unwrap();
+        let poseidon_parameters = <PoseidonCRH as CRHTrait>::setup(&mut rng).unwrap();
+        let pedersen =
+            crh_constraints::<PedersenCRH, PedersenCRHGadget>(&pedersen_parameters, &input);
+        let poseidon =
+            crh_constraints::<PoseidonCRH, PoseidonCRHGadget>(&poseidon_parameters, &input);
+        assert!(poseidon * 5 < pedersen);
+
+        let left = to_bytes![Fq::from(3u64)].unwrap();
+        let right = to_bytes![-Fq::from(5u64)].unwrap();
+        let pedersen_parameters =
+            <PedersenTwoToOneCRH as TwoToOneCRHTrait>::setup(&mut rng).unwrap();
+        let pedersen = two_to_one_crh_constraints::<PedersenTwoToOneCRH, PedersenTwoToOneCRHGadget>(
+            &pedersen_parameters,
+            &left,
+            &right,
+        );
+        let poseidon = two_to_one_crh_constraints::<TwoToOneCRH<Fq>, TwoToOneCRHGadget<Fq>>(
+            &poseidon_parameters,
+            &left,
+            &right,
+        );
+        assert!(poseidon * 5 < pedersen);
+    }
+}
@@ -0,0 +1,424 @@
+use ark_crypto_primitives::crh::{TwoToOneCRH as TwoToOneCRHTrait, CRH as CRHTrait};
+use ark_crypto_primitives::Error;
+use ark_ff::{BigInteger, FpParameters, FromBytes, PrimeField};
+use ark_std::marker::PhantomData;
+use ark_std::rand::Rng;
+use derivative::Derivative;
+use lazy_static::lazy_static;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// The number of field elements in the state of the permutation.
+pub const WIDTH: usize = 3;
+/// The number of field elements absorbed per permutation. The remaining
+/// element of the state is the capacity.
+pub const RATE: usize = 2;
+
+/// The domain tag of `TwoToOneCRH`. No input is this long, so it never coincides
+/// with the domain tag of a `CRH` or of the Poseidon random oracle.
+const TWO_TO_ONE_DOMAIN_TAG: u64 = u64::MAX;
+
+/// Parameters of the Poseidon permutation over `F`, with a state of `WIDTH`
+/// field elements.
+///
+/// The round constants and the MDS matrix are derived with the Grain LFSR, like
+/// in the reference implementation of Poseidon, so the permutation matches the
+/// reference permutation with the same parameters.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), PartialEq(bound = ""))]
+pub struct PoseidonParameters<F: PrimeField> {
+    /// The number of rounds that apply the S-box to the whole state. Half of them
+    /// come before the partial rounds, and half of them after.
+    full_rounds: usize,
+    /// The number of rounds that apply the S-box to the first element only.
+    partial_rounds: usize,
+    /// The S-box is `x -> x^alpha`. It is a permutation iff `gcd(alpha, |F| - 1) = 1`.
+    alpha: u64,
+    /// `round_constants[r]` is added to the state at the start of round `r`.
+    round_constants: Vec<[F; WIDTH]>,
+    /// The MDS matrix that mixes the state at the end of each round.
+    mds: [[F; WIDTH]; WIDTH],
+}
+
+impl<F: PrimeField> PoseidonParameters<F> {
+    /// Derive the parameters of a permutation with the given number of rounds
+    /// and the S-box `x -> x^alpha`, like `generate_parameters_grain.sage` of the
+    /// reference implementation: the round constants come first out of the Grain
+    /// LFSR, and then the points of the Cauchy matrix `1 / (x_i + y_j)`, which is MDS.
+    ///
+    /// The reference script also checks the matrix against invariant subspace
+    /// trails, and samples another one if the check fails. This is not done here,
+    /// so parameters other than the default ones must be checked with the script.
+    pub fn new(full_rounds: usize, partial_rounds: usize, alpha: u64) -> Self {
+        assert!(
+            full_rounds % 2 == 0,
+            "the full rounds are split in two halves"
+        );
+        let mut lfsr = GrainLFSR::new(
+            F::Params::MODULUS_BITS,
+            full_rounds as u16,
+            partial_rounds as u16,
+        );
+        let round_constants = (0..full_rounds + partial_rounds)
+            .map(|_| {
+                let mut constants = [F::zero(); WIDTH];
+                for constant in constants.iter_mut() {
+                    *constant = lfsr.field_element_by_rejection();
+                }
+                constants
+            })
+            .collect();
+        let mds = loop {
+            let mut points = [F::zero(); 2 * WIDTH];
+            for point in points.iter_mut() {
+                *point = lfsr.field_element_mod_modulus();
+            }
+            let (xs, ys) = points.split_at(WIDTH);
+            let is_distinct = points
+                .iter()
+                .enumerate()
+                .all(|(i, point)| !points[..i].contains(point));
+            if !is_distinct || xs.iter().any(|x| ys.iter().any(|y| (*x + y).is_zero())) {
+                continue;
+            }
+            let mut mds = [[F::zero(); WIDTH]; WIDTH];
+            for (row, x) in mds.iter_mut().zip(xs) {
+                for (entry, y) in row.iter_mut().zip(ys) {
+                    *entry = (*x + y).inverse().unwrap();
+                }
+            }
+            break mds;
+        };
+        Self {
+            full_rounds,
+            partial_rounds,
+            alpha,
+            round_constants,
+            mds,
+        }
+    }
+
+    /// Is `round` one of the full rounds?
+    fn is_full_round(&self, round: usize) -> bool {
+        round < self.full_rounds / 2 || round >= self.full_rounds / 2 + self.partial_rounds
+    }
+
+    /// Apply the Poseidon permutation to `state`.
+    pub fn permute(&self, state: &mut [F; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (x, constant) in state.iter_mut().zip(constants) {
+                *x += constant;
+            }
+            if self.is_full_round(round) {
+                for x in state.iter_mut() {
+                    *x = x.pow([self.alpha]);
+                }
+            } else {
+                state[0] = state[0].pow([self.alpha]);
+            }
+            let mut mixed = [F::zero(); WIDTH];
+            for (mixed, row) in mixed.iter_mut().zip(&self.mds) {
+                for (entry, x) in row.iter().zip(state.iter()) {
+                    *mixed += *entry * x;
+                }
+            }
+            *state = mixed;
+        }
+    }
+
+    /// Hash `inputs` with a sponge that absorbs `RATE` elements per permutation, and
+    /// whose capacity element starts out as `domain_tag`. The domain tag must
+    /// determine the number of inputs, as the inputs are implicitly padded with zeros.
+    pub fn hash(&self, domain_tag: u64, inputs: &[F]) -> F {
+        let mut state = [F::zero(); WIDTH];
+        state[0] = F::from(domain_tag);
+        for chunk in inputs.chunks(RATE) {
+            for (x, input) in state[1..].iter_mut().zip(chunk) {
+                *x += input;
+            }
+            self.permute(&mut state);
+        }
+        if inputs.is_empty() {
+            self.permute(&mut state);
+        }
+        state[1]
+    }
+
+    /// The default parameters, which are derived once per field and then shared.
+    /// Deriving them runs the Grain LFSR, which costs far more than a hash, so
+    /// hashes that have no parameters of their own should use these.
+    pub fn cached() -> &'static Self {
+        lazy_static! {
+            static ref CACHE: Mutex<HashMap<TypeId, &'static (dyn Any + Send + Sync)>> =
+                Mutex::new(HashMap::new());
+        }
+        let mut cache = CACHE.lock().unwrap();
+        let parameters = *cache
+            .entry(TypeId::of::<F>())
+            .or_insert_with(|| Box::leak(Box::new(Self::default())));
+        parameters
+            .downcast_ref()
+            .expect("the parameters are cached by field")
+    }
+}
+
+impl<F: PrimeField> Default for PoseidonParameters<F> {
+    /// 8 full rounds and 57 partial rounds with the `x^5` S-box, which are the
+    /// numbers of rounds that `calc_round_numbers.py` of the reference
+    /// implementation gives for a state of 3 elements of a 254- or 255-bit field,
+    /// such as the scalar fields of BN254 and BLS12-381, at the 128-bit security
+    /// level. For these fields, the parameters are the reference parameters
+    /// `x5_254_3` and `x5_255_3`.
+    fn default() -> Self {
+        Self::new(8, 57, 5)
+    }
+}
+
+/// The Grain LFSR, in self-shrinking mode, which the reference implementation of
+/// Poseidon uses to generate its parameters.
+struct GrainLFSR {
+    state: [bool; 80],
+    head: usize,
+}
+
+impl GrainLFSR {
+    /// Initialize the LFSR for a permutation of `WIDTH` elements of a prime field
+    /// of `modulus_bits` bits, with an S-box `x -> x^alpha`, and discard the first
+    /// 160 bits of output.
+    fn new(modulus_bits: u32, full_rounds: u16, partial_rounds: u16) -> Self {
+        let mut state = [true; 80];
+        // Bits 0 and 1 are 0b01 for a prime field, bits 2 to 5 are 0b0000 for the
+        // S-box `x -> x^alpha`, and bits 50 to 79 are set.
+        state[..6].copy_from_slice(&[false, true, false, false, false, false]);
+        for (bits, value) in [
+            (6..18, modulus_bits as u64),
+            (18..30, WIDTH as u64),
+            (30..40, full_rounds as u64),
+            (40..50, partial_rounds as u64),
+        ] {
+            let len = bits.len();
+            for (i, bit) in state[bits].iter_mut().enumerate() {
+                *bit = (value >> (len - 1 - i)) & 1 == 1;
+            }
+        }
+        let mut lfsr = Self { state, head: 0 };
+        for _ in 0..160 {
+            lfsr.update();
+        }
+        lfsr
+    }
+
+    /// Clock the LFSR once.
+    fn update(&mut self) -> bool {
+        let new_bit = [62, 51, 38, 23, 13, 0]
+            .iter()
+            .fold(false, |bit, tap| bit ^ self.state[(self.head + tap) % 80]);
+        self.state[self.head] = new_bit;
+        self.head = (self.head + 1) % 80;
+        new_bit
+    }
+
+    /// Output the next bit: the LFSR is clocked twice, and the second bit is
+    /// output if the first one is set, and discarded otherwise.
+    fn next_bit(&mut self) -> bool {
+        while !self.update() {
+            self.update();
+        }
+        self.update()
+    }
+
+    /// The next integer of `F::Params::MODULUS_BITS` bits, most significant bit
+    /// first.
+    fn next_integer<F: PrimeField>(&mut self) -> F::BigInt {
+        let bits = (0..F::Params::MODULUS_BITS)
+            .map(|_| self.next_bit())
+            .collect::<Vec<_>>();
+        F::BigInt::from_bits_be(&bits)
+    }
+
+    /// Sample a field element, rejecting integers that are not below the modulus.
+    fn field_element_by_rejection<F: PrimeField>(&mut self) -> F {
+        loop {
+            if let Some(element) = F::from_repr(self.next_integer::<F>()) {
+                return element;
+            }
+        }
+    }
+
+    /// Sample a field element, reducing integers modulo the modulus.
+    fn field_element_mod_modulus<F: PrimeField>(&mut self) -> F {
+        F::from_le_bytes_mod_order(&self.next_integer::<F>().to_bytes_le())
+    }
+}
+
+/// The number of bytes that are packed into each field element, so that packing
+/// never wraps around the modulus.
+pub fn bytes_per_field_element<F: PrimeField>() -> usize {
+    F::Params::CAPACITY as usize / 8
+}
+
+/// Pack `bytes` into field elements, `bytes_per_field_element` bytes at a time.
+pub fn bytes_to_field_elements<F: PrimeField>(bytes: &[u8]) -> Vec<F> {
+    bytes
+        .chunks(bytes_per_field_element::<F>())
+        .map(F::from_le_bytes_mod_order)
+        .collect()
+}
+
+/// Parse the canonical little-endian encoding of a field element.
+fn canonical_field_element<F: PrimeField>(bytes: &[u8]) -> Result<F, Error> {
+    if bytes.len() != F::BigInt::NUM_LIMBS * 8 {
+        return Err("incorrect input length".into());
+    }
+    F::from_repr(F::BigInt::read(bytes)?).ok_or_else(|| "non-canonical field element".into())
+}
+
+/// A collision-resistant hash of inputs of up to `INPUT_SIZE_BYTES` bytes, based on
+/// the Poseidon permutation. Like Pedersen hashes, it pads shorter inputs with zeros.
+pub struct CRH<F: PrimeField, const INPUT_SIZE_BYTES: usize> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField, const INPUT_SIZE_BYTES: usize> CRHTrait for CRH<F, INPUT_SIZE_BYTES> {
+    const INPUT_SIZE_BITS: usize = INPUT_SIZE_BYTES * 8;
+    type Output = F;
+    type Parameters = PoseidonParameters<F>;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(PoseidonParameters::default())
+    }
+
+    fn evaluate(parameters: &Self::Parameters, input: &[u8]) -> Result<Self::Output, Error> {
+        if input.len() > INPUT_SIZE_BYTES {
+            return Err("incorrect input length".into());
+        }
+        let mut padded_input = input.to_vec();
+        padded_input.resize(INPUT_SIZE_BYTES, 0);
+        Ok(parameters.hash(
+            INPUT_SIZE_BYTES as u64,
+            &bytes_to_field_elements(&padded_input),
+        ))
+    }
+}
+
+/// A collision-resistant hash of two field elements, based on the Poseidon
+/// permutation. Both inputs must be canonical encodings of field elements, such
+/// as the outputs of `CRH` and `TwoToOneCRH`.
+pub struct TwoToOneCRH<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField> TwoToOneCRHTrait for TwoToOneCRH<F> {
+    const LEFT_INPUT_SIZE_BITS: usize = F::BigInt::NUM_LIMBS * 64;
+    const RIGHT_INPUT_SIZE_BITS: usize = F::BigInt::NUM_LIMBS * 64;
+    type Output = F;
+    type Parameters = PoseidonParameters<F>;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(PoseidonParameters::default())
+    }
+
+    fn evaluate(
+        parameters: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, Error> {
+        let left = canonical_field_element(left_input)?;
+        let right = canonical_field_element(right_input)?;
+        Ok(parameters.hash(TWO_TO_ONE_DOMAIN_TAG, &[left, right]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_ed_on_bls12_381::Fq;
+    use ark_ff::{to_bytes, One, Zero};
+
+    /// Permute `[0, 1, 2]` with the default parameters, and return the result as
+    /// big-endian hex strings.
+    fn permute_test_input<F: PrimeField>() -> Vec<String> {
+        let mut state = [F::zero(), F::one(), F::from(2u64)];
+        PoseidonParameters::default().permute(&mut state);
+        state
+            .iter()
+            .map(|x| {
+                let mut bytes = x.into_repr().to_bytes_le();
+                bytes.reverse();
+                bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_test_vectors() {
+        // The test vectors of the reference implementation, for the parameters
+        // x5_254_3 and x5_255_3.
+        assert_eq!(
+            permute_test_input::<ark_bn254::Fr>(),
+            [
+                "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a",
+                "0fca49b798923ab0239de1c9e7a4a9a2210312b6a2f616d18b5a87f9b628ae29",
+                "0e7ae82e40091e63cbd4f16a6d16310b3729d4b6e138fcf54110e2867045a30c",
+            ]
+        );
+        assert_eq!(
+            permute_test_input::<Fq>(),
+            [
+                "28ce19420fc246a05553ad1e8c98f5c9d67166be2c18e9e4cb4b4e317dd2a78a",
+                "51f3e312c95343a896cfd8945ea82ba956c1118ce9b9859b6ea56637b4b1ddc4",
+                "3b2b69139b235626a0bfb56c9527ae66a7bf486ad8c11c14d1da0c69bbe0f79a",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_parameters_are_cached_per_field() {
+        let parameters = PoseidonParameters::<Fq>::cached();
+        assert_eq!(*parameters, PoseidonParameters::default());
+        assert!(std::ptr::eq(parameters, PoseidonParameters::cached()));
+        assert_eq!(
+            *PoseidonParameters::<ark_bn254::Fr>::cached(),
+            PoseidonParameters::default()
+        );
+    }
+
+    #[test]
+    fn hashes_are_domain_separated() {
+        let parameters = PoseidonParameters::<Fq>::default();
+        let one = Fq::one();
+        assert_ne!(parameters.hash(0, &[]), parameters.hash(1, &[]));
+        assert_ne!(
+            parameters.hash(2, &[one]),
+            parameters.hash(2, &[Fq::zero(), one])
+        );
+        assert_ne!(parameters.hash(2, &[one]), parameters.hash(3, &[one]));
+
+        // Inputs are padded with zeros, but inputs that are too long are rejected.
+        type TestCRH = CRH<Fq, 40>;
+        assert_eq!(
+            TestCRH::evaluate(&parameters, &[1, 2, 3]).unwrap(),
+            TestCRH::evaluate(&parameters, &[1, 2, 3, 0, 0]).unwrap()
+        );
+        assert_ne!(
+            TestCRH::evaluate(&parameters, &[1, 2, 3]).unwrap(),
+            TestCRH::evaluate(&parameters, &[1, 2, 4]).unwrap()
+        );
+        assert!(TestCRH::evaluate(&parameters, &[0; 41]).is_err());
+
+        // The two-to-one hash only accepts field elements.
+        let left = to_bytes![Fq::one()].unwrap();
+        let right = to_bytes![-Fq::one()].unwrap();
+        let hash = TwoToOneCRH::<Fq>::evaluate(&parameters, &left, &right).unwrap();
+        assert_ne!(
+            hash,
+            TwoToOneCRH::<Fq>::evaluate(&parameters, &right, &left).unwrap()
+        );
+        assert!(TwoToOneCRH::<Fq>::evaluate(&parameters, &left, &[0xff; 32]).is_err());
+        assert!(TwoToOneCRH::<Fq>::evaluate(&parameters, &left, &right[1..]).is_err());
+    }
+}
@@ -82,8 +82,8 @@ mod test {
             <TestROGadget as RandomOracleGadget<TestRO, Fr>>::evaluate(&parameters_var, &input_var)
                 .unwrap();
 
-        for i in 0..32 {
-            assert_eq!(primitive_result[i], result_var.0[i].value().unwrap());
+        for (expected, byte_var) in primitive_result.iter().zip(&result_var.0) {
+            assert_eq!(*expected, byte_var.value().unwrap());
         }
         assert!(cs.is_satisfied().unwrap());
     }
@@ -0,0 +1,71 @@
+use crate::poseidon::constraints::{bytes_to_field_elements, ParametersVar};
+use crate::random_oracle::{poseidon, RandomOracleGadget};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+pub struct ROGadget;
+
+impl<F: PrimeField> RandomOracleGadget<poseidon::RO<F>, F> for ROGadget {
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        parameters.hash(input.len() as u64, &bytes_to_field_elements(input)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::random_oracle::{blake2s, poseidon, RandomOracle, RandomOracleGadget};
+    use ark_ed_on_bls12_381::Fq as Fr;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    /// Evaluate the random oracle gadget `ROG` on `input`, check that it agrees
+    /// with `RO`, and return the number of constraints that it costs.
+    fn random_oracle_constraints<RO: RandomOracle, ROG: RandomOracleGadget<RO, Fr>>(
+        input: &[u8],
+    ) -> usize {
+        let mut rng = ark_std::test_rng();
+        let parameters = RO::setup(&mut rng).unwrap();
+        let primitive_result = RO::evaluate(&parameters, input).unwrap();
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let input_var = UInt8::new_witness_vec(cs.clone(), input).unwrap();
+        let parameters_var = ROG::ParametersVar::new_constant(cs.clone(), &parameters).unwrap();
+        let before = cs.num_constraints();
+        let result_var = ROG::evaluate(&parameters_var, &input_var).unwrap();
+        let num_constraints = cs.num_constraints() - before;
+        assert_eq!(
+            result_var.to_bytes().unwrap().value().unwrap(),
+            ark_ff::to_bytes![primitive_result].unwrap()
+        );
+        assert!(cs.is_satisfied().unwrap());
+        num_constraints
+    }
+
+    #[test]
+    fn poseidon_is_cheaper_than_blake2s() {
+        // The input of the random oracle in Schnorr signatures over Jubjub: a public
+        // key, a commitment, and a transaction.
+        let input = (0..160u8).collect::<Vec<_>>();
+        let blake2s =
+            random_oracle_constraints::<blake2s::RO, blake2s::constraints::ROGadget>(&input);
+        let poseidon =
+            random_oracle_constraints::<poseidon::RO<Fr>, poseidon::constraints::ROGadget>(&input);
+        assert!(poseidon * 10 < blake2s);
+
+        // Inputs of different lengths are domain-separated.
+        let mut rng = ark_std::test_rng();
+        let parameters = poseidon::RO::<Fr>::setup(&mut rng).unwrap();
+        assert_ne!(
+            poseidon::RO::evaluate(&parameters, &[1]).unwrap(),
+            poseidon::RO::evaluate(&parameters, &[1, 0]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use super::RandomOracle;
+use crate::poseidon::{bytes_to_field_elements, PoseidonParameters};
+use ark_crypto_primitives::Error;
+use ark_ff::PrimeField;
+use ark_std::marker::PhantomData;
+use ark_std::rand::Rng;
+
+/// A random oracle based on the Poseidon permutation, which is much cheaper than
+/// Blake2s to evaluate in a circuit over `F`.
+pub struct RO<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+impl<F: PrimeField> RandomOracle for RO<F> {
+    type Parameters = PoseidonParameters<F>;
+    type Output = F;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(PoseidonParameters::default())
+    }
+
+    fn evaluate(parameters: &Self::Parameters, input: &[u8]) -> Result<Self::Output, Error> {
+        // The length of the input determines the number of field elements.
+        Ok(parameters.hash(input.len() as u64, &bytes_to_field_elements(input)))
+    }
+}
@@ -7,7 +7,10 @@ use crate::signature::SignatureScheme;
 pub trait SigVerifyGadget<S: SignatureScheme, ConstraintF: Field> {
     type ParametersVar: AllocVar<S::Parameters, ConstraintF> + Clone;
 
-    type PublicKeyVar: ToBytesGadget<ConstraintF> + AllocVar<S::PublicKey, ConstraintF> + Clone;
+    type PublicKeyVar: ToBytesGadget<ConstraintF>
+        + EqGadget<ConstraintF>
+        + AllocVar<S::PublicKey, ConstraintF>
+        + Clone;
 
     type SignatureVar: AllocVar<S::Signature, ConstraintF> + Clone;
 
@@ -37,6 +40,7 @@ pub trait SigRandomizePkGadget<S: SignatureScheme, ConstraintF: Field> {
 
 #[cfg(test)]
 mod test {
+    use crate::random_oracle::poseidon::{self, constraints::ROGadget as PoseidonROGadget};
     use crate::signature::{schnorr, schnorr::constraints::*, *};
     use ark_ec::ProjectiveCurve;
     use ark_ed_on_bls12_381::constraints::EdwardsVar as JubJubVar;
@@ -46,14 +50,16 @@ mod test {
     use ark_relations::r1cs::ConstraintSystem;
     use ark_std::test_rng;
 
+    /// Sign `message` and verify the signature, natively and in-circuit. Returns
+    /// the number of constraints used to verify the signature.
     fn sign_and_verify<F: PrimeField, S: SignatureScheme, SG: SigVerifyGadget<S, F>>(
         message: &[u8],
-    ) {
+    ) -> usize {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
 
         let cs = ConstraintSystem::<F>::new_ref();
 
@@ -61,13 +67,14 @@ mod test {
         let signature_var = SG::SignatureVar::new_witness(cs.clone(), || Ok(&sig)).unwrap();
         let pk_var = SG::PublicKeyVar::new_witness(cs.clone(), || Ok(&pk)).unwrap();
         let mut msg_var = Vec::new();
-        for i in 0..message.len() {
-            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(&message[i])).unwrap())
+        for byte in message {
+            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(byte)).unwrap())
         }
         let valid_sig_var = SG::verify(&parameters_var, &pk_var, &msg_var, &signature_var).unwrap();
 
         valid_sig_var.enforce_equal(&Boolean::<F>::TRUE).unwrap();
         assert!(cs.is_satisfied().unwrap());
+        cs.num_constraints()
     }
 
     fn failed_verification<S: SignatureScheme>(message: &[u8], bad_message: &[u8]) {
@@ -78,6 +85,72 @@ mod test {
         assert!(!S::verify(&parameters, &pk, bad_message, &sig).unwrap());
     }
 
+    /// Randomize a public key natively and in-circuit, and check that both agree, and
+    /// that a signature under the randomized secret key verifies in-circuit under the
+    /// randomized public key.
+    fn randomize_and_verify<F, S, SG>(message: &[u8])
+    where
+        F: PrimeField,
+        S: SignatureScheme,
+        SG: SigVerifyGadget<S, F>
+            + SigRandomizePkGadget<
+                S,
+                F,
+                ParametersVar = <SG as SigVerifyGadget<S, F>>::ParametersVar,
+                PublicKeyVar = <SG as SigVerifyGadget<S, F>>::PublicKeyVar,
+            >,
+    {
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let randomness = b"some randomness";
+        let randomized_pk = S::randomize_public_key(&parameters, &pk, randomness).unwrap();
+        let randomized_sk = S::randomize_secret_key(&parameters, &sk, randomness).unwrap();
+        let sig = S::sign(&parameters, &randomized_sk, message, rng).unwrap();
+
+        for (randomness, expect_satisfied) in [(&randomness[..], true), (b"other", false)] {
+            let cs = ConstraintSystem::<F>::new_ref();
+            let parameters_var =
+                <SG as SigVerifyGadget<S, F>>::ParametersVar::new_constant(cs.clone(), &parameters)
+                    .unwrap();
+            let pk_var =
+                <SG as SigVerifyGadget<S, F>>::PublicKeyVar::new_witness(cs.clone(), || Ok(&pk))
+                    .unwrap();
+            let randomness_var = UInt8::new_witness_vec(cs.clone(), randomness).unwrap();
+            let randomized_pk_var =
+                <SG as SigVerifyGadget<S, F>>::PublicKeyVar::new_input(cs.clone(), || {
+                    Ok(&randomized_pk)
+                })
+                .unwrap();
+            let signature_var = SG::SignatureVar::new_witness(cs.clone(), || Ok(&sig)).unwrap();
+            let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+
+            let randomized_var = SG::randomize(&parameters_var, &pk_var, &randomness_var).unwrap();
+            randomized_var.enforce_equal(&randomized_pk_var).unwrap();
+            SG::verify(&parameters_var, &randomized_var, &msg_var, &signature_var)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            assert_eq!(cs.is_satisfied().unwrap(), expect_satisfied);
+        }
+    }
+
+    #[test]
+    fn schnorr_randomize_pk_test() {
+        type F = <JubJub as ProjectiveCurve>::BaseField;
+        let message = "Hi, I am a Schnorr signature!".as_bytes();
+        randomize_and_verify::<
+            F,
+            schnorr::Schnorr<JubJub>,
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar>,
+        >(message);
+        randomize_and_verify::<
+            F,
+            schnorr::Schnorr<JubJub, poseidon::RO<F>>,
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar, poseidon::RO<F>, PoseidonROGadget>,
+        >(message);
+    }
+
     #[test]
     fn schnorr_signature_test() {
         type F = <JubJub as ProjectiveCurve>::BaseField;
@@ -92,4 +165,85 @@ mod test {
             "Bad message".as_bytes(),
         );
     }
+
+    #[test]
+    fn schnorr_signature_with_poseidon_test() {
+        type F = <JubJub as ProjectiveCurve>::BaseField;
+        type PoseidonSchnorr = schnorr::Schnorr<JubJub, poseidon::RO<F>>;
+        let message = "Hi, I am a Schnorr signature!";
+        let poseidon_constraints = sign_and_verify::<
+            F,
+            PoseidonSchnorr,
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar, poseidon::RO<F>, PoseidonROGadget>,
+        >(message.as_bytes());
+        failed_verification::<PoseidonSchnorr>(message.as_bytes(), "Bad message".as_bytes());
+
+        let blake2s_constraints = sign_and_verify::<
+            F,
+            schnorr::Schnorr<JubJub>,
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar>,
+        >(message.as_bytes());
+        assert!(poseidon_constraints * 2 < blake2s_constraints);
+    }
+
+    #[test]
+    fn threshold_signature_test() {
+        type F = <JubJub as ProjectiveCurve>::BaseField;
+        type S = schnorr::Schnorr<JubJub, poseidon::RO<F>>;
+        type SG =
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar, poseidon::RO<F>, PoseidonROGadget>;
+        type Threshold = threshold::Threshold<S, F, 3>;
+        type ThresholdGadget = threshold::constraints::ThresholdSignatureVerifyGadget<S, SG, F, 3>;
+        let message = "Hi, I am a threshold signature!".as_bytes();
+        sign_and_verify::<F, Threshold, ThresholdGadget>(message);
+
+        // A 2-of-3 account, signed by one and then by two of its keys.
+        let rng = &mut test_rng();
+        let parameters = Threshold::setup(rng).unwrap();
+        let (pk, sk) = Threshold::keygen(&parameters, rng).unwrap();
+        let pk = threshold::PublicKey::<S, F, 3>::new(2, pk.keys).unwrap();
+        for (num_signers, expect_valid) in [(1, false), (2, true)] {
+            let mut signer_sk = sk.clone();
+            for key in &mut signer_sk.keys[num_signers..] {
+                *key = None;
+            }
+            let sig = Threshold::sign(&parameters, &signer_sk, message, rng).unwrap();
+            assert_eq!(
+                Threshold::verify(&parameters, &pk, message, &sig).unwrap(),
+                expect_valid
+            );
+
+            let cs = ConstraintSystem::<F>::new_ref();
+            let parameters_var =
+                <ThresholdGadget as SigVerifyGadget<Threshold, F>>::ParametersVar::new_constant(
+                    cs.clone(),
+                    &parameters,
+                )
+                .unwrap();
+            let pk_var =
+                <ThresholdGadget as SigVerifyGadget<Threshold, F>>::PublicKeyVar::new_witness(
+                    cs.clone(),
+                    || Ok(&pk),
+                )
+                .unwrap();
+            let signature_var =
+                <ThresholdGadget as SigVerifyGadget<Threshold, F>>::SignatureVar::new_witness(
+                    cs.clone(),
+                    || Ok(&sig),
+                )
+                .unwrap();
+            let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+            let valid_sig_var =
+                ThresholdGadget::verify(&parameters_var, &pk_var, &msg_var, &signature_var)
+                    .unwrap();
+            assert_eq!(valid_sig_var.value().unwrap(), expect_valid);
+
+            // The public key is encoded in-circuit like it is natively.
+            assert_eq!(
+                pk_var.to_bytes().unwrap().value().unwrap(),
+                ark_ff::to_bytes![pk].unwrap()
+            );
+            assert!(cs.is_satisfied().unwrap());
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use ark_crypto_primitives::Error;
 use ark_ff::bytes::ToBytes;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::fmt::Debug;
 use ark_std::hash::Hash;
 use ark_std::rand::Rng;
 
@@ -9,12 +11,30 @@ pub mod constraints;
 pub use constraints::*;
 
 pub mod schnorr;
+pub mod threshold;
 
+/// A signature scheme. The ledger is generic over the scheme that authorizes
+/// transactions, so the parameters, public keys and signatures must be serializable.
 pub trait SignatureScheme {
-    type Parameters: Clone + Send + Sync;
-    type PublicKey: ToBytes + Hash + Eq + Clone + Default + Send + Sync;
+    type Parameters: Clone + Send + Sync + CanonicalSerialize + CanonicalDeserialize;
+    type PublicKey: ToBytes
+        + Hash
+        + Eq
+        + Clone
+        + Default
+        + Debug
+        + Send
+        + Sync
+        + CanonicalSerialize
+        + CanonicalDeserialize;
     type SecretKey: ToBytes + Clone + Default;
-    type Signature: Clone + Default + Send + Sync;
+    type Signature: Clone
+        + Default
+        + Debug
+        + Send
+        + Sync
+        + CanonicalSerialize
+        + CanonicalDeserialize;
 
     fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error>;
 
@@ -36,20 +56,78 @@ pub trait SignatureScheme {
         message: &[u8],
         signature: &Self::Signature,
     ) -> Result<bool, Error>;
+
+    /// Whether signatures under `pk` can verify at all. The ledger refuses to register
+    /// other keys. The default implementation accepts every key; schemes whose keys
+    /// must be checked beyond deserialization override it.
+    fn is_valid_public_key(_pk: &Self::PublicKey) -> bool {
+        true
+    }
+
+    /// Randomize `public_key` with `randomness`, so that the result cannot be linked
+    /// to `public_key` by anyone who does not know `randomness`.
+    fn randomize_public_key(
+        pp: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error>;
+
+    /// Randomize `secret_key` with `randomness`, so that signatures under the result
+    /// verify under the public key randomized with the same `randomness`.
+    ///
+    /// This takes the place of a `randomize_signature` method, which would move an
+    /// existing signature to the randomized public key. Whoever can do that can also
+    /// move a signature that they merely observed to any randomization of the key,
+    /// which is why `schnorr::Schnorr` binds the public key in its challenges and
+    /// cannot implement it. Instead, the signer signs with the randomized secret key,
+    /// as with the re-randomized spend authorization keys of Zcash.
+    fn randomize_secret_key(
+        pp: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error>;
+
+    /// Verify a batch of signatures, where `signatures[i]` is a signature on
+    /// `messages[i]` under `pks[i]`. Returns the index of the first invalid
+    /// signature, or `None` if all of them are valid.
+    ///
+    /// The default implementation verifies the signatures one at a time; schemes
+    /// can override it with something faster.
+    fn batch_verify<M: AsRef<[u8]>>(
+        pp: &Self::Parameters,
+        pks: &[Self::PublicKey],
+        messages: &[M],
+        signatures: &[Self::Signature],
+    ) -> Result<Option<usize>, Error> {
+        if pks.len() != messages.len() || pks.len() != signatures.len() {
+            return Err("batch has mismatched lengths".into());
+        }
+        for (i, ((pk, message), signature)) in pks.iter().zip(messages).zip(signatures).enumerate()
+        {
+            if !Self::verify(pp, pk, message.as_ref(), signature)? {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::random_oracle::{blake2s, poseidon, RandomOracle};
     use crate::signature::{schnorr, *};
-    use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+    use ark_ec::{AffineCurve, ProjectiveCurve};
+    use ark_ed_on_bls12_381::{EdwardsProjective as JubJub, Fq};
+    use ark_ff::{to_bytes, PrimeField};
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
     use ark_std::test_rng;
 
     fn sign_and_verify<S: SignatureScheme>(message: &[u8]) {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
     }
 
     fn failed_verification<S: SignatureScheme>(message: &[u8], bad_message: &[u8]) {
@@ -68,5 +146,210 @@ mod test {
             message.as_bytes(),
             "Bad message".as_bytes(),
         );
+        sign_and_verify::<schnorr::Schnorr<JubJub, poseidon::RO<Fq>>>(message.as_bytes());
+        failed_verification::<schnorr::Schnorr<JubJub, poseidon::RO<Fq>>>(
+            message.as_bytes(),
+            "Bad message".as_bytes(),
+        );
+    }
+
+    fn batch_verification<S: SignatureScheme>() {
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let messages = (0..40u8).map(|i| vec![i; 10]).collect::<Vec<_>>();
+        let mut pks = Vec::new();
+        let mut sigs = Vec::new();
+        for message in &messages {
+            let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+            sigs.push(S::sign(&parameters, &sk, message, rng).unwrap());
+            pks.push(pk);
+        }
+        assert_eq!(
+            S::batch_verify(&parameters, &pks, &messages, &sigs).unwrap(),
+            None
+        );
+        assert_eq!(
+            S::batch_verify::<&[u8]>(&parameters, &[], &[], &[]).unwrap(),
+            None
+        );
+
+        // The first invalid signature is reported.
+        let mut bad_messages = messages.clone();
+        bad_messages[31] = b"Bad message".to_vec();
+        bad_messages[35] = b"Bad message".to_vec();
+        assert_eq!(
+            S::batch_verify(&parameters, &pks, &bad_messages, &sigs).unwrap(),
+            Some(31)
+        );
+        pks.swap(3, 4);
+        assert_eq!(
+            S::batch_verify(&parameters, &pks, &messages, &sigs).unwrap(),
+            Some(3)
+        );
+        assert!(S::batch_verify(&parameters, &pks, &messages[1..], &sigs).is_err());
+    }
+
+    fn key_randomization<S: SignatureScheme>(message: &[u8]) {
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let randomness = b"some randomness";
+        let randomized_pk = S::randomize_public_key(&parameters, &pk, randomness).unwrap();
+        let randomized_sk = S::randomize_secret_key(&parameters, &sk, randomness).unwrap();
+        assert!(randomized_pk != pk);
+
+        let sig = S::sign(&parameters, &randomized_sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &randomized_pk, message, &sig).unwrap());
+        assert!(!S::verify(&parameters, &pk, message, &sig).unwrap());
+        let other_pk = S::randomize_public_key(&parameters, &pk, b"other randomness").unwrap();
+        assert!(!S::verify(&parameters, &other_pk, message, &sig).unwrap());
+    }
+
+    #[test]
+    fn schnorr_key_randomization_test() {
+        let message = "Hi, I am a Schnorr signature!".as_bytes();
+        key_randomization::<schnorr::Schnorr<JubJub>>(message);
+        key_randomization::<schnorr::Schnorr<JubJub, poseidon::RO<Fq>>>(message);
+    }
+
+    /// Sign `message` deterministically with a fixed secret key, and return the
+    /// signature as a hex string.
+    fn deterministic_signature<RO: RandomOracle>(
+        message: &[u8],
+        extra_randomness: &[u8],
+    ) -> String {
+        type Scalar = <JubJub as ProjectiveCurve>::ScalarField;
+        let parameters = schnorr::Schnorr::<JubJub, RO>::setup(&mut test_rng()).unwrap();
+        let secret_key = Scalar::from_le_bytes_mod_order(b"a fixed secret key for test vectors");
+        let sk = schnorr::SecretKey {
+            secret_key,
+            public_key: parameters.generator.mul(secret_key).into_affine(),
+        };
+        let sig = schnorr::Schnorr::<JubJub, RO>::sign_deterministic(
+            &parameters,
+            &sk,
+            message,
+            extra_randomness,
+        )
+        .unwrap();
+        assert!(
+            schnorr::Schnorr::<JubJub, RO>::verify(&parameters, &sk.public_key, message, &sig)
+                .unwrap()
+        );
+        let mut bytes = Vec::new();
+        sig.serialize(&mut bytes).unwrap();
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn schnorr_deterministic_signature_test() {
+        // Signatures are encoded as the prover commitment followed by the prover
+        // response. These vectors must only change along with the nonce derivation.
+        let message = "Hi, I am a Schnorr signature!".as_bytes();
+        assert_eq!(
+            deterministic_signature::<blake2s::RO>(message, &[]),
+            "9af45888b29daffa4e36e2b2cc5692e044381598ea1139c2b6ceb8c2c3bbdbcb\
+             eac47ec7337b59e852f7aa1bf07f6b2d3233c9e7ef84aace34d77a13136b500b"
+        );
+        assert_eq!(
+            deterministic_signature::<blake2s::RO>(message, b"extra randomness"),
+            "6e840d70ad196a546a8c214d777e66e1f848c0d69057b0c8fadcf0fe8ec1eb49\
+             d741bace59bc399873f21ae415aaeab1becb3e55cfb3bb06025c734450c7ed04"
+        );
+        assert_eq!(
+            deterministic_signature::<blake2s::RO>(b"Another message", &[]),
+            "798641b4a0747e3ba6fc4c21edd0f3440664bf985579bcd135b57f1bf807ff5e\
+             84b1e4aa3a5499eede86d9bd0fb211e3f163b0baa7317377304af0572c538d04"
+        );
+        assert_eq!(
+            deterministic_signature::<poseidon::RO<Fq>>(message, &[]),
+            "9af45888b29daffa4e36e2b2cc5692e044381598ea1139c2b6ceb8c2c3bbdbcb\
+             4eabf0f00d85a51f694c145569ae2f402d75254c37a1c1f5f00f195197d5200b"
+        );
+    }
+
+    #[test]
+    fn schnorr_batch_verification_test() {
+        batch_verification::<schnorr::Schnorr<JubJub>>();
+        batch_verification::<schnorr::Schnorr<JubJub, poseidon::RO<Fq>>>();
+    }
+
+    #[test]
+    fn schnorr_serialization_test() {
+        let rng = &mut test_rng();
+        let message = "Hi, I am a Schnorr signature!".as_bytes();
+        let mut parameters = schnorr::Schnorr::<JubJub>::setup(rng).unwrap();
+        parameters.salt = Some([7u8; 32]);
+        let (pk, sk) = schnorr::Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let sig = schnorr::Schnorr::<JubJub>::sign(&parameters, &sk, message, rng).unwrap();
+
+        let mut bytes = Vec::new();
+        parameters.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), parameters.serialized_size());
+        let parameters = schnorr::Parameters::<JubJub>::deserialize(&bytes[..]).unwrap();
+        assert_eq!(parameters.salt, Some([7u8; 32]));
+
+        let mut bytes = Vec::new();
+        sig.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), sig.serialized_size());
+        let sig = schnorr::Signature::<JubJub>::deserialize(&bytes[..]).unwrap();
+        assert!(schnorr::Schnorr::<JubJub>::verify(&parameters, &pk, message, &sig).unwrap());
+
+        // Truncated signatures are rejected.
+        assert!(schnorr::Signature::<JubJub>::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn threshold_signature_test() {
+        type Threshold = threshold::Threshold<schnorr::Schnorr<JubJub>, Fq, 3>;
+        type PublicKey = threshold::PublicKey<schnorr::Schnorr<JubJub>, Fq, 3>;
+        let message = "Hi, I am a threshold signature!".as_bytes();
+        sign_and_verify::<Threshold>(message);
+        failed_verification::<Threshold>(message, "Bad message".as_bytes());
+        key_randomization::<Threshold>(message);
+
+        // Three signers, each holding one of the keys of a 2-of-3 account.
+        let rng = &mut test_rng();
+        let parameters = Threshold::setup(rng).unwrap();
+        let (pk, sk) = Threshold::keygen(&parameters, rng).unwrap();
+        let pk = PublicKey::new(2, pk.keys).unwrap();
+        let signatures = (0..3)
+            .map(|i| {
+                let mut signer_sk = threshold::SecretKey::default();
+                signer_sk.keys[i] = sk.keys[i].clone();
+                Threshold::sign(&parameters, &signer_sk, message, rng).unwrap()
+            })
+            .collect::<Vec<_>>();
+        for sig in &signatures {
+            assert!(!Threshold::verify(&parameters, &pk, message, sig).unwrap());
+        }
+        let sig = threshold::Signature::combine(&signatures[1..]);
+        assert!(Threshold::verify(&parameters, &pk, message, &sig).unwrap());
+        assert!(!Threshold::verify(&parameters, &pk, b"Bad message", &sig).unwrap());
+
+        // The threshold is part of the encoding of the public key.
+        let one_of_three = PublicKey::new(1, pk.keys.clone()).unwrap();
+        assert_ne!(to_bytes![pk].unwrap(), to_bytes![one_of_three].unwrap());
+        assert!(PublicKey::new(0, pk.keys.clone()).is_none());
+        assert!(PublicKey::new(4, pk.keys.clone()).is_none());
+        assert!(PublicKey::new(2, pk.keys[1..].to_vec()).is_none());
+
+        // Each key counts once, so keys cannot be repeated.
+        let repeated_keys = vec![pk.keys[0], pk.keys[1], pk.keys[0]];
+        assert!(PublicKey::new(2, repeated_keys.clone()).is_none());
+        let mut bytes = Vec::new();
+        2u8.serialize(&mut bytes).unwrap();
+        repeated_keys.serialize(&mut bytes).unwrap();
+        assert!(PublicKey::deserialize(&bytes[..]).is_err());
+
+        let mut bytes = Vec::new();
+        pk.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), pk.serialized_size());
+        assert_eq!(PublicKey::deserialize(&bytes[..]).unwrap(), pk);
+        let mut bytes = Vec::new();
+        sig.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), sig.serialized_size());
+        let sig = threshold::Signature::deserialize(&bytes[..]).unwrap();
+        assert!(Threshold::verify(&parameters, &pk, message, &sig).unwrap());
     }
 }
@@ -1,13 +1,12 @@
-use ark_ec::ProjectiveCurve;
-use ark_ff::{to_bytes, Field};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{to_bytes, BitIteratorBE, Field};
 use ark_r1cs_std::{bits::uint8::UInt8, prelude::*};
 use ark_relations::r1cs::ConstraintSystemRef;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_std::vec::Vec;
 
-use crate::random_oracle::blake2s::constraints::{ParametersVar as B2SParamsVar, ROGadget};
-use crate::random_oracle::RandomOracleGadget;
-use crate::signature::SigVerifyGadget;
+use crate::random_oracle::{blake2s, RandomOracle, RandomOracleGadget};
+use crate::signature::{SigRandomizePkGadget, SigVerifyGadget};
 
 use derivative::Derivative;
 
@@ -50,26 +49,36 @@ pub struct SignatureVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
 where
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
+    prover_commitment: GC,
     prover_response: Vec<UInt8<ConstraintF<C>>>,
-    verifier_challenge: Vec<UInt8<ConstraintF<C>>>,
     #[doc(hidden)]
-    _group: PhantomData<GC>,
+    _group: PhantomData<*const C>,
 }
 
-pub struct SchnorrSignatureVerifyGadget<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
-where
+/// Verifies `Schnorr<C, RO>` signatures, evaluating the random oracle `RO` with `ROG`.
+pub struct SchnorrSignatureVerifyGadget<
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    RO: RandomOracle = blake2s::RO,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>> = blake2s::constraints::ROGadget,
+> where
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
     #[doc(hidden)]
     _group: PhantomData<*const C>,
     #[doc(hidden)]
     _group_gadget: PhantomData<*const GC>,
+    #[doc(hidden)]
+    _random_oracle: PhantomData<*const (RO, ROG)>,
 }
 
-impl<C, GC> SigVerifyGadget<Schnorr<C>, ConstraintF<C>> for SchnorrSignatureVerifyGadget<C, GC>
+impl<C, GC, RO, ROG> SigVerifyGadget<Schnorr<C, RO>, ConstraintF<C>>
+    for SchnorrSignatureVerifyGadget<C, GC, RO, ROG>
 where
     C: ProjectiveCurve,
     GC: CurveVar<C, ConstraintF<C>>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
     type ParametersVar = ParametersVar<C, GC>;
@@ -82,31 +91,69 @@ where
         message: &[UInt8<ConstraintF<C>>],
         signature: &Self::SignatureVar,
     ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
-        let prover_response = signature.prover_response.clone();
-        let verifier_challenge = signature.verifier_challenge.clone();
-        let mut claimed_prover_commitment = parameters
+        let mut hash_input = Vec::new();
+        if let Some(salt) = parameters.salt.as_ref() {
+            hash_input.extend_from_slice(salt);
+        }
+        hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
+        hash_input.extend_from_slice(&signature.prover_commitment.to_bytes()?);
+        hash_input.extend_from_slice(message);
+
+        let ro_params =
+            ROG::ParametersVar::new_constant(ConstraintSystemRef::None, RO::Parameters::default())?;
+        let verifier_challenge = ROG::evaluate(&ro_params, &hash_input)?.to_bytes()?;
+
+        // sG + eY - kG = 0 once multiplied by the cofactor, like `Schnorr::verify`.
+        // The public key and the prover commitment are allocated without projecting
+        // them onto the prime-order subgroup, so a point of small order in either
+        // stays in the challenge as it does natively.
+        let mut difference = parameters
             .generator
-            .scalar_mul_le(prover_response.to_bits_le()?.iter())?;
+            .scalar_mul_le(signature.prover_response.to_bits_le()?.iter())?;
         let public_key_times_verifier_challenge = public_key
             .pub_key
             .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
-        claimed_prover_commitment += &public_key_times_verifier_challenge;
+        difference += &public_key_times_verifier_challenge;
+        difference -= &signature.prover_commitment;
 
-        let mut hash_input = Vec::new();
-        if parameters.salt.is_some() {
-            hash_input.extend_from_slice(parameters.salt.as_ref().unwrap());
+        let mut difference_times_cofactor = GC::zero();
+        for bit in BitIteratorBE::without_leading_zeros(C::Affine::COFACTOR) {
+            difference_times_cofactor.double_in_place()?;
+            if bit {
+                difference_times_cofactor += &difference;
+            }
         }
-        hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
-        hash_input.extend_from_slice(&claimed_prover_commitment.to_bytes()?);
-        hash_input.extend_from_slice(message);
+        // Not `is_zero`, which never holds for twisted Edwards curves in
+        // ark-r1cs-std 0.3.
+        difference_times_cofactor.is_eq(&GC::zero())
+    }
+}
 
-        let b2s_params = <B2SParamsVar as AllocVar<_, ConstraintF<C>>>::new_constant(
-            ConstraintSystemRef::None,
-            (),
-        )?;
-        let obtained_verifier_challenge = ROGadget::evaluate(&b2s_params, &hash_input)?.0;
+impl<C, GC, RO, ROG> SigRandomizePkGadget<Schnorr<C, RO>, ConstraintF<C>>
+    for SchnorrSignatureVerifyGadget<C, GC, RO, ROG>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
 
-        obtained_verifier_challenge.is_eq(&verifier_challenge.to_vec())
+    /// Y' = Y + rG, like `Schnorr::randomize_public_key`.
+    fn randomize(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        randomness: &[UInt8<ConstraintF<C>>],
+    ) -> Result<Self::PublicKeyVar, SynthesisError> {
+        let randomness_times_generator = parameters
+            .generator
+            .scalar_mul_le(randomness.to_bits_le()?.iter())?;
+        Ok(PublicKeyVar {
+            pub_key: randomness_times_generator + &public_key.pub_key,
+            _group: PhantomData,
+        })
     }
 }
 
@@ -161,7 +208,11 @@ where
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
-        let pub_key = GC::new_variable(cs, f, mode)?;
+        let pub_key = GC::new_variable_omit_prime_order_check(
+            cs,
+            || f().map(|pk| pk.borrow().into_projective()),
+            mode,
+        )?;
         Ok(Self {
             pub_key,
             _group: PhantomData,
@@ -182,10 +233,13 @@ where
     ) -> Result<Self, SynthesisError> {
         f().and_then(|val| {
             let cs = cs.into();
+            let prover_commitment = GC::new_variable_omit_prime_order_check(
+                cs.clone(),
+                || Ok(val.borrow().prover_commitment.into_projective()),
+                mode,
+            )?;
             let response_bytes = to_bytes![val.borrow().prover_response].unwrap();
-            let challenge_bytes = val.borrow().verifier_challenge;
             let mut prover_response = Vec::<UInt8<ConstraintF<C>>>::new();
-            let mut verifier_challenge = Vec::<UInt8<ConstraintF<C>>>::new();
             for byte in &response_bytes {
                 prover_response.push(UInt8::<ConstraintF<C>>::new_variable(
                     cs.clone(),
@@ -193,16 +247,9 @@ where
                     mode,
                 )?);
             }
-            for byte in &challenge_bytes {
-                verifier_challenge.push(UInt8::<ConstraintF<C>>::new_variable(
-                    cs.clone(),
-                    || Ok(byte),
-                    mode,
-                )?);
-            }
             Ok(SignatureVar {
+                prover_commitment,
                 prover_response,
-                verifier_challenge,
                 _group: PhantomData,
             })
         })
@@ -1,23 +1,29 @@
 use super::SignatureScheme;
 use ark_crypto_primitives::Error;
-use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ec::{msm::VariableBaseMSM, AffineCurve, ProjectiveCurve};
 use ark_ff::{
     bytes::ToBytes,
-    fields::{Field, PrimeField},
-    to_bytes, ToConstraintField, UniformRand,
+    fields::{Field, FpParameters, PrimeField},
+    to_bytes, ToConstraintField, UniformRand, Zero,
 };
-use ark_std::io::{Result as IoResult, Write};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Result as IoResult, Write};
 use ark_std::rand::Rng;
 use ark_std::{hash::Hash, marker::PhantomData, vec::Vec};
-use blake2::Blake2s;
-use digest::Digest;
 
+use crate::random_oracle::{blake2s, RandomOracle};
+
+use blake2::Blake2s;
 use derivative::Derivative;
+use digest::Digest;
 #[cfg(feature = "r1cs")]
 pub mod constraints;
 
-pub struct Schnorr<C: ProjectiveCurve> {
+/// Schnorr signatures over the group `C`, whose verifier challenges are computed
+/// with the random oracle `RO`.
+pub struct Schnorr<C: ProjectiveCurve, RO: RandomOracle = blake2s::RO> {
     _group: PhantomData<C>,
+    _random_oracle: PhantomData<RO>,
 }
 
 #[derive(Derivative)]
@@ -42,13 +48,15 @@ impl<C: ProjectiveCurve> ToBytes for SecretKey<C> {
     }
 }
 
+/// A signature (R, s), where R = kG is the prover commitment, and s = k - xe is the
+/// prover response to the verifier challenge e = H(salt || pubkey || R || msg).
 #[derive(Clone, Default, Debug)]
 pub struct Signature<C: ProjectiveCurve> {
+    pub prover_commitment: C::Affine,
     pub prover_response: C::ScalarField,
-    pub verifier_challenge: [u8; 32],
 }
 
-impl<C: ProjectiveCurve + Hash> SignatureScheme for Schnorr<C>
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> SignatureScheme for Schnorr<C, RO>
 where
     C::ScalarField: PrimeField,
 {
@@ -94,44 +102,9 @@ where
         message: &[u8],
         rng: &mut R,
     ) -> Result<Self::Signature, Error> {
-        // let sign_time = start_timer!(|| "SchnorrSig::Sign");
-        // (k, e);
-        let (random_scalar, verifier_challenge) = {
-            // Sample a random scalar `k` from the prime scalar field.
-            let random_scalar: C::ScalarField = C::ScalarField::rand(rng);
-            // Commit to the random scalar via r := k · G.
-            // This is the prover's first msg in the Sigma protocol.
-            let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
-
-            // Hash everything to get verifier challenge.
-            // e := H(salt || pubkey || r || msg);
-            let mut hash_input = Vec::new();
-            if parameters.salt != None {
-                hash_input.extend_from_slice(&parameters.salt.unwrap());
-            }
-            hash_input.extend_from_slice(&to_bytes![sk.public_key]?);
-            hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
-            hash_input.extend_from_slice(message);
-
-            let hash_digest = Blake2s::digest(&hash_input);
-            assert!(hash_digest.len() >= 32);
-            let mut verifier_challenge = [0u8; 32];
-            verifier_challenge.copy_from_slice(&hash_digest);
-
-            (random_scalar, verifier_challenge)
-        };
-
-        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
-
-        // k - xe;
-        let prover_response = random_scalar - (verifier_challenge_fe * sk.secret_key);
-        let signature = Signature {
-            prover_response,
-            verifier_challenge,
-        };
-
-        // end_timer!(sign_time);
-        Ok(signature)
+        // Sample a random scalar `k` from the prime scalar field.
+        let random_scalar: C::ScalarField = C::ScalarField::rand(rng);
+        Self::sign_with_nonce(parameters, sk, message, random_scalar)
     }
 
     fn verify(
@@ -143,33 +116,278 @@ where
         // let verify_time = start_timer!(|| "SchnorrSig::Verify");
 
         let Signature {
+            prover_commitment,
             prover_response,
-            verifier_challenge,
         } = signature;
-        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(verifier_challenge);
+        // e = H(salt, Y, kG, msg)
+        let verifier_challenge = Self::verifier_challenge(
+            parameters,
+            &RO::Parameters::default(),
+            pk,
+            prover_commitment,
+            message,
+        )?;
+        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
+
         // sG = kG - eY
-        // kG = sG + eY
-        // so we first solve for kG.
-        let mut claimed_prover_commitment = parameters.generator.mul(*prover_response);
-        let public_key_times_verifier_challenge = pk.mul(verifier_challenge_fe);
-        claimed_prover_commitment += &public_key_times_verifier_challenge;
-        let claimed_prover_commitment = claimed_prover_commitment.into_affine();
-
-        // e = H(salt, kG, msg)
+        // so the signature is valid iff sG + eY - kG = 0.
+        let mut difference = parameters.generator.mul(*prover_response);
+        difference += &pk.mul(verifier_challenge_fe);
+        difference.add_assign_mixed(&-*prover_commitment);
+        // end_timer!(verify_time);
+        Ok(Self::is_zero_without_cofactor(difference))
+    }
+
+    /// Whether `pk` is in the prime-order subgroup. Deserialization checks this, but
+    /// the coordinates of a `PublicKey<C>` are public. Because `verify` clears the
+    /// cofactor, Y + T for a point T of small order is another key of the owner of Y.
+    fn is_valid_public_key(pk: &Self::PublicKey) -> bool {
+        pk.mul(<C::ScalarField as PrimeField>::Params::MODULUS)
+            .is_zero()
+    }
+
+    /// Y' = Y + rG, where the scalar r is read from `randomness`.
+    fn randomize_public_key(
+        parameters: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error> {
+        let randomness = C::ScalarField::from_le_bytes_mod_order(randomness);
+        let mut randomized_pk = parameters.generator.mul(randomness);
+        randomized_pk.add_assign_mixed(public_key);
+        Ok(randomized_pk.into_affine())
+    }
+
+    /// x' = x + r, so that x'G = Y + rG.
+    fn randomize_secret_key(
+        parameters: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error> {
+        Ok(SecretKey {
+            secret_key: secret_key.secret_key + C::ScalarField::from_le_bytes_mod_order(randomness),
+            public_key: Self::randomize_public_key(parameters, &secret_key.public_key, randomness)?,
+        })
+    }
+
+    /// Verify the batch with a random linear combination of the verification
+    /// equations: with random weights z_i, the signatures are all valid (except with
+    /// probability about 2^-128) iff
+    ///
+    /// (Σ z_i s_i) G + Σ (z_i e_i) Y_i - Σ z_i R_i = 0,
+    ///
+    /// which takes two multi-scalar multiplications rather than a scalar
+    /// multiplication per signature. If the check fails, the signatures are verified
+    /// one at a time to find the first invalid one.
+    fn batch_verify<M: AsRef<[u8]>>(
+        parameters: &Self::Parameters,
+        pks: &[Self::PublicKey],
+        messages: &[M],
+        signatures: &[Self::Signature],
+    ) -> Result<Option<usize>, Error> {
+        if pks.len() != messages.len() || pks.len() != signatures.len() {
+            return Err("batch has mismatched lengths".into());
+        }
+        if signatures.is_empty() {
+            return Ok(None);
+        }
+        let weights = Self::batch_weights(pks, messages, signatures)?;
+
+        let ro_parameters = RO::Parameters::default();
+        let mut generator_scalar = C::ScalarField::zero();
+        let mut pk_scalars = Vec::with_capacity(signatures.len());
+        for (((pk, message), signature), weight) in
+            pks.iter().zip(messages).zip(signatures).zip(&weights)
+        {
+            let verifier_challenge = Self::verifier_challenge(
+                parameters,
+                &ro_parameters,
+                pk,
+                &signature.prover_commitment,
+                message.as_ref(),
+            )?;
+            let verifier_challenge_fe =
+                C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
+            generator_scalar += *weight * signature.prover_response;
+            pk_scalars.push((*weight * verifier_challenge_fe).into_repr());
+        }
+        let prover_commitments = signatures
+            .iter()
+            .map(|signature| signature.prover_commitment)
+            .collect::<Vec<_>>();
+        let weights = weights
+            .iter()
+            .map(|weight| weight.into_repr())
+            .collect::<Vec<_>>();
+
+        let mut difference = VariableBaseMSM::multi_scalar_mul(pks, &pk_scalars);
+        difference += &parameters.generator.mul(generator_scalar);
+        difference -= &VariableBaseMSM::multi_scalar_mul(&prover_commitments, &weights);
+        if Self::is_zero_without_cofactor(difference) {
+            return Ok(None);
+        }
+
+        // `verify` clears the cofactor like the check above, so if the batch is
+        // invalid, one of the signatures is.
+        for (i, ((pk, message), signature)) in pks.iter().zip(messages).zip(signatures).enumerate()
+        {
+            if !Self::verify(parameters, pk, message.as_ref(), signature)? {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The Blake2s personalization of nonce derivation.
+const NONCE_PERSONALIZATION: &[u8; 8] = b"SchnNonc";
+
+/// The Blake2s personalization of the weights of batch verification.
+const BATCH_PERSONALIZATION: &[u8; 8] = b"SchnBtch";
+
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> Schnorr<C, RO>
+where
+    C::ScalarField: PrimeField,
+{
+    /// Sign `message` without an RNG, in the style of RFC 6979: the nonce is derived
+    /// from the secret key, `extra_randomness` and `message`, so signing the same
+    /// message twice with the same `extra_randomness` gives the same signature.
+    ///
+    /// `extra_randomness` may be empty. Otherwise, mixing fresh randomness into the
+    /// nonce protects against fault attacks, while a weak source of randomness still
+    /// cannot leak the secret key.
+    pub fn sign_deterministic(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        extra_randomness: &[u8],
+    ) -> Result<Signature<C>, Error> {
+        let nonce = Self::deterministic_nonce(sk, message, extra_randomness)?;
+        Self::sign_with_nonce(parameters, sk, message, nonce)
+    }
+
+    /// Derive the nonce k from 64 bytes of output of Blake2s, keyed with the secret
+    /// key, so that reducing them modulo the group order has negligible bias. In the
+    /// unlikely case that k = 0, the derivation is repeated with the next counter.
+    fn deterministic_nonce(
+        sk: &SecretKey<C>,
+        message: &[u8],
+        extra_randomness: &[u8],
+    ) -> Result<C::ScalarField, Error> {
+        let key = to_bytes![sk.secret_key]?;
+        if key.len() > 32 {
+            return Err("the secret key must be at most 32 bytes long".into());
+        }
+        for counter in 0u32.. {
+            let mut nonce_bytes = Vec::with_capacity(64);
+            for block in 0u8..2 {
+                let mut h = Blake2s::with_params(&key, &[], NONCE_PERSONALIZATION);
+                h.update(counter.to_le_bytes());
+                h.update([block]);
+                h.update((extra_randomness.len() as u64).to_le_bytes());
+                h.update(extra_randomness);
+                h.update(message);
+                nonce_bytes.extend_from_slice(&h.finalize());
+            }
+            let nonce = C::ScalarField::from_le_bytes_mod_order(&nonce_bytes);
+            if !nonce.is_zero() {
+                return Ok(nonce);
+            }
+        }
+        unreachable!()
+    }
+
+    /// Sign `message` with the nonce k.
+    fn sign_with_nonce(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        random_scalar: C::ScalarField,
+    ) -> Result<Signature<C>, Error> {
+        // Commit to the random scalar via r := k · G.
+        // This is the prover's first msg in the Sigma protocol.
+        let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
+
+        // Hash everything to get verifier challenge.
+        // e := H(salt || pubkey || r || msg);
+        let verifier_challenge = Self::verifier_challenge(
+            parameters,
+            &RO::Parameters::default(),
+            &sk.public_key,
+            &prover_commitment,
+            message,
+        )?;
+        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
+
+        // k - xe;
+        let prover_response = random_scalar - (verifier_challenge_fe * sk.secret_key);
+        Ok(Signature {
+            prover_commitment,
+            prover_response,
+        })
+    }
+
+    /// Derive the 128-bit weights of batch verification by hashing the whole batch,
+    /// so that the signer cannot choose the signatures after seeing the weights, and
+    /// batch verification needs no RNG.
+    fn batch_weights<M: AsRef<[u8]>>(
+        pks: &[PublicKey<C>],
+        messages: &[M],
+        signatures: &[Signature<C>],
+    ) -> Result<Vec<C::ScalarField>, Error> {
+        let mut h = Blake2s::with_params(&[], &[], BATCH_PERSONALIZATION);
+        for ((pk, message), signature) in pks.iter().zip(messages).zip(signatures) {
+            h.update(to_bytes![
+                pk,
+                signature.prover_commitment,
+                signature.prover_response
+            ]?);
+            h.update((message.as_ref().len() as u64).to_le_bytes());
+            h.update(message);
+        }
+        let seed = h.finalize();
+        Ok((0..signatures.len() as u64)
+            .map(|i| {
+                let mut h = Blake2s::with_params(&seed, &[], BATCH_PERSONALIZATION);
+                h.update(i.to_le_bytes());
+                C::ScalarField::from_le_bytes_mod_order(&h.finalize()[..16])
+            })
+            .collect())
+    }
+
+    /// Whether `point` is zero once multiplied by the cofactor. Signatures are
+    /// checked up to the small-order subgroup, so that checking a random linear
+    /// combination of them accepts exactly the batches whose signatures are all
+    /// valid. This only matters for points that were not deserialized, because
+    /// deserialization rejects points outside the prime-order subgroup.
+    fn is_zero_without_cofactor(point: C) -> bool {
+        point
+            .into_affine()
+            .mul_by_cofactor_to_projective()
+            .is_zero()
+    }
+}
+
+impl<C: ProjectiveCurve, RO: RandomOracle> Schnorr<C, RO> {
+    /// Compute the verifier challenge e := H(salt || pubkey || r || msg). The
+    /// challenge is the encoding of the output of `RO`, which is read as a
+    /// little-endian integer, like the bits that the gadget multiplies by.
+    fn verifier_challenge(
+        parameters: &Parameters<C>,
+        ro_parameters: &RO::Parameters,
+        pk: &PublicKey<C>,
+        prover_commitment: &C::Affine,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
         let mut hash_input = Vec::new();
-        if parameters.salt != None {
-            hash_input.extend_from_slice(&parameters.salt.unwrap());
+        if let Some(salt) = parameters.salt {
+            hash_input.extend_from_slice(&salt);
         }
         hash_input.extend_from_slice(&to_bytes![pk]?);
-        hash_input.extend_from_slice(&to_bytes![claimed_prover_commitment]?);
+        hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
         hash_input.extend_from_slice(message);
 
-        // cast the hash output to get e
-        let obtained_verifier_challenge = &Blake2s::digest(&hash_input)[..];
-        // end_timer!(verify_time);
-        // The signature is valid iff the computed verifier challenge is the same as the one
-        // provided in the signature
-        Ok(verifier_challenge == obtained_verifier_challenge)
+        Ok(to_bytes![RO::evaluate(ro_parameters, &hash_input)?]?)
     }
 }
 
@@ -184,6 +402,110 @@ pub fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
     bits
 }
 
+// The salt is encoded like an `Option`: a flag, followed by the 32 salt bytes if present.
+fn serialize_salt<W: Write>(
+    salt: &Option<[u8; 32]>,
+    mut writer: W,
+) -> Result<(), SerializationError> {
+    salt.is_some().serialize(&mut writer)?;
+    if let Some(salt) = salt {
+        writer.write_all(salt)?;
+    }
+    Ok(())
+}
+
+fn salt_size(salt: &Option<[u8; 32]>) -> usize {
+    salt.map_or(1, |salt| 1 + salt.len())
+}
+
+fn deserialize_salt<R: Read>(mut reader: R) -> Result<Option<[u8; 32]>, SerializationError> {
+    if bool::deserialize(&mut reader)? {
+        let mut salt = [0u8; 32];
+        reader.read_exact(&mut salt)?;
+        Ok(Some(salt))
+    } else {
+        Ok(None)
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalSerialize for Parameters<C> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.generator.serialize(&mut writer)?;
+        serialize_salt(&self.salt, writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.generator.serialized_size() + salt_size(&self.salt)
+    }
+
+    fn serialize_uncompressed<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.generator.serialize_uncompressed(&mut writer)?;
+        serialize_salt(&self.salt, writer)
+    }
+
+    fn serialize_unchecked<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.generator.serialize_unchecked(&mut writer)?;
+        serialize_salt(&self.salt, writer)
+    }
+
+    fn uncompressed_size(&self) -> usize {
+        self.generator.uncompressed_size() + salt_size(&self.salt)
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalDeserialize for Parameters<C> {
+    /// Deserialize the parameters, checking that the generator is a non-zero
+    /// element of the prime-order subgroup.
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let generator = C::Affine::deserialize(&mut reader)?;
+        if generator.is_zero() {
+            return Err(SerializationError::InvalidData);
+        }
+        let salt = deserialize_salt(reader)?;
+        Ok(Self { generator, salt })
+    }
+
+    fn deserialize_uncompressed<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let generator = C::Affine::deserialize_uncompressed(&mut reader)?;
+        if generator.is_zero() {
+            return Err(SerializationError::InvalidData);
+        }
+        let salt = deserialize_salt(reader)?;
+        Ok(Self { generator, salt })
+    }
+
+    fn deserialize_unchecked<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let generator = C::Affine::deserialize_unchecked(&mut reader)?;
+        let salt = deserialize_salt(reader)?;
+        Ok(Self { generator, salt })
+    }
+}
+
+// A signature is encoded as the prover commitment, followed by the prover response.
+impl<C: ProjectiveCurve> CanonicalSerialize for Signature<C> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.prover_commitment.serialize(&mut writer)?;
+        self.prover_response.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.prover_commitment.serialized_size() + self.prover_response.serialized_size()
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalDeserialize for Signature<C> {
+    /// Deserialize the signature, checking that the prover commitment is in the
+    /// prime-order subgroup.
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let prover_commitment = C::Affine::deserialize(&mut reader)?;
+        let prover_response = C::ScalarField::deserialize(reader)?;
+        Ok(Self {
+            prover_commitment,
+            prover_response,
+        })
+    }
+}
+
 impl<ConstraintF: Field, C: ProjectiveCurve + ToConstraintField<ConstraintF>>
     ToConstraintField<ConstraintF> for Parameters<C>
 {
@@ -192,3 +514,158 @@ impl<ConstraintF: Field, C: ProjectiveCurve + ToConstraintField<ConstraintF>>
         self.generator.into_projective().to_field_elements()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective as JubJub, Fq, Fr};
+    use ark_std::test_rng;
+
+    /// Whether `SchnorrSignatureVerifyGadget` accepts `signature`.
+    #[cfg(feature = "r1cs")]
+    fn verifies_in_circuit(
+        parameters: &Parameters<JubJub>,
+        pk: &PublicKey<JubJub>,
+        message: &[u8],
+        signature: &Signature<JubJub>,
+    ) -> bool {
+        use super::constraints::{
+            ParametersVar, PublicKeyVar, SchnorrSignatureVerifyGadget, SignatureVar,
+        };
+        use crate::signature::SigVerifyGadget;
+        use ark_ed_on_bls12_381::constraints::EdwardsVar;
+        use ark_r1cs_std::prelude::*;
+        use ark_relations::r1cs::ConstraintSystem;
+        type SG = SchnorrSignatureVerifyGadget<JubJub, EdwardsVar>;
+
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, EdwardsVar>::new_constant(cs.clone(), parameters).unwrap();
+        let pk_var =
+            PublicKeyVar::<JubJub, EdwardsVar>::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let signature_var =
+            SignatureVar::<JubJub, EdwardsVar>::new_witness(cs.clone(), || Ok(signature)).unwrap();
+        let message_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        SG::verify(&parameters_var, &pk_var, &message_var, &signature_var)
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn small_order_commitments_test() {
+        type S = Schnorr<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let message = b"Hi, I am a Schnorr signature!";
+        let small_order_point = (1u64..)
+            .find_map(|x| {
+                let point = EdwardsAffine::get_point_from_x(Fq::from(x), true)?;
+                let point = point.mul(<Fr as PrimeField>::Params::MODULUS);
+                Some(point.into_affine()).filter(|point| !point.is_zero())
+            })
+            .unwrap();
+
+        // A signature whose prover commitment is off by a point of small order.
+        let mut pks = Vec::new();
+        let mut signatures = Vec::new();
+        for _ in 0..3 {
+            let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+            let nonce = Fr::rand(rng);
+            let prover_commitment =
+                parameters.generator.mul(nonce).into_affine() + small_order_point;
+            let verifier_challenge = S::verifier_challenge(
+                &parameters,
+                &Default::default(),
+                &pk,
+                &prover_commitment,
+                message,
+            )
+            .unwrap();
+            let verifier_challenge_fe = Fr::from_le_bytes_mod_order(&verifier_challenge);
+            signatures.push(Signature {
+                prover_commitment,
+                prover_response: nonce - verifier_challenge_fe * sk.secret_key,
+            });
+            pks.push(pk);
+        }
+        let messages = [message; 3];
+
+        // Verifying the signatures alone, in a batch and in-circuit agree.
+        for (pk, signature) in pks.iter().zip(&signatures) {
+            assert!(S::verify(&parameters, pk, message, signature).unwrap());
+            #[cfg(feature = "r1cs")]
+            assert!(verifies_in_circuit(&parameters, pk, message, signature));
+        }
+        assert_eq!(
+            S::batch_verify(&parameters, &pks, &messages, &signatures).unwrap(),
+            None
+        );
+        signatures[1].prover_response += Fr::from(1u64);
+        assert!(!S::verify(&parameters, &pks[1], message, &signatures[1]).unwrap());
+        #[cfg(feature = "r1cs")]
+        assert!(!verifies_in_circuit(
+            &parameters,
+            &pks[1],
+            message,
+            &signatures[1]
+        ));
+        assert_eq!(
+            S::batch_verify(&parameters, &pks, &messages, &signatures).unwrap(),
+            Some(1)
+        );
+
+        // Such signatures cannot be deserialized.
+        let mut bytes = Vec::new();
+        signatures[0].serialize(&mut bytes).unwrap();
+        assert!(Signature::<JubJub>::deserialize(&bytes[..]).is_err());
+
+        // A public key off by a point of small order is another key of the same
+        // signer, which `State::register` rejects.
+        let (pk, mut sk) = S::keygen(&parameters, rng).unwrap();
+        sk.public_key = pk + small_order_point;
+        let signature = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::is_valid_public_key(&pk));
+        assert!(!S::is_valid_public_key(&sk.public_key));
+        assert!(S::verify(&parameters, &sk.public_key, message, &signature).unwrap());
+        #[cfg(feature = "r1cs")]
+        assert!(verifies_in_circuit(
+            &parameters,
+            &sk.public_key,
+            message,
+            &signature
+        ));
+    }
+
+    #[test]
+    fn signatures_cannot_be_randomized_test() {
+        type S = Schnorr<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let message = b"Hi, I am a Schnorr signature!";
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let signature = S::sign(&parameters, &sk, message, rng).unwrap();
+        let randomness = b"some randomness";
+        let randomized_pk = S::randomize_public_key(&parameters, &pk, randomness).unwrap();
+
+        // Were the public key not hashed into the challenge, (R, s - er) would be a
+        // signature under Y + rG.
+        let verifier_challenge = S::verifier_challenge(
+            &parameters,
+            &Default::default(),
+            &pk,
+            &signature.prover_commitment,
+            message,
+        )
+        .unwrap();
+        let moved_signature = Signature {
+            prover_commitment: signature.prover_commitment,
+            prover_response: signature.prover_response
+                - Fr::from_le_bytes_mod_order(&verifier_challenge)
+                    * Fr::from_le_bytes_mod_order(randomness),
+        };
+        assert!(S::verify(&parameters, &pk, message, &signature).unwrap());
+        assert!(!S::verify(&parameters, &randomized_pk, message, &moved_signature).unwrap());
+    }
+}
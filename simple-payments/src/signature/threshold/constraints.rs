@@ -0,0 +1,177 @@
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
+use ark_std::vec::Vec;
+
+use crate::poseidon::constraints::{bytes_to_field_elements, ParametersVar as PoseidonParamsVar};
+use crate::poseidon::PoseidonParameters;
+use crate::signature::threshold::{PublicKey, Signature, Threshold};
+use crate::signature::{SigVerifyGadget, SignatureScheme};
+
+use derivative::Derivative;
+
+use core::{borrow::Borrow, cmp::Ordering, marker::PhantomData};
+
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct PublicKeyVar<S, SG, F, const N: usize>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    threshold: UInt8<F>,
+    keys: Vec<SG::PublicKeyVar>,
+}
+
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct SignatureVar<S, SG, F, const N: usize>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    signatures: Vec<SG::SignatureVar>,
+}
+
+/// Verifies `Threshold<S, F, N>` signatures, verifying each of the `N` signatures
+/// with `SG`.
+pub struct ThresholdSignatureVerifyGadget<S, SG, F, const N: usize>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    #[doc(hidden)]
+    _scheme: PhantomData<*const (S, SG, F)>,
+}
+
+impl<S, SG, F, const N: usize> SigVerifyGadget<Threshold<S, F, N>, F>
+    for ThresholdSignatureVerifyGadget<S, SG, F, N>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    type ParametersVar = SG::ParametersVar;
+    type PublicKeyVar = PublicKeyVar<S, SG, F, N>;
+    type SignatureVar = SignatureVar<S, SG, F, N>;
+
+    /// Count the signatures that are valid under the matching keys, and check that
+    /// there are at least `threshold` of them, and that `threshold` is not 0.
+    fn verify(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        message: &[UInt8<F>],
+        signature: &Self::SignatureVar,
+    ) -> Result<Boolean<F>, SynthesisError> {
+        let mut num_valid = FpVar::zero();
+        for (key, sig) in public_key.keys.iter().zip(&signature.signatures) {
+            num_valid += FpVar::from(SG::verify(parameters, key, message, sig)?);
+        }
+        let threshold = Boolean::le_bits_to_fp_var(&public_key.threshold.to_bits_le()?)?;
+        // Both sides are at most 255, so they do not need to be range-checked.
+        let enough_signatures = num_valid.is_cmp_unchecked(&threshold, Ordering::Greater, true)?;
+        let threshold_is_positive = threshold.is_neq(&FpVar::zero())?;
+        enough_signatures.and(&threshold_is_positive)
+    }
+}
+
+impl<S, SG, F, const N: usize> AllocVar<PublicKey<S, F, N>, F> for PublicKeyVar<S, SG, F, N>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    fn new_variable<T: Borrow<PublicKey<S, F, N>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|val| {
+            let cs = cs.into();
+            let public_key = val.borrow();
+            if public_key.keys.len() != N {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+            let threshold = UInt8::new_variable(cs.clone(), || Ok(public_key.threshold), mode)?;
+            let keys = public_key
+                .keys
+                .iter()
+                .map(|key| SG::PublicKeyVar::new_variable(cs.clone(), || Ok(key), mode))
+                .collect::<Result<_, _>>()?;
+            Ok(Self { threshold, keys })
+        })
+    }
+}
+
+impl<S, SG, F, const N: usize> AllocVar<Signature<S, N>, F> for SignatureVar<S, SG, F, N>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    /// Missing signatures are replaced by `S::Signature::default()`, which does not
+    /// verify.
+    fn new_variable<T: Borrow<Signature<S, N>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|val| {
+            let cs = cs.into();
+            let signature = val.borrow();
+            if signature.signatures.len() != N {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+            let signatures = signature
+                .signatures
+                .iter()
+                .map(|sig| {
+                    let sig = sig.clone().unwrap_or_default();
+                    SG::SignatureVar::new_variable(cs.clone(), || Ok(sig), mode)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Self { signatures })
+        })
+    }
+}
+
+impl<S, SG, F, const N: usize> EqGadget<F> for PublicKeyVar<S, SG, F, N>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    fn is_eq(&self, other: &Self) -> Result<Boolean<F>, SynthesisError> {
+        let mut result = self.threshold.is_eq(&other.threshold)?;
+        for (key, other_key) in self.keys.iter().zip(&other.keys) {
+            result = result.and(&key.is_eq(other_key)?)?;
+        }
+        Ok(result)
+    }
+}
+
+impl<S, SG, F, const N: usize> ToBytesGadget<F> for PublicKeyVar<S, SG, F, N>
+where
+    S: SignatureScheme,
+    SG: SigVerifyGadget<S, F>,
+    F: PrimeField,
+{
+    /// The bytes of the Poseidon hash of `threshold || keys`, like the native encoding.
+    fn to_bytes(&self) -> Result<Vec<UInt8<F>>, SynthesisError> {
+        let mut bytes = vec![self.threshold.clone()];
+        for key in &self.keys {
+            bytes.extend(key.to_bytes()?);
+        }
+        let poseidon_params = PoseidonParamsVar::new_constant(
+            ConstraintSystemRef::None,
+            PoseidonParameters::cached(),
+        )?;
+        poseidon_params
+            .hash(bytes.len() as u64, &bytes_to_field_elements(&bytes)?)?
+            .to_bytes()
+    }
+}
@@ -0,0 +1,337 @@
+use super::SignatureScheme;
+use crate::poseidon::{self, PoseidonParameters};
+use ark_crypto_primitives::Error;
+use ark_ff::{bytes::ToBytes, to_bytes, PrimeField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use ark_std::rand::Rng;
+use ark_std::{marker::PhantomData, vec, vec::Vec};
+use blake2::Blake2s;
+use derivative::Derivative;
+use digest::Digest;
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// Threshold signatures made of `N` signatures of the scheme `S`. A public key holds
+/// `N` keys of `S` and a threshold `k`, and a signature is valid if at least `k` of
+/// the `N` keys signed the message. With `k > 1`, an account does not depend on a
+/// single key.
+///
+/// A public key is encoded as the Poseidon hash over `F` of its threshold and keys,
+/// so that its encoding fits into a ledger leaf whatever `N` is. `F` must be the
+/// field of the circuits that verify these signatures.
+pub struct Threshold<S: SignatureScheme, F: PrimeField, const N: usize> {
+    _scheme: PhantomData<S>,
+    _field: PhantomData<F>,
+}
+
+/// `N` distinct keys of `S`, of which at least `threshold` must sign a message.
+/// `N` must be between 1 and 255, or using the keys fails to compile.
+#[derive(Derivative)]
+#[derivative(
+    Clone(bound = ""),
+    Debug(bound = ""),
+    Hash(bound = ""),
+    PartialEq(bound = ""),
+    Eq(bound = "")
+)]
+pub struct PublicKey<S: SignatureScheme, F: PrimeField, const N: usize> {
+    /// The number of keys that must sign a message.
+    pub threshold: u8,
+    /// The keys that may sign a message.
+    pub keys: Vec<S::PublicKey>,
+    #[doc(hidden)]
+    _field: PhantomData<F>,
+}
+
+impl<S: SignatureScheme, F: PrimeField, const N: usize> PublicKey<S, F, N> {
+    /// `N`, the largest threshold. Evaluating it fails unless `N` fits in a `u8`.
+    const NUM_KEYS: u8 = {
+        assert!(
+            N >= 1 && N <= u8::MAX as usize,
+            "a threshold public key holds between 1 and 255 keys"
+        );
+        N as u8
+    };
+
+    /// Require `threshold` of `keys` to sign messages. Returns `None` unless there are
+    /// `N` distinct keys and `1 <= threshold <= N`. A key that appeared twice would
+    /// count twice towards the threshold.
+    pub fn new(threshold: u8, keys: Vec<S::PublicKey>) -> Option<Self> {
+        if keys.len() != N || threshold == 0 || threshold > Self::NUM_KEYS {
+            return None;
+        }
+        if keys
+            .iter()
+            .enumerate()
+            .any(|(i, key)| keys[..i].contains(key))
+        {
+            return None;
+        }
+        Some(Self {
+            threshold,
+            keys,
+            _field: PhantomData,
+        })
+    }
+
+    /// The Poseidon hash of `threshold || keys`, which encodes the public key.
+    pub fn commitment(&self) -> Result<F, Error> {
+        let bytes = to_bytes![self.threshold, self.keys]?;
+        Ok(PoseidonParameters::cached().hash(
+            bytes.len() as u64,
+            &poseidon::bytes_to_field_elements(&bytes),
+        ))
+    }
+}
+
+impl<S: SignatureScheme, F: PrimeField, const N: usize> Default for PublicKey<S, F, N> {
+    /// A placeholder, made of `N` copies of the default key of `S`, which `new`
+    /// would reject.
+    fn default() -> Self {
+        Self {
+            threshold: Self::NUM_KEYS,
+            keys: vec![S::PublicKey::default(); N],
+            _field: PhantomData,
+        }
+    }
+}
+
+impl<S: SignatureScheme, F: PrimeField, const N: usize> ToBytes for PublicKey<S, F, N> {
+    #[inline]
+    fn write<W: Write>(&self, writer: W) -> IoResult<()> {
+        self.commitment()
+            .map_err(|_| IoError::new(ErrorKind::Other, "could not encode the keys"))?
+            .write(writer)
+    }
+}
+
+/// The secret keys that a signer holds, at the positions of the matching public keys.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct SecretKey<S: SignatureScheme, const N: usize> {
+    /// `keys[i]` is the secret key of the i-th public key, if the signer holds it.
+    pub keys: Vec<Option<S::SecretKey>>,
+}
+
+impl<S: SignatureScheme, const N: usize> Default for SecretKey<S, N> {
+    fn default() -> Self {
+        Self {
+            keys: vec![None; N],
+        }
+    }
+}
+
+impl<S: SignatureScheme, const N: usize> ToBytes for SecretKey<S, N> {
+    #[inline]
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        for key in &self.keys {
+            key.is_some().write(&mut writer)?;
+            if let Some(key) = key {
+                key.write(&mut writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Signatures under some of the `N` keys of a public key.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct Signature<S: SignatureScheme, const N: usize> {
+    /// `signatures[i]` is a signature under the i-th public key, if its owner signed.
+    pub signatures: Vec<Option<S::Signature>>,
+}
+
+impl<S: SignatureScheme, const N: usize> Signature<S, N> {
+    /// Combine the signatures of several signers into one, taking the signature
+    /// under each key from the first signer that provides it.
+    pub fn combine(signatures: &[Self]) -> Self {
+        let mut combined = Self::default();
+        for signature in signatures {
+            for (slot, sig) in combined.signatures.iter_mut().zip(&signature.signatures) {
+                if slot.is_none() {
+                    *slot = sig.clone();
+                }
+            }
+        }
+        combined
+    }
+}
+
+impl<S: SignatureScheme, const N: usize> Default for Signature<S, N> {
+    fn default() -> Self {
+        Self {
+            signatures: vec![None; N],
+        }
+    }
+}
+
+impl<S: SignatureScheme, F: PrimeField, const N: usize> SignatureScheme for Threshold<S, F, N> {
+    type Parameters = S::Parameters;
+    type PublicKey = PublicKey<S, F, N>;
+    type SecretKey = SecretKey<S, N>;
+    type Signature = Signature<S, N>;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+        S::setup(rng)
+    }
+
+    /// Sample `N` keys, all of which must sign messages.
+    fn keygen<R: Rng>(
+        parameters: &Self::Parameters,
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), Error> {
+        let mut public_keys = Vec::with_capacity(N);
+        let mut secret_keys = Vec::with_capacity(N);
+        for _ in 0..N {
+            let (pk, sk) = S::keygen(parameters, rng)?;
+            public_keys.push(pk);
+            secret_keys.push(Some(sk));
+        }
+        let public_key = PublicKey::new(PublicKey::<S, F, N>::NUM_KEYS, public_keys)
+            .ok_or("the keys must be distinct")?;
+        Ok((public_key, SecretKey { keys: secret_keys }))
+    }
+
+    /// Sign `message` with each of the keys that the signer holds.
+    fn sign<R: Rng>(
+        parameters: &Self::Parameters,
+        sk: &Self::SecretKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Self::Signature, Error> {
+        let signatures = sk
+            .keys
+            .iter()
+            .map(|key| {
+                key.as_ref()
+                    .map(|key| S::sign(parameters, key, message, rng))
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Signature { signatures })
+    }
+
+    /// Check that at least `pk.threshold` of the signatures are valid under the
+    /// matching keys.
+    fn verify(
+        parameters: &Self::Parameters,
+        pk: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<bool, Error> {
+        if pk.keys.len() != N || signature.signatures.len() != N || pk.threshold == 0 {
+            return Ok(false);
+        }
+        let mut num_valid = 0;
+        for (key, sig) in pk.keys.iter().zip(&signature.signatures) {
+            if let Some(sig) = sig {
+                if S::verify(parameters, key, message, sig)? {
+                    num_valid += 1;
+                }
+            }
+        }
+        Ok(num_valid >= pk.threshold as usize)
+    }
+
+    fn is_valid_public_key(pk: &Self::PublicKey) -> bool {
+        pk.keys.iter().all(S::is_valid_public_key)
+    }
+
+    /// Randomize each key with its own randomness, derived from `randomness`, so that
+    /// the randomized keys cannot be linked to each other either.
+    fn randomize_public_key(
+        parameters: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error> {
+        let keys = public_key
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                S::randomize_public_key(parameters, key, &key_randomness(randomness, i))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PublicKey {
+            threshold: public_key.threshold,
+            keys,
+            _field: PhantomData,
+        })
+    }
+
+    fn randomize_secret_key(
+        parameters: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error> {
+        let keys = secret_key
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                key.as_ref()
+                    .map(|key| {
+                        S::randomize_secret_key(parameters, key, &key_randomness(randomness, i))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SecretKey { keys })
+    }
+}
+
+/// The randomness of the i-th key, when a public key is randomized with `randomness`.
+fn key_randomness(randomness: &[u8], i: usize) -> Vec<u8> {
+    let mut h = Blake2s::new();
+    h.update(b"threshold key randomness");
+    h.update((i as u64).to_le_bytes());
+    h.update(randomness);
+    h.finalize().to_vec()
+}
+
+// A public key is encoded as its threshold, followed by its keys. Deserialization
+// checks that there are `N` distinct keys and that `1 <= threshold <= N`.
+impl<S: SignatureScheme, F: PrimeField, const N: usize> CanonicalSerialize for PublicKey<S, F, N> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.threshold.serialize(&mut writer)?;
+        self.keys.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.threshold.serialized_size() + self.keys.serialized_size()
+    }
+}
+
+impl<S: SignatureScheme, F: PrimeField, const N: usize> CanonicalDeserialize
+    for PublicKey<S, F, N>
+{
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let threshold = u8::deserialize(&mut reader)?;
+        let keys = Vec::deserialize(reader)?;
+        Self::new(threshold, keys).ok_or(SerializationError::InvalidData)
+    }
+}
+
+// A signature is encoded as its `N` optional signatures.
+impl<S: SignatureScheme, const N: usize> CanonicalSerialize for Signature<S, N> {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.signatures.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.signatures.serialized_size()
+    }
+}
+
+impl<S: SignatureScheme, const N: usize> CanonicalDeserialize for Signature<S, N> {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        let signatures = Vec::deserialize(reader)?;
+        if signatures.len() != N {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(Self { signatures })
+    }
+}
@@ -0,0 +1,442 @@
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{
+    Config, LeafDigest, LeafParam, Path, TwoToOneDigest, TwoToOneParam,
+};
+use ark_crypto_primitives::Error;
+use derivative::Derivative;
+use std::collections::HashMap;
+
+/// A Merkle tree with `2^(height - 1)` leaves that only stores the nodes whose
+/// subtrees contain a non-empty leaf. Every other node is the root of an empty
+/// subtree, whose hash depends only on its level and is computed once, up front.
+///
+/// The tree hashes leaves and nodes exactly like `ark_crypto_primitives::merkle_tree::MerkleTree`,
+/// with empty leaves being all-zero. It thus has the same roots, and produces
+/// the same `Path`s, as a `MerkleTree` with the same leaves, so that these paths
+/// can be checked with `Path::verify` or with `PathVar`.
+#[derive(Clone)]
+pub struct SparseMerkleTree<P: Config> {
+    /// The hashes of the non-empty leaves, indexed by position.
+    leaf_nodes: HashMap<usize, LeafDigest<P>>,
+    /// The hashes of the non-empty non-leaf nodes. `non_leaf_nodes[i]` contains
+    /// the nodes at `i + 1` levels above the leaves, indexed by position in the level.
+    non_leaf_nodes: Vec<HashMap<usize, TwoToOneDigest<P>>>,
+    /// The hash of an empty leaf.
+    empty_leaf_hash: LeafDigest<P>,
+    /// `empty_non_leaf_hashes[i]` is the hash of an empty subtree whose root is
+    /// `i + 1` levels above the leaves.
+    empty_non_leaf_hashes: Vec<TwoToOneDigest<P>>,
+    two_to_one_hash_param: TwoToOneParam<P>,
+    leaf_hash_param: LeafParam<P>,
+    height: usize,
+}
+
+impl<P: Config> SparseMerkleTree<P> {
+    /// Create a Merkle tree of height `height` in which all leaves are empty.
+    /// `height` must be at least 2, and the tree must have at most
+    /// `usize::MAX + 1` leaves.
+    pub fn blank(
+        leaf_hash_param: &LeafParam<P>,
+        two_to_one_hash_param: &TwoToOneParam<P>,
+        height: usize,
+    ) -> Result<Self, Error> {
+        if height < 2 || height - 1 > usize::BITS as usize {
+            return Err("unsupported Merkle tree height".into());
+        }
+        let empty_leaf = vec![0u8; P::LeafHash::INPUT_SIZE_BITS / 8];
+        let empty_leaf_hash = P::LeafHash::evaluate(leaf_hash_param, &empty_leaf)?;
+        let mut empty_non_leaf_hashes = Vec::with_capacity(height - 1);
+        let mut child_bytes = ark_ff::to_bytes!(empty_leaf_hash)?;
+        for _ in 1..height {
+            let hash =
+                P::TwoToOneHash::evaluate(two_to_one_hash_param, &child_bytes, &child_bytes)?;
+            child_bytes = ark_ff::to_bytes!(hash)?;
+            empty_non_leaf_hashes.push(hash);
+        }
+
+        Ok(Self {
+            leaf_nodes: HashMap::new(),
+            non_leaf_nodes: vec![HashMap::new(); height - 1],
+            empty_leaf_hash,
+            empty_non_leaf_hashes,
+            two_to_one_hash_param: two_to_one_hash_param.clone(),
+            leaf_hash_param: leaf_hash_param.clone(),
+            height,
+        })
+    }
+
+    /// Returns the root of the Merkle tree.
+    pub fn root(&self) -> TwoToOneDigest<P> {
+        self.non_leaf_node(self.height - 1, 0)
+    }
+
+    /// Returns the height of the Merkle tree.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of leaves that are not empty.
+    pub fn num_non_empty_leaves(&self) -> usize {
+        self.leaf_nodes.len()
+    }
+
+    /// Returns the authentication path from the leaf at `index` to the root.
+    pub fn generate_proof(&self, index: usize) -> Result<Path<P>, Error> {
+        self.overlay().generate_proof(index)
+    }
+
+    /// Returns the root of a tree that has the siblings of `path`, and in which the
+    /// leaf at `path.leaf_index` is `leaf`. If `path` comes from this tree, this is
+    /// the root that updating that leaf to `leaf` would give, which is computed
+    /// without touching the tree.
+    pub fn root_from_path<L: ark_ff::ToBytes>(
+        &self,
+        path: &Path<P>,
+        leaf: &L,
+    ) -> Result<TwoToOneDigest<P>, Error> {
+        self.check_index(path.leaf_index)?;
+        if path.auth_path.len() != self.height - 2 {
+            return Err("authentication path of the wrong length".into());
+        }
+        let leaf_hash = P::LeafHash::evaluate(&self.leaf_hash_param, &ark_ff::to_bytes!(leaf)?)?;
+        let (left, right) = if path.leaf_index & 1 == 0 {
+            (leaf_hash, path.leaf_sibling_hash.clone())
+        } else {
+            (path.leaf_sibling_hash.clone(), leaf_hash)
+        };
+        let mut current = P::TwoToOneHash::evaluate(
+            &self.two_to_one_hash_param,
+            &ark_ff::to_bytes!(left)?,
+            &ark_ff::to_bytes!(right)?,
+        )?;
+        // The siblings are stored from top to bottom, so walk them in reverse.
+        for (level, sibling) in (1..).zip(path.auth_path.iter().rev()) {
+            let (left, right) = if (path.leaf_index >> level) & 1 == 0 {
+                (current, sibling.clone())
+            } else {
+                (sibling.clone(), current)
+            };
+            current = P::TwoToOneHash::evaluate(
+                &self.two_to_one_hash_param,
+                &ark_ff::to_bytes!(left)?,
+                &ark_ff::to_bytes!(right)?,
+            )?;
+        }
+        Ok(current)
+    }
+
+    /// Update the leaf at `index` to `new_leaf`, and recompute the nodes above it.
+    pub fn update<L: ark_ff::ToBytes>(&mut self, index: usize, new_leaf: &L) -> Result<(), Error> {
+        let mut overlay = self.overlay();
+        overlay.update(index, new_leaf)?;
+        let diff = overlay.into_diff();
+        self.commit(diff);
+        Ok(())
+    }
+
+    /// Returns a view of the tree to which updates can be made without changing
+    /// the tree itself.
+    pub fn overlay(&self) -> SparseMerkleTreeOverlay<'_, P> {
+        SparseMerkleTreeOverlay {
+            tree: self,
+            diff: SparseMerkleTreeDiff {
+                leaf_nodes: HashMap::new(),
+                non_leaf_nodes: HashMap::new(),
+            },
+        }
+    }
+
+    /// Apply the updates recorded in `diff`. The diff must come from an overlay of
+    /// this tree, and the tree must not have changed since.
+    pub fn commit(&mut self, diff: SparseMerkleTreeDiff<P>) {
+        // Only store non-empty leaves, and roots of non-empty subtrees.
+        for (index, hash) in diff.leaf_nodes {
+            if hash == self.empty_leaf_hash {
+                self.leaf_nodes.remove(&index);
+            } else {
+                self.leaf_nodes.insert(index, hash);
+            }
+        }
+        for ((level, position), hash) in diff.non_leaf_nodes {
+            let nodes = &mut self.non_leaf_nodes[level - 1];
+            if hash == self.empty_non_leaf_hashes[level - 1] {
+                nodes.remove(&position);
+            } else {
+                nodes.insert(position, hash);
+            }
+        }
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), Error> {
+        if (index >> (self.height - 2)) > 1 {
+            return Err("leaf index out of range".into());
+        }
+        Ok(())
+    }
+
+    fn leaf_node(&self, index: usize) -> LeafDigest<P> {
+        self.leaf_nodes
+            .get(&index)
+            .unwrap_or(&self.empty_leaf_hash)
+            .clone()
+    }
+
+    /// Returns the node at `level` levels above the leaves, and at position `index`
+    /// within that level.
+    fn non_leaf_node(&self, level: usize, index: usize) -> TwoToOneDigest<P> {
+        self.non_leaf_nodes[level - 1]
+            .get(&index)
+            .unwrap_or(&self.empty_non_leaf_hashes[level - 1])
+            .clone()
+    }
+}
+
+/// Updates to a `SparseMerkleTree`, as recorded by a `SparseMerkleTreeOverlay`.
+/// `SparseMerkleTree::commit` applies them to the tree.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct SparseMerkleTreeDiff<P: Config> {
+    /// The new hashes of the updated leaves, indexed by position.
+    leaf_nodes: HashMap<usize, LeafDigest<P>>,
+    /// The new hashes of the nodes above the updated leaves, indexed by their
+    /// level above the leaves and their position within that level.
+    non_leaf_nodes: HashMap<(usize, usize), TwoToOneDigest<P>>,
+}
+
+impl<P: Config> SparseMerkleTreeDiff<P> {
+    /// The new hashes of the updated leaves, indexed by position.
+    pub(crate) fn leaf_nodes(&self) -> &HashMap<usize, LeafDigest<P>> {
+        &self.leaf_nodes
+    }
+
+    /// The new hashes of the nodes above the updated leaves, indexed by their level
+    /// above the leaves and their position within that level.
+    pub(crate) fn non_leaf_nodes(&self) -> &HashMap<(usize, usize), TwoToOneDigest<P>> {
+        &self.non_leaf_nodes
+    }
+}
+
+/// A view of a `SparseMerkleTree` with updates on top, which leaves the tree itself
+/// untouched. Its roots and paths are those of the tree with the updates applied.
+pub struct SparseMerkleTreeOverlay<'a, P: Config> {
+    tree: &'a SparseMerkleTree<P>,
+    diff: SparseMerkleTreeDiff<P>,
+}
+
+impl<'a, P: Config> SparseMerkleTreeOverlay<'a, P> {
+    /// Returns the root of the Merkle tree, with the updates applied.
+    pub fn root(&self) -> TwoToOneDigest<P> {
+        self.non_leaf_node(self.tree.height - 1, 0)
+    }
+
+    /// Returns the height of the Merkle tree.
+    pub fn height(&self) -> usize {
+        self.tree.height
+    }
+
+    /// Returns the authentication path from the leaf at `index` to the root, with
+    /// the updates applied.
+    pub fn generate_proof(&self, index: usize) -> Result<Path<P>, Error> {
+        self.tree.check_index(index)?;
+        let leaf_sibling_hash = self.leaf_node(index ^ 1);
+        // The path excludes the leaf level and the root, and goes from top to bottom.
+        let auth_path = (1..self.tree.height - 1)
+            .rev()
+            .map(|level| self.non_leaf_node(level, (index >> level) ^ 1))
+            .collect();
+        Ok(Path {
+            leaf_sibling_hash,
+            auth_path,
+            leaf_index: index,
+        })
+    }
+
+    /// Update the leaf at `index` to `new_leaf`, and recompute the nodes above it.
+    pub fn update<L: ark_ff::ToBytes>(&mut self, index: usize, new_leaf: &L) -> Result<(), Error> {
+        self.tree.check_index(index)?;
+        let leaf_hash =
+            P::LeafHash::evaluate(&self.tree.leaf_hash_param, &ark_ff::to_bytes!(new_leaf)?)?;
+        self.diff.leaf_nodes.insert(index, leaf_hash);
+
+        for level in 1..self.tree.height {
+            let position = index >> level;
+            let (left, right) = if level == 1 {
+                (
+                    ark_ff::to_bytes!(self.leaf_node(2 * position))?,
+                    ark_ff::to_bytes!(self.leaf_node(2 * position + 1))?,
+                )
+            } else {
+                (
+                    ark_ff::to_bytes!(self.non_leaf_node(level - 1, 2 * position))?,
+                    ark_ff::to_bytes!(self.non_leaf_node(level - 1, 2 * position + 1))?,
+                )
+            };
+            let hash = P::TwoToOneHash::evaluate(&self.tree.two_to_one_hash_param, &left, &right)?;
+            self.diff.non_leaf_nodes.insert((level, position), hash);
+        }
+        Ok(())
+    }
+
+    /// Returns the updates made through this overlay.
+    pub fn into_diff(self) -> SparseMerkleTreeDiff<P> {
+        self.diff
+    }
+
+    fn leaf_node(&self, index: usize) -> LeafDigest<P> {
+        match self.diff.leaf_nodes.get(&index) {
+            Some(hash) => hash.clone(),
+            None => self.tree.leaf_node(index),
+        }
+    }
+
+    fn non_leaf_node(&self, level: usize, index: usize) -> TwoToOneDigest<P> {
+        match self.diff.non_leaf_nodes.get(&(level, index)) {
+            Some(hash) => hash.clone(),
+            None => self.tree.non_leaf_node(level, index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SparseMerkleTree;
+    use crate::ledger::{LeafHash, MerkleConfig, PoseidonMerkleConfig, TwoToOneHash};
+    use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+    use ark_crypto_primitives::merkle_tree::{Config, MerkleTree};
+
+    fn matches_dense_tree<P: Config>() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = <P::LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_crh_params = <P::TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let mut dense =
+            MerkleTree::<P>::blank(&leaf_crh_params, &two_to_one_crh_params, 5).unwrap();
+        let mut sparse =
+            SparseMerkleTree::<P>::blank(&leaf_crh_params, &two_to_one_crh_params, 5).unwrap();
+        assert_eq!(sparse.root(), dense.root());
+
+        for (index, value) in [(3usize, 1u8), (0, 2), (15, 3), (3, 4), (8, 0)] {
+            let leaf = [value; 16];
+            dense.update(index, &leaf).unwrap();
+            sparse.update(index, &leaf).unwrap();
+            assert_eq!(sparse.root(), dense.root());
+        }
+        // Resetting a leaf to empty removes it from the tree.
+        assert_eq!(sparse.num_non_empty_leaves(), 3);
+
+        for index in 0..16 {
+            let dense_path = dense.generate_proof(index).unwrap();
+            let sparse_path = sparse.generate_proof(index).unwrap();
+            assert_eq!(sparse_path.leaf_sibling_hash, dense_path.leaf_sibling_hash);
+            assert_eq!(sparse_path.auth_path, dense_path.auth_path);
+            assert_eq!(sparse_path.leaf_index, dense_path.leaf_index);
+        }
+        let path = sparse.generate_proof(15).unwrap();
+        assert!(path
+            .verify(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &sparse.root(),
+                &[3u8; 16]
+            )
+            .unwrap());
+
+        assert!(sparse.generate_proof(16).is_err());
+        assert!(sparse.update(16, &[1u8; 16]).is_err());
+    }
+
+    #[test]
+    fn matches_dense_tree_with_pedersen() {
+        matches_dense_tree::<MerkleConfig>();
+    }
+
+    #[test]
+    fn matches_dense_tree_with_poseidon() {
+        matches_dense_tree::<PoseidonMerkleConfig>();
+    }
+
+    #[test]
+    fn overlays_leave_the_tree_untouched() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let mut tree =
+            SparseMerkleTree::<MerkleConfig>::blank(&leaf_crh_params, &two_to_one_crh_params, 5)
+                .unwrap();
+        tree.update(3, &[1u8; 16]).unwrap();
+        let mut updated = tree.clone();
+        let root = tree.root();
+
+        let mut overlay = tree.overlay();
+        for (index, value) in [(5usize, 2u8), (3, 0), (15, 3)] {
+            overlay.update(index, &[value; 16]).unwrap();
+            updated.update(index, &[value; 16]).unwrap();
+            assert_eq!(overlay.root(), updated.root());
+        }
+        for index in 0..16 {
+            let path = overlay.generate_proof(index).unwrap();
+            let expected = updated.generate_proof(index).unwrap();
+            assert_eq!(path.leaf_sibling_hash, expected.leaf_sibling_hash);
+            assert_eq!(path.auth_path, expected.auth_path);
+        }
+        let diff = overlay.into_diff();
+        assert_eq!(tree.root(), root);
+
+        // Committing the diff stores the same nodes as updating the tree directly.
+        tree.commit(diff);
+        assert_eq!(tree.root(), updated.root());
+        assert_eq!(tree.num_non_empty_leaves(), updated.num_non_empty_leaves());
+    }
+
+    #[test]
+    fn roots_can_be_computed_from_paths() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let mut tree =
+            SparseMerkleTree::<MerkleConfig>::blank(&leaf_crh_params, &two_to_one_crh_params, 5)
+                .unwrap();
+        tree.update(6, &[1u8; 16]).unwrap();
+        tree.update(9, &[2u8; 16]).unwrap();
+
+        // Leaf 12 is empty, and empty leaves hash like all-zero ones.
+        for (index, value) in [(6usize, 1u8), (9, 2), (12, 0)] {
+            let path = tree.generate_proof(index).unwrap();
+            assert_eq!(
+                tree.root_from_path(&path, &[value; 16]).unwrap(),
+                tree.root()
+            );
+            // The same path gives the root of the tree in which only this leaf changed.
+            let new_root = tree.root_from_path(&path, &[7u8; 16]).unwrap();
+            let mut updated = tree.clone();
+            updated.update(index, &[7u8; 16]).unwrap();
+            assert_eq!(new_root, updated.root());
+        }
+
+        let mut path = tree.generate_proof(6).unwrap();
+        path.auth_path.pop();
+        assert!(tree.root_from_path(&path, &[1u8; 16]).is_err());
+    }
+
+    #[test]
+    fn supports_large_trees() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let mut tree =
+            SparseMerkleTree::<MerkleConfig>::blank(&leaf_crh_params, &two_to_one_crh_params, 33)
+                .unwrap();
+        let index = (1 << 32) - 1;
+        tree.update(index, &[7u8; 16]).unwrap();
+        let path = tree.generate_proof(index).unwrap();
+        assert_eq!(path.auth_path.len(), 31);
+        assert!(path
+            .verify(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &tree.root(),
+                &[7u8; 16]
+            )
+            .unwrap());
+        assert!(tree.generate_proof(1 << 32).is_err());
+    }
+}
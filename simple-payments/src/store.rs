@@ -0,0 +1,716 @@
+use crate::account::{AccountId, AccountInformation};
+use crate::ledger::{
+    next_account_leaf, AccMerkleConfig, AccRoot, DefaultSignatureScheme, MerkleConfig, State,
+    NEXT_ACCOUNT_LEAF_INDEX,
+};
+use crate::overlay::StateDiff;
+use crate::signature::SignatureScheme;
+use crate::Error;
+use ark_crypto_primitives::merkle_tree::{LeafDigest, TwoToOneDigest};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use blake2::{Blake2s, Digest};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Identifies a ledger store file.
+const MAGIC: &[u8; 8] = b"SPLEDGER";
+/// The version of the file format.
+const VERSION: u8 = 2;
+/// The length of a frame header: the payload length, its bitwise complement, and
+/// the checksum of the payload.
+const FRAME_HEADER_LEN: usize = 4 + 4 + CHECKSUM_LEN;
+/// The length of the checksum of a frame, a truncated Blake2s hash of its payload.
+const CHECKSUM_LEN: usize = 8;
+
+/// Errors that can occur when reading or writing a `LedgerStore`.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading from or writing to the underlying file failed.
+    Io(io::Error),
+    /// The file is not a ledger store, or its committed contents are malformed.
+    Serialization(SerializationError),
+    /// A commit that is followed by other data has a bad length or checksum. Such a
+    /// commit cannot come from an interrupted write, so the file is left untouched.
+    Corrupted,
+    /// The root or the Merkle tree nodes recorded by a commit differ from the ones
+    /// recomputed from the accounts.
+    RootMismatch,
+    /// The ledger rejected the changes, for the given reason.
+    Ledger(Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "I/O error: {}", e),
+            StoreError::Serialization(e) => write!(f, "malformed ledger store: {}", e),
+            StoreError::Corrupted => write!(f, "corrupted commit in ledger store"),
+            StoreError::RootMismatch => write!(f, "stored root does not match the accounts"),
+            StoreError::Ledger(e) => write!(f, "changes rejected: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<SerializationError> for StoreError {
+    fn from(e: SerializationError) -> Self {
+        StoreError::Serialization(e)
+    }
+}
+
+impl From<Error> for StoreError {
+    fn from(e: Error) -> Self {
+        StoreError::Ledger(e)
+    }
+}
+
+/// An entry of a commit.
+enum Record<S: SignatureScheme, M: AccMerkleConfig> {
+    /// Sets the information of an account, registering it if needed.
+    Account(AccountId, AccountInformation<S>),
+    /// Sets the next available account identifier.
+    NextAvailableAccount(Option<AccountId>),
+    /// Sets the fee collector.
+    FeeCollector(Option<AccountId>),
+    /// Sets the hash of the leaf at the given position of the account tree.
+    LeafNode(usize, LeafDigest<M>),
+    /// Sets the hash of the node at the given level above the leaves, and at the
+    /// given position within that level, of the account tree.
+    NonLeafNode(usize, usize, TwoToOneDigest<M>),
+    /// Ends a commit, and records the resulting root.
+    Commit(AccRoot),
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> Record<S, M> {
+    fn write(&self, buf: &mut Vec<u8>) -> Result<(), SerializationError> {
+        match self {
+            Record::Account(id, account_info) => {
+                0u8.serialize(&mut *buf)?;
+                id.serialize(&mut *buf)?;
+                account_info.serialize(&mut *buf)
+            }
+            Record::NextAvailableAccount(id) => {
+                1u8.serialize(&mut *buf)?;
+                id.serialize(&mut *buf)
+            }
+            Record::FeeCollector(id) => {
+                2u8.serialize(&mut *buf)?;
+                id.serialize(&mut *buf)
+            }
+            Record::LeafNode(index, hash) => {
+                3u8.serialize(&mut *buf)?;
+                (*index as u64).serialize(&mut *buf)?;
+                hash.serialize(&mut *buf)
+            }
+            Record::NonLeafNode(level, position, hash) => {
+                4u8.serialize(&mut *buf)?;
+                (*level as u64).serialize(&mut *buf)?;
+                (*position as u64).serialize(&mut *buf)?;
+                hash.serialize(&mut *buf)
+            }
+            Record::Commit(root) => {
+                5u8.serialize(&mut *buf)?;
+                root.serialize(&mut *buf)
+            }
+        }
+    }
+
+    fn read(reader: &mut &[u8]) -> Result<Self, SerializationError> {
+        let read_usize = |reader: &mut &[u8]| {
+            usize::try_from(u64::deserialize(reader)?).map_err(|_| SerializationError::InvalidData)
+        };
+        match u8::deserialize(&mut *reader)? {
+            0 => Ok(Record::Account(
+                AccountId::deserialize(&mut *reader)?,
+                AccountInformation::deserialize(&mut *reader)?,
+            )),
+            1 => Ok(Record::NextAvailableAccount(Option::deserialize(
+                &mut *reader,
+            )?)),
+            2 => Ok(Record::FeeCollector(Option::deserialize(&mut *reader)?)),
+            3 => Ok(Record::LeafNode(
+                read_usize(&mut *reader)?,
+                LeafDigest::<M>::deserialize(&mut *reader)?,
+            )),
+            4 => Ok(Record::NonLeafNode(
+                read_usize(&mut *reader)?,
+                read_usize(&mut *reader)?,
+                TwoToOneDigest::<M>::deserialize(&mut *reader)?,
+            )),
+            5 => Ok(Record::Commit(AccRoot::deserialize(&mut *reader)?)),
+            _ => Err(SerializationError::InvalidData),
+        }
+    }
+}
+
+/// Append `payload` to `buf` as a frame, which starts with the length of the
+/// payload, the bitwise complement of that length, and a checksum of the payload.
+fn write_frame(buf: &mut Vec<u8>, payload: &[u8]) -> Result<(), SerializationError> {
+    let len = u32::try_from(payload.len()).map_err(|_| SerializationError::InvalidData)?;
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(&(!len).to_le_bytes());
+    buf.extend_from_slice(&checksum(payload));
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    checksum.copy_from_slice(&Blake2s::digest(payload)[..CHECKSUM_LEN]);
+    checksum
+}
+
+/// The outcome of reading a frame.
+enum Frame<'a> {
+    /// A frame with a valid length and checksum, and the offset at which it ends.
+    Complete(&'a [u8], usize),
+    /// The final frame, which is incomplete or has a bad checksum, as a write that
+    /// was interrupted by a crash leaves it.
+    Torn,
+    /// A frame with a bad length, or one with a bad checksum that is followed by
+    /// more data.
+    Corrupted,
+}
+
+/// Read the frame that starts at offset `start` of `contents`, if any.
+fn read_frame(contents: &[u8], start: usize) -> Option<Frame<'_>> {
+    let rest = &contents[start..];
+    if rest.is_empty() {
+        return None;
+    }
+    if rest.len() < FRAME_HEADER_LEN {
+        return Some(Frame::Torn);
+    }
+    let mut len = [0u8; 4];
+    let mut len_complement = [0u8; 4];
+    len.copy_from_slice(&rest[..4]);
+    len_complement.copy_from_slice(&rest[4..8]);
+    let len = u32::from_le_bytes(len);
+    if len != !u32::from_le_bytes(len_complement) {
+        return Some(Frame::Corrupted);
+    }
+    let frame_len = FRAME_HEADER_LEN + len as usize;
+    if rest.len() < frame_len {
+        return Some(Frame::Torn);
+    }
+    let payload = &rest[FRAME_HEADER_LEN..frame_len];
+    if rest[8..FRAME_HEADER_LEN] != checksum(payload) {
+        if rest.len() == frame_len {
+            return Some(Frame::Torn);
+        }
+        return Some(Frame::Corrupted);
+    }
+    Some(Frame::Complete(payload, start + frame_len))
+}
+
+/// A crash-safe, append-only file that persists a `State`.
+///
+/// The file starts with a snapshot of the initial state, followed by one frame
+/// per commit. A commit holds the accounts that changed, the Merkle tree nodes
+/// that changed, and the resulting root, so it grows with the size of the
+/// changes rather than with the size of the ledger. Every frame carries its
+/// length and a checksum. When the store is reopened, each commit is checked
+/// by recomputing the tree nodes above the changed accounts, and comparing them
+/// and the root with the stored ones. A torn final frame, as left by a crash in
+/// the middle of a commit, is discarded, while a bad frame in the middle of the
+/// file is reported as `StoreError::Corrupted`.
+pub struct LedgerStore<
+    S: SignatureScheme = DefaultSignatureScheme,
+    M: AccMerkleConfig = MerkleConfig,
+> {
+    file: File,
+    /// The length of the file up to the end of the last commit.
+    len: u64,
+    _signature_scheme: std::marker::PhantomData<(S, M)>,
+}
+
+impl<S: SignatureScheme, M: AccMerkleConfig> LedgerStore<S, M> {
+    /// Create a new store at `path` that starts from `state`. Fails if a file
+    /// already exists at `path`.
+    pub fn create<P: AsRef<Path>>(path: P, state: &State<S, M>) -> Result<Self, StoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(path)?;
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        let mut snapshot = Vec::new();
+        state.serialize(&mut snapshot)?;
+        Record::<S, M>::Commit(state.root()).write(&mut snapshot)?;
+        write_frame(&mut buf, &snapshot)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Ok(Self::new(file, buf.len()))
+    }
+
+    /// Open the store at `path`, and reload the last committed state.
+    /// Returns `StoreError::RootMismatch` if the accounts in the store do not
+    /// match the roots and nodes that were committed alongside them, and
+    /// `StoreError::Corrupted` if a commit other than the last one is damaged.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(Self, State<S, M>), StoreError> {
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut header = [0u8; 9];
+        (&contents[..]).read_exact(&mut header)?;
+        if &header[..8] != MAGIC || header[8] != VERSION {
+            return Err(SerializationError::InvalidData.into());
+        }
+        // The snapshot is written when the store is created, so it must be intact.
+        let (snapshot, mut committed_len) = match read_frame(&contents, header.len()) {
+            Some(Frame::Complete(payload, end)) => (payload, end),
+            Some(Frame::Corrupted) => return Err(StoreError::Corrupted),
+            _ => return Err(SerializationError::InvalidData.into()),
+        };
+        let mut reader = snapshot;
+        let mut state = State::<S, M>::deserialize(&mut reader)?;
+        match Record::<S, M>::read(&mut reader)? {
+            Record::Commit(root) if root == state.root() => {}
+            Record::Commit(_) => return Err(StoreError::RootMismatch),
+            _ => return Err(SerializationError::InvalidData.into()),
+        }
+        if !reader.is_empty() {
+            return Err(SerializationError::InvalidData.into());
+        }
+
+        // Replay the commits in order.
+        loop {
+            match read_frame(&contents, committed_len) {
+                None | Some(Frame::Torn) => break,
+                Some(Frame::Corrupted) => return Err(StoreError::Corrupted),
+                Some(Frame::Complete(payload, end)) => {
+                    replay_commit(&mut state, payload)?;
+                    committed_len = end;
+                }
+            }
+        }
+
+        // Drop a torn final frame, so that new commits follow the last good one.
+        if committed_len < contents.len() {
+            file.set_len(committed_len as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok((Self::new(file, committed_len), state))
+    }
+
+    /// Apply `diff`, which was made through an overlay of `state`, to `state`, and
+    /// persist it. Only the accounts and the Merkle tree nodes that `diff` changes
+    /// are written. Once this returns, the changes survive a crash.
+    /// Returns `StoreError::Ledger(Error::StaleDiff)`, and changes neither `state`
+    /// nor the store, if `state` has changed since the overlay was created.
+    pub fn commit(
+        &mut self,
+        state: &mut State<S, M>,
+        diff: StateDiff<S, M>,
+    ) -> Result<(), StoreError> {
+        if diff.base_root != state.root() {
+            return Err(Error::StaleDiff.into());
+        }
+        let mut accounts = diff.accounts.iter().collect::<Vec<_>>();
+        accounts.sort_by_key(|(id, _)| **id);
+        let mut leaf_nodes = diff
+            .account_merkle_tree
+            .leaf_nodes()
+            .iter()
+            .collect::<Vec<_>>();
+        leaf_nodes.sort_by_key(|(index, _)| **index);
+        let mut non_leaf_nodes = diff
+            .account_merkle_tree
+            .non_leaf_nodes()
+            .iter()
+            .collect::<Vec<_>>();
+        non_leaf_nodes.sort_by_key(|(position, _)| **position);
+
+        let mut payload = Vec::new();
+        for (id, account_info) in accounts {
+            Record::<S, M>::Account(*id, account_info.clone()).write(&mut payload)?;
+        }
+        if diff.next_available_account != state.next_available_account {
+            Record::<S, M>::NextAvailableAccount(diff.next_available_account)
+                .write(&mut payload)?;
+        }
+        for (index, hash) in leaf_nodes {
+            Record::<S, M>::LeafNode(*index, *hash).write(&mut payload)?;
+        }
+        for ((level, position), hash) in non_leaf_nodes {
+            Record::<S, M>::NonLeafNode(*level, *position, *hash).write(&mut payload)?;
+        }
+        Record::<S, M>::Commit(diff.root).write(&mut payload)?;
+        self.append(&payload)?;
+
+        state
+            .commit(diff)
+            .expect("the diff was checked to be made for this state");
+        Ok(())
+    }
+
+    /// Make `id` the account that receives transaction fees in `state`, and persist
+    /// this. Returns `StoreError::Ledger(Error::UnknownAccount(id))` if no account
+    /// has identifier `id`.
+    pub fn set_fee_collector(
+        &mut self,
+        state: &mut State<S, M>,
+        id: AccountId,
+    ) -> Result<(), StoreError> {
+        if !state.id_to_account_info.contains_key(&id) {
+            return Err(Error::UnknownAccount(id).into());
+        }
+        let mut payload = Vec::new();
+        Record::<S, M>::FeeCollector(Some(id)).write(&mut payload)?;
+        Record::<S, M>::Commit(state.root()).write(&mut payload)?;
+        self.append(&payload)?;
+
+        state
+            .set_fee_collector(id)
+            .expect("the account was checked to exist");
+        Ok(())
+    }
+
+    /// Append a commit to the file, and sync it. If this fails, the file is cut
+    /// back to the previous commit, so that later commits are not appended after
+    /// a partial one.
+    fn append(&mut self, payload: &[u8]) -> Result<(), StoreError> {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, payload)?;
+        let result = self
+            .file
+            .write_all(&buf)
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = result {
+            let _ = self.file.set_len(self.len);
+            return Err(e.into());
+        }
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    fn new(file: File, len: usize) -> Self {
+        Self {
+            file,
+            len: len as u64,
+            _signature_scheme: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Check the commit in `payload` against `state`, and apply it. The stored tree
+/// nodes must be exactly the ones that updating the changed leaves recomputes,
+/// so the check costs a path of hashes per changed account.
+fn replay_commit<S: SignatureScheme, M: AccMerkleConfig>(
+    state: &mut State<S, M>,
+    payload: &[u8],
+) -> Result<(), StoreError> {
+    let mut accounts = HashMap::new();
+    let mut next_available_account = state.next_available_account;
+    let mut fee_collector = state.fee_collector;
+    let mut leaf_nodes = HashMap::new();
+    let mut non_leaf_nodes = HashMap::new();
+    let mut reader = payload;
+    let root = loop {
+        match Record::<S, M>::read(&mut reader)? {
+            Record::Account(id, account_info) => {
+                accounts.insert(id, account_info);
+            }
+            Record::NextAvailableAccount(id) => next_available_account = id,
+            Record::FeeCollector(id) => fee_collector = id,
+            Record::LeafNode(index, hash) => {
+                leaf_nodes.insert(index, hash);
+            }
+            Record::NonLeafNode(level, position, hash) => {
+                non_leaf_nodes.insert((level, position), hash);
+            }
+            Record::Commit(root) => break root,
+        }
+    };
+    if !reader.is_empty() {
+        return Err(SerializationError::InvalidData.into());
+    }
+
+    // Accounts must be registered below the account counter, and have distinct
+    // public keys, as `State::register` ensures.
+    let mut public_keys = HashSet::with_capacity(accounts.len());
+    for (id, account_info) in &accounts {
+        let below_counter = match next_available_account {
+            Some(next_id) => *id < next_id,
+            None => true,
+        };
+        let key_owner = state.pub_key_to_id.get(&account_info.public_key);
+        if *id == AccountId(0)
+            || !below_counter
+            || matches!(key_owner, Some(owner) if owner != id)
+            || !public_keys.insert(&account_info.public_key)
+        {
+            return Err(SerializationError::InvalidData.into());
+        }
+    }
+    if let Some(fee_collector) = fee_collector {
+        if !accounts.contains_key(&fee_collector)
+            && !state.id_to_account_info.contains_key(&fee_collector)
+        {
+            return Err(SerializationError::InvalidData.into());
+        }
+    }
+
+    let mut tree = state.account_merkle_tree.overlay();
+    for (id, account_info) in &accounts {
+        let index = id.leaf_index().ok_or(SerializationError::InvalidData)?;
+        tree.update(index, &account_info.to_bytes_le())
+            .map_err(|_| SerializationError::InvalidData)?;
+    }
+    if next_available_account != state.next_available_account {
+        tree.update(
+            NEXT_ACCOUNT_LEAF_INDEX,
+            &next_account_leaf(next_available_account),
+        )
+        .map_err(|_| SerializationError::InvalidData)?;
+    }
+    if tree.root() != root {
+        return Err(StoreError::RootMismatch);
+    }
+    let account_merkle_tree = tree.into_diff();
+    if account_merkle_tree.leaf_nodes() != &leaf_nodes
+        || account_merkle_tree.non_leaf_nodes() != &non_leaf_nodes
+    {
+        return Err(StoreError::RootMismatch);
+    }
+
+    let diff = StateDiff {
+        base_root: state.root(),
+        root,
+        accounts,
+        next_available_account,
+        account_merkle_tree,
+    };
+    state
+        .commit(diff)
+        .expect("the diff was made for this state");
+    state.fee_collector = fee_collector;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_frame, LedgerStore, Record, StoreError};
+    use crate::account::Nonce;
+    use crate::ledger::{AccRoot, Amount, DefaultSignatureScheme, MerkleConfig, Parameters, State};
+    use crate::operation::{Deposit, Operation};
+    use crate::signature::SignatureScheme;
+    use crate::transaction::Transaction;
+    use crate::Error;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn store_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "simple-payments-{}-{}.ledger",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn state_survives_reopening() {
+        let path = store_path("reopen");
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let mut store = LedgerStore::create(&path, &state).unwrap();
+        assert!(LedgerStore::create(&path, &state).is_err());
+
+        let (alice_pk, alice_sk) =
+            DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (bob_pk, _bob_sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut overlay = state.overlay();
+        let alice_id = overlay.register(alice_pk).unwrap();
+        let bob_id = overlay.register(bob_pk).unwrap();
+        let deposit = Operation::Deposit(Deposit {
+            account: alice_id,
+            amount: Amount(10),
+        });
+        overlay.apply_operation(&pp, &deposit).unwrap();
+        let diff = overlay.into_diff();
+        store.commit(&mut state, diff).unwrap();
+        store.set_fee_collector(&mut state, bob_id).unwrap();
+        drop(store);
+
+        let (mut store, mut restored) = LedgerStore::open(&path).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.id_to_account_info, state.id_to_account_info);
+        assert_eq!(restored.pub_key_to_id, state.pub_key_to_id);
+        assert_eq!(
+            restored.next_available_account,
+            state.next_available_account
+        );
+        assert_eq!(restored.fee_collector, Some(bob_id));
+
+        // The reloaded state can be used, and committed to, as before.
+        let tx = Transaction::create_with_fee(
+            &pp,
+            alice_id,
+            bob_id,
+            Amount(5),
+            Nonce(0),
+            Amount(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut overlay = restored.overlay();
+        overlay.apply_transaction(&pp, &tx).unwrap();
+        let diff = overlay.into_diff();
+        store.commit(&mut restored, diff).unwrap();
+        drop(store);
+
+        let (_store, reloaded): (_, State) = LedgerStore::open(&path).unwrap();
+        assert_eq!(reloaded.root(), restored.root());
+        assert_eq!(reloaded.id_to_account_info[&bob_id].balance, Amount(6));
+        assert_eq!(reloaded.id_to_account_info[&alice_id].nonce, Nonce(1));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stale_diffs_are_not_committed() {
+        let path = store_path("stale");
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let mut store = LedgerStore::create(&path, &state).unwrap();
+        let (pk, _sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut overlay = state.overlay();
+        overlay.register(pk).unwrap();
+        let diff = overlay.into_diff();
+        store.commit(&mut state, diff.clone()).unwrap();
+
+        let root = state.root();
+        assert!(matches!(
+            store.commit(&mut state, diff),
+            Err(StoreError::Ledger(Error::StaleDiff))
+        ));
+        drop(store);
+        let (_store, reloaded): (_, State) = LedgerStore::open(&path).unwrap();
+        assert_eq!(reloaded.root(), root);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_commits_are_discarded() {
+        let path = store_path("torn");
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let mut store = LedgerStore::create(&path, &state).unwrap();
+        let (bob_pk, _bob_sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut overlay = state.overlay();
+        overlay.register(bob_pk).unwrap();
+        let diff = overlay.into_diff();
+        store.commit(&mut state, diff).unwrap();
+        let committed_root = state.root();
+
+        // Simulate a crash halfway through writing the next commit.
+        let (alice_pk, _alice_sk) =
+            DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let mut overlay = state.overlay();
+        let alice_id = overlay.register(alice_pk).unwrap();
+        let diff = overlay.into_diff();
+        let mut payload = Vec::new();
+        Record::<DefaultSignatureScheme, MerkleConfig>::Account(
+            alice_id,
+            diff.account_info(&alice_id).unwrap().clone(),
+        )
+        .write(&mut payload)
+        .unwrap();
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &payload).unwrap();
+        buf.truncate(buf.len() - 1);
+        store.file.write_all(&buf).unwrap();
+        drop(store);
+
+        let (mut store, mut restored) = LedgerStore::open(&path).unwrap();
+        assert_eq!(restored.root(), committed_root);
+        assert!(!restored.id_to_account_info.contains_key(&alice_id));
+
+        // New commits are appended after the last good one.
+        store.commit(&mut restored, diff).unwrap();
+        drop(store);
+        let (_store, reloaded): (_, State) = LedgerStore::open(&path).unwrap();
+        assert_eq!(reloaded.root(), restored.root());
+        assert!(reloaded.id_to_account_info.contains_key(&alice_id));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_commits_are_rejected() {
+        let path = store_path("corrupted");
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let mut store = LedgerStore::create(&path, &state).unwrap();
+        let snapshot_len = std::fs::metadata(&path).unwrap().len() as usize;
+        for _ in 0..2 {
+            let (pk, _sk) = DefaultSignatureScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+            let mut overlay = state.overlay();
+            overlay.register(pk).unwrap();
+            let diff = overlay.into_diff();
+            store.commit(&mut state, diff).unwrap();
+        }
+        drop(store);
+
+        // Flip a byte in the payload of the first commit, which the second one follows.
+        let contents = std::fs::read(&path).unwrap();
+        let mut corrupted = contents.clone();
+        corrupted[snapshot_len + super::FRAME_HEADER_LEN] ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(matches!(
+            LedgerStore::<DefaultSignatureScheme>::open(&path),
+            Err(StoreError::Corrupted)
+        ));
+        // Nor can a damaged length hide the commits that follow it.
+        let mut corrupted = contents.clone();
+        corrupted[snapshot_len] ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(matches!(
+            LedgerStore::<DefaultSignatureScheme>::open(&path),
+            Err(StoreError::Corrupted)
+        ));
+        // Nothing is truncated.
+        assert_eq!(std::fs::read(&path).unwrap(), corrupted);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mismatched_roots_are_rejected() {
+        let path = store_path("mismatch");
+        let mut rng = ark_std::test_rng();
+        let pp: Parameters = Parameters::sample(&mut rng);
+        let state = State::new(32, &pp);
+        let mut store = LedgerStore::create(&path, &state).unwrap();
+
+        let mut payload = Vec::new();
+        Record::<DefaultSignatureScheme, MerkleConfig>::Commit(AccRoot::from(1u64))
+            .write(&mut payload)
+            .unwrap();
+        store.append(&payload).unwrap();
+        drop(store);
+
+        assert!(matches!(
+            LedgerStore::<DefaultSignatureScheme>::open(&path),
+            Err(StoreError::RootMismatch)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
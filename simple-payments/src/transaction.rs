@@ -1,105 +1,259 @@
-use crate::account::{AccountId, AccountPublicKey, AccountSecretKey};
-use crate::ledger::{self, Amount};
-use crate::signature::{
-    schnorr::{self, Schnorr},
-    SignatureScheme,
-};
-use ark_ed_on_bls12_381::EdwardsProjective;
+use crate::account::{AccountId, AccountPublicKey, AccountSecretKey, Nonce};
+use crate::ledger::{self, AccMerkleConfig, Amount, DefaultSignatureScheme};
+use crate::message::{DomainTag, MessageKind};
+use crate::overlay::StateOverlay;
+use crate::signature::SignatureScheme;
+use crate::Error;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
 use ark_std::rand::Rng;
+use derivative::Derivative;
 
-/// Transaction transferring some amount from one account to another.
-#[derive(Clone, Debug)]
-pub struct Transaction {
+/// Transaction transferring some amount from one account to another, signed with
+/// the signature scheme `S`.
+#[derive(Derivative, CanonicalSerialize, CanonicalDeserialize)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct Transaction<S: SignatureScheme = DefaultSignatureScheme> {
     /// The account information of the sender.
     pub sender: AccountId,
     /// The account information of the recipient.
     pub recipient: AccountId,
     /// The amount being transferred from the sender to the receiver.
     pub amount: Amount,
-    /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: schnorr::Signature<EdwardsProjective>,
+    /// The sender's nonce at the time the transaction was created.
+    pub nonce: Nonce,
+    /// The fee paid by the sender to the ledger's fee collector, on top of `amount`.
+    pub fee: Amount,
+    /// The spend authorization is a signature over the ledger's domain tag, the
+    /// sender, the recipient, the amount, the nonce, and the fee.
+    pub signature: S::Signature,
 }
 
-impl Transaction {
+/// Construct the message that the sender signs to authorize a transaction on the
+/// ledger identified by `domain_tag`.
+fn signed_message(
+    domain_tag: &DomainTag,
+    sender: AccountId,
+    recipient: AccountId,
+    amount: Amount,
+    nonce: Nonce,
+    fee: Amount,
+) -> Vec<u8> {
+    // The authorized message consists of
+    // (Version || Kind || DomainTag || SenderAccId || RecipientAccId || Amount || Nonce || Fee)
+    let mut message = MessageKind::Transaction.prefix(domain_tag);
+    message.extend(sender.to_bytes_le());
+    message.extend(recipient.to_bytes_le());
+    message.extend(amount.to_bytes_le());
+    message.extend(nonce.to_bytes_le());
+    message.extend(fee.to_bytes_le());
+    message
+}
+
+impl<S: SignatureScheme> Transaction<S> {
     /// Verify just the signature in the transaction.
-    fn verify_signature(
+    fn verify_signature<M: AccMerkleConfig>(
         &self,
-        pp: &schnorr::Parameters<EdwardsProjective>,
-        pub_key: &AccountPublicKey,
+        pp: &ledger::Parameters<S, M>,
+        pub_key: &AccountPublicKey<S>,
     ) -> bool {
-        // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
-        let mut message = self.sender.to_bytes_le();
-        message.extend(self.recipient.to_bytes_le());
-        message.extend(self.amount.to_bytes_le());
-        Schnorr::verify(pp, pub_key, &message, &self.signature).unwrap()
+        let message = signed_message(
+            &pp.domain_tag,
+            self.sender,
+            self.recipient,
+            self.amount,
+            self.nonce,
+            self.fee,
+        );
+        S::verify(&pp.sig_params, pub_key, &message, &self.signature).unwrap()
     }
 
     /// Check that the transaction is valid for the given ledger state. This checks
     /// the following conditions:
-    /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
-    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+    /// 1. Verify that the sender's account exists, and matches the account tree.
+    /// 2. Verify that the signature is valid with respect to the public key
+    ///    corresponding to `self.sender`.
+    /// 3. Verify that the transaction nonce matches the sender's current nonce.
+    /// 4. Verify that the sender's account has sufficient balance to finance
+    ///    the transaction amount and the fee.
+    /// 5. Verify that the recipient's account exists.
+    /// 6. If the transaction pays a fee, verify that the ledger has a fee collector.
+    ///
+    /// Returns the first condition that does not hold.
+    pub fn validate<M: AccMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<S, M>,
+        state: &ledger::State<S, M>,
+    ) -> Result<(), Error> {
+        self.validate_inner(parameters, &state.overlay(), true)
+    }
+
+    /// Check that the transaction is valid for the given ledger state, like
+    /// `validate`, but skip the signature if `verify_signature` is false because
+    /// it was already checked.
+    pub(crate) fn validate_inner<M: AccMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<S, M>,
+        state: &StateOverlay<S, M>,
+        verify_signature: bool,
+    ) -> Result<(), Error> {
         // Lookup public key corresponding to sender ID
-        if let Some(sender_acc_info) = state.id_to_account_info.get(&self.sender) {
-            let mut result = true;
-            // Check that the account_info exists in the Merkle tree.
-            result &= {
-                let path = state
-                    .account_merkle_tree
-                    .generate_proof(self.sender.0 as usize)
-                    .expect("path should exist");
-                path.verify(
-                    &parameters.leaf_crh_params,
-                    &parameters.two_to_one_crh_params,
-                    &state.account_merkle_tree.root(),
-                    &sender_acc_info.to_bytes_le(),
-                )
-                .unwrap()
-            };
-            // Verify the signature against the sender pubkey.
-            result &= self.verify_signature(&parameters.sig_params, &sender_acc_info.public_key);
-            // assert!(result, "signature verification failed");
-            // Verify the amount is available in the sender account.
-            result &= self.amount <= sender_acc_info.balance;
-            // Verify that recipient account exists.
-            result &= state.id_to_account_info.get(&self.recipient).is_some();
-            result
-        } else {
-            false
+        let sender_acc_info = state
+            .account_info(&self.sender)
+            .ok_or(Error::UnknownSender(self.sender))?;
+        // Check that the account_info exists in the Merkle tree.
+        let path = self
+            .sender
+            .leaf_index()
+            .and_then(|index| state.account_merkle_tree().generate_proof(index).ok())
+            .ok_or(Error::MerklePathInvalid)?;
+        let in_tree = path
+            .verify(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                &state.root(),
+                &sender_acc_info.to_bytes_le(),
+            )
+            .map_err(|_| Error::MerklePathInvalid)?;
+        if !in_tree {
+            return Err(Error::MerklePathInvalid);
+        }
+        // Verify the signature against the sender pubkey.
+        if verify_signature && !self.verify_signature(parameters, &sender_acc_info.public_key) {
+            return Err(Error::BadSignature);
+        }
+        // Verify that the transaction has not been applied before.
+        if self.nonce != sender_acc_info.nonce {
+            return Err(Error::BadNonce);
+        }
+        // Verify the amount and the fee are available in the sender account.
+        let total = self.amount.checked_add(self.fee).ok_or(Error::Overflow)?;
+        if total > sender_acc_info.balance {
+            return Err(Error::InsufficientBalance);
         }
+        // Verify that recipient account exists.
+        if state.account_info(&self.recipient).is_none() {
+            return Err(Error::UnknownRecipient(self.recipient));
+        }
+        // Verify that someone can receive the fee.
+        if self.fee > Amount(0)
+            && state
+                .fee_collector()
+                .and_then(|id| state.account_info(&id))
+                .is_none()
+        {
+            return Err(Error::NoFeeCollector);
+        }
+        Ok(())
+    }
+
+    /// Create a no-op transaction, which is used to pad batches of transactions to a
+    /// fixed size. Its sender and recipient are 0, which no account uses, so it is
+    /// never valid on its own.
+    pub fn noop() -> Self {
+        Self {
+            sender: AccountId(0),
+            recipient: AccountId(0),
+            amount: Amount(0),
+            nonce: Nonce::default(),
+            fee: Amount(0),
+            signature: S::Signature::default(),
+        }
+    }
+
+    /// Is this a no-op transaction, as created by `Transaction::noop`?
+    pub fn is_noop(&self) -> bool {
+        self.sender == AccountId(0)
     }
 
-    /// Create a (possibly invalid) transaction.
-    pub fn create<R: Rng>(
-        parameters: &ledger::Parameters,
+    /// Create a (possibly invalid) transaction that pays no fee.
+    pub fn create<M: AccMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<S, M>,
         sender: AccountId,
         recipient: AccountId,
         amount: Amount,
-        sender_sk: &AccountSecretKey,
+        nonce: Nonce,
+        sender_sk: &AccountSecretKey<S>,
         rng: &mut R,
     ) -> Self {
-        // The authorized message consists of (SenderAccId || RecipientAccId || Amount)
-        let mut message = sender.to_bytes_le();
-        message.extend(recipient.to_bytes_le());
-        message.extend(amount.to_bytes_le());
-        let signature = Schnorr::sign(&parameters.sig_params, sender_sk, &message, rng).unwrap();
+        Self::create_with_fee(
+            parameters,
+            sender,
+            recipient,
+            amount,
+            nonce,
+            Amount(0),
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) transaction that pays `fee` to the ledger's fee collector.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_fee<M: AccMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<S, M>,
+        sender: AccountId,
+        recipient: AccountId,
+        amount: Amount,
+        nonce: Nonce,
+        fee: Amount,
+        sender_sk: &AccountSecretKey<S>,
+        rng: &mut R,
+    ) -> Self {
+        let message = signed_message(
+            &parameters.domain_tag,
+            sender,
+            recipient,
+            amount,
+            nonce,
+            fee,
+        );
+        let signature = S::sign(&parameters.sig_params, sender_sk, &message, rng).unwrap();
         Self {
             sender,
             recipient,
             amount,
+            nonce,
+            fee,
             signature,
         }
     }
 }
 
+/// Verify the signatures of `transactions` against the public keys of their
+/// senders in `state`, as a batch. Returns the index of the first transaction
+/// whose signature is invalid, if any. Transactions from unknown senders are
+/// skipped, as they fail validation anyway.
+pub(crate) fn first_invalid_signature<S: SignatureScheme, M: AccMerkleConfig>(
+    parameters: &ledger::Parameters<S, M>,
+    state: &StateOverlay<S, M>,
+    transactions: &[Transaction<S>],
+) -> Option<usize> {
+    let mut indices = Vec::new();
+    let mut pub_keys = Vec::new();
+    let mut messages = Vec::new();
+    let mut signatures = Vec::new();
+    for (i, tx) in transactions.iter().enumerate() {
+        if let Some(sender_acc_info) = state.account_info(&tx.sender) {
+            indices.push(i);
+            pub_keys.push(sender_acc_info.public_key.clone());
+            messages.push(signed_message(
+                &parameters.domain_tag,
+                tx.sender,
+                tx.recipient,
+                tx.amount,
+                tx.nonce,
+                tx.fee,
+            ));
+            signatures.push(tx.signature.clone());
+        }
+    }
+    S::batch_verify(&parameters.sig_params, &pub_keys, &messages, &signatures)
+        .unwrap()
+        .map(|i| indices[i])
+}
+
 // Ideas to make exercises more interesting/complex:
-// 1. Add fees
-// 2. Add recipient confirmation requirement if tx amount is too large.
-// 3. Add authority confirmation if tx amount is too large.
-// 4. Create account if it doesn't exist.
-// 5. Add idea for compressing state transitions with repeated senders and recipients.
+// 1. Add recipient confirmation requirement if tx amount is too large.
+// 2. Add authority confirmation if tx amount is too large.
+// 3. Create account if it doesn't exist.
+// 4. Add idea for compressing state transitions with repeated senders and recipients.